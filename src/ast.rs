@@ -1,8 +1,22 @@
-use crate::code_generator::CodeGenerator;
+use crate::code_generator::builtin_funcs;
+use crate::code_generator::function_code::FunctionCode;
+use crate::code_generator::instruction::Instruction;
+use crate::code_generator::lower::{lower_expr, lower_function, Constants, Locals};
 use crate::operator::Operator;
+use crate::span::Span;
+use crate::types::Type;
+use std::fmt;
 
-#[derive(Debug)]
-pub enum ExprNode {
+#[derive(Debug, Clone)]
+pub struct ExprNode {
+    pub kind: ExprKind,
+    pub span: Span,
+    /// Filled in by the type checker.
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Number(f64),
     Variable(String),
     BinaryExpr {
@@ -23,135 +37,567 @@ pub enum ExprNode {
         then_branch: Box<ExprNode>,
         else_branch: Box<ExprNode>,
     },
+    LetExpr {
+        name: String,
+        name_span: Span,
+        value: Box<ExprNode>,
+        body: Box<ExprNode>,
+    },
 }
 
 impl ExprNode {
-    pub fn create_call(callee: String, args: Vec<ExprNode>) -> ExprNode {
-        ExprNode::CallExpr { callee, args }
+    pub fn new(kind: ExprKind, span: Span) -> ExprNode {
+        ExprNode {
+            kind,
+            span,
+            ty: Type::F32,
+        }
+    }
+
+    pub fn create_number(number: f64, span: Span) -> ExprNode {
+        ExprNode::new(ExprKind::Number(number), span)
+    }
+
+    pub fn create_variable(name: String, span: Span) -> ExprNode {
+        ExprNode::new(ExprKind::Variable(name), span)
+    }
+
+    pub fn create_call(callee: String, args: Vec<ExprNode>, span: Span) -> ExprNode {
+        ExprNode::new(ExprKind::CallExpr { callee, args }, span)
     }
 
     pub fn create_binary_op(op: Operator, lhs: ExprNode, rhs: ExprNode) -> ExprNode {
-        ExprNode::BinaryExpr {
-            op: op,
-            lhs: Box::new(lhs),
-            rhs: Box::new(rhs),
-        }
+        let span = lhs.span.to(rhs.span);
+
+        ExprNode::new(
+            ExprKind::BinaryExpr {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            },
+            span,
+        )
     }
 
-    pub fn create_unary_op(op: Operator, rhs: ExprNode) -> ExprNode {
-        ExprNode::UnaryExpr {
-            op: op,
-            rhs: Box::new(rhs),
-        }
+    pub fn create_unary_op(op: Operator, rhs: ExprNode, span: Span) -> ExprNode {
+        let span = span.to(rhs.span);
+
+        ExprNode::new(
+            ExprKind::UnaryExpr {
+                op,
+                rhs: Box::new(rhs),
+            },
+            span,
+        )
     }
 
     pub fn create_if_then_else(
         cond: ExprNode,
         then_branch: ExprNode,
         else_branch: ExprNode,
+        span: Span,
     ) -> ExprNode {
-        ExprNode::IfExpr {
-            cond: Box::new(cond),
-            then_branch: Box::new(then_branch),
-            else_branch: Box::new(else_branch),
-        }
+        let span = span.to(else_branch.span);
+
+        ExprNode::new(
+            ExprKind::IfExpr {
+                cond: Box::new(cond),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            },
+            span,
+        )
     }
 
-    pub fn to_wat(&self) -> Vec<String> {
-        let mut wat = vec![];
+    pub fn create_let(
+        name: String,
+        name_span: Span,
+        value: ExprNode,
+        body: ExprNode,
+        span: Span,
+    ) -> ExprNode {
+        let span = span.to(body.span);
 
-        match self {
-            ExprNode::Number(number) => wat = vec![format!("f32.const {}\n", number)],
-            ExprNode::Variable(var) => wat = vec![format!("local.get ${}\n", var)],
-            ExprNode::BinaryExpr { op, lhs, rhs } => {
-                wat.append(&mut lhs.to_wat());
-                wat.append(&mut rhs.to_wat());
-                wat.push(op.to_wat());
-            }
-            ExprNode::UnaryExpr { op, rhs } => {
-                wat.append(&mut rhs.to_wat());
-                wat.push(op.to_wat());
-            }
+        ExprNode::new(
+            ExprKind::LetExpr {
+                name,
+                name_span,
+                value: Box::new(value),
+                body: Box::new(body),
+            },
+            span,
+        )
+    }
 
-            ExprNode::CallExpr { callee, args } => {
-                let builtin_funcs = CodeGenerator::builtin_funcs();
+    /// Appends an indented tree of the expression, one node per line.
+    pub fn dump(&self, depth: usize, out: &mut String) {
+        let label = match &self.kind {
+            ExprKind::Number(number) => format!("Number {}", number),
+            ExprKind::Variable(name) => format!("Variable {}", name),
+            ExprKind::BinaryExpr { op, .. } => format!("BinaryExpr {}", op.symbol()),
+            ExprKind::UnaryExpr { op, .. } => format!("UnaryExpr {}", op.symbol()),
+            ExprKind::CallExpr { callee, .. } => format!("CallExpr {}", callee),
+            ExprKind::IfExpr { .. } => String::from("IfExpr"),
+            ExprKind::LetExpr { name, .. } => format!("LetExpr {}", name),
+        };
+        out.push_str(&format!(
+            "{}{} @{}:{}\n",
+            "  ".repeat(depth),
+            label,
+            self.span.line,
+            self.span.column
+        ));
 
-                for expr in args {
-                    wat.append(&mut expr.to_wat());
-                }
-                if let Some(name) = builtin_funcs.get(callee.as_str()) {
-                    wat.append(&mut vec![format!("{}\n", name)]);
-                } else {
-                    wat.append(&mut vec![format!("call ${}\n", callee)]);
+        match &self.kind {
+            ExprKind::Number(_) | ExprKind::Variable(_) => {}
+            ExprKind::BinaryExpr { lhs, rhs, .. } => {
+                lhs.dump(depth + 1, out);
+                rhs.dump(depth + 1, out);
+            }
+            ExprKind::UnaryExpr { rhs, .. } => rhs.dump(depth + 1, out),
+            ExprKind::CallExpr { args, .. } => {
+                for arg in args {
+                    arg.dump(depth + 1, out);
                 }
             }
-            ExprNode::IfExpr {
+            ExprKind::IfExpr {
                 cond,
                 then_branch,
                 else_branch,
             } => {
-                wat.append(&mut cond.to_wat());
-                wat.append(&mut vec![String::from("if (result f32)\n")]);
-                wat.append(&mut then_branch.to_wat());
-                wat.append(&mut vec![String::from("else\n")]);
-                wat.append(&mut else_branch.to_wat());
-                wat.append(&mut vec![String::from("end\n")]);
+                cond.dump(depth + 1, out);
+                then_branch.dump(depth + 1, out);
+                else_branch.dump(depth + 1, out);
             }
-        };
+            ExprKind::LetExpr { value, body, .. } => {
+                value.dump(depth + 1, out);
+                body.dump(depth + 1, out);
+            }
+        }
+    }
 
-        wat
+    /// Whether the expression calls a function other than a builtin.
+    pub fn calls_definitions(&self) -> bool {
+        match &self.kind {
+            ExprKind::Number(_) | ExprKind::Variable(_) => false,
+            ExprKind::BinaryExpr { lhs, rhs, .. } => {
+                lhs.calls_definitions() || rhs.calls_definitions()
+            }
+            ExprKind::UnaryExpr { rhs, .. } => rhs.calls_definitions(),
+            ExprKind::CallExpr { callee, args } => {
+                !builtin_funcs().contains_key(callee.as_str())
+                    || args.iter().any(ExprNode::calls_definitions)
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                cond.calls_definitions()
+                    || then_branch.calls_definitions()
+                    || else_branch.calls_definitions()
+            }
+            ExprKind::LetExpr { value, body, .. } => {
+                value.calls_definitions() || body.calls_definitions()
+            }
+        }
     }
+
+    pub fn to_wat(&self) -> Vec<String> {
+        lower_expr(self, &mut Locals::default())
+            .iter()
+            .map(Instruction::to_wat)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Prototype {
     name: String,
-    args: Vec<String>,
+    args: Vec<Parameter>,
+    name_span: Span,
+    span: Span,
 }
 
 impl Prototype {
-    pub fn new(name: String, args: Vec<String>) -> Self {
-        Self { name, args }
+    pub fn new(name: String, args: Vec<Parameter>, name_span: Span, span: Span) -> Self {
+        Self {
+            name,
+            args,
+            name_span,
+            span,
+        }
     }
 
-    pub fn to_wat(&self) -> Vec<String> {
-        let mut proto = vec![format!("(func ${}", self.name)];
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_params(&self) -> &Vec<Parameter> {
+        &self.args
+    }
+
+    pub fn get_name_span(&self) -> Span {
+        self.name_span
+    }
+
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
+}
+
+impl fmt::Display for Prototype {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<&str> = self.args.iter().map(|arg| arg.name.as_str()).collect();
+        write!(f, "{}({})", self.name, params.join(" "))
+    }
+}
+
+/// An annotation written before a definition: `@name` or `@name("arg")`.
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub arg: Option<String>,
+    pub span: Span,
+}
 
-        for arg in self.args.iter() {
-            proto.append(&mut vec![format!(" (param ${} f32)", arg)]);
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.arg {
+            Some(arg) => write!(f, "@{}(\"{}\")", self.name, arg),
+            None => write!(f, "@{}", self.name),
         }
-        proto.append(&mut vec![String::from(" (result f32)\n")]);
-        proto
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Function {
     proto: Prototype,
     body: ExprNode,
+    attributes: Vec<Attribute>,
+    span: Span,
 }
 
 impl Function {
-    pub fn new(proto: Prototype, body: ExprNode) -> Self {
-        Self { proto, body }
+    pub fn new(proto: Prototype, body: ExprNode, span: Span) -> Self {
+        Self {
+            proto,
+            body,
+            attributes: vec![],
+            span,
+        }
+    }
+
+    pub fn with_attributes(mut self, attributes: Vec<Attribute>) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    pub fn get_attributes(&self) -> &Vec<Attribute> {
+        &self.attributes
+    }
+
+    pub fn get_attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|attr| attr.name == name)
     }
 
+    /// Name the function is exported under if it is marked `@export`.
+    pub fn get_export_name(&self) -> Option<&str> {
+        self.get_attribute("export")
+            .map(|attr| attr.arg.as_deref().unwrap_or(&self.proto.name))
+    }
+
+    /// Code of the definition on its own: names of constants read locals.
+    /// `ModuleCode` lowers a whole program with its constants.
     pub fn to_wat(&self) -> Vec<String> {
-        let mut func = vec![];
+        FunctionCode::from_function(self, Locals::new(self.get_params(), &Constants::new()))
+            .to_wat()
+    }
 
-        func.append(&mut self.proto.to_wat());
-        func.append(&mut self.body.to_wat());
-        func.append(&mut vec![String::from(")\n")]);
+    pub fn to_instructions(&self) -> Vec<Instruction> {
+        lower_function(self, Locals::new(self.get_params(), &Constants::new())).0
+    }
 
-        func
+    /// Indented tree of the definition, one node per line.
+    pub fn dump(&self) -> String {
+        let mut out = format!(
+            "Definition {} @{}:{}\n",
+            self.proto, self.span.line, self.span.column
+        );
+        self.body.dump(1, &mut out);
+        out
     }
 
     pub fn get_function_name(&self) -> &str {
         &self.proto.name
     }
+
+    pub fn get_params(&self) -> &Vec<Parameter> {
+        &self.proto.args
+    }
+
+    pub fn get_proto(&self) -> &Prototype {
+        &self.proto
+    }
+
+    pub fn get_body(&self) -> &ExprNode {
+        &self.body
+    }
+
+    pub fn get_body_mut(&mut self) -> &mut ExprNode {
+        &mut self.body
+    }
+
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
+}
+
+/// Module an `extern` is imported from unless it names another one.
+pub const DEFAULT_IMPORT_MODULE: &str = "env";
+
+/// A function provided by the host, declared with
+/// `extern name(params) [from "module"] [as "field"];`.
+#[derive(Debug, Clone)]
+pub struct Extern {
+    proto: Prototype,
+    module: String,
+    field: String,
+    span: Span,
+}
+
+impl Extern {
+    pub fn new(proto: Prototype, module: String, field: String, span: Span) -> Self {
+        Self {
+            proto,
+            module,
+            field,
+            span,
+        }
+    }
+
+    pub fn get_proto(&self) -> &Prototype {
+        &self.proto
+    }
+
+    /// Name of the import's module, `env` by default.
+    pub fn get_module(&self) -> &str {
+        &self.module
+    }
+
+    /// Name the host provides the function under, by default the same as in
+    /// the program.
+    pub fn get_field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
+}
+
+/// A named value, `const name = expression;`, computed at compile time.
+#[derive(Debug, Clone)]
+pub struct Const {
+    name: String,
+    name_span: Span,
+    expr: ExprNode,
+    attributes: Vec<Attribute>,
+    /// Filled in once the program is checked, see `evaluate_constants`.
+    value: Option<f32>,
+    span: Span,
+}
+
+impl Const {
+    pub fn new(name: String, name_span: Span, expr: ExprNode, span: Span) -> Self {
+        Self {
+            name,
+            name_span,
+            expr,
+            attributes: vec![],
+            value: None,
+            span,
+        }
+    }
+
+    pub fn with_attributes(mut self, attributes: Vec<Attribute>) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_name_span(&self) -> Span {
+        self.name_span
+    }
+
+    pub fn get_expr(&self) -> &ExprNode {
+        &self.expr
+    }
+
+    pub fn get_expr_mut(&mut self) -> &mut ExprNode {
+        &mut self.expr
+    }
+
+    pub fn get_attributes(&self) -> &Vec<Attribute> {
+        &self.attributes
+    }
+
+    /// Name the constant is exported under as a global if it is marked
+    /// `@export`.
+    pub fn get_export_name(&self) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attr| attr.name == "export")
+            .map(|attr| attr.arg.as_deref().unwrap_or(&self.name))
+    }
+
+    /// The value, once computed. A boolean constant is 1 or 0.
+    pub fn get_value(&self) -> Option<f32> {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: f32) {
+        self.value = Some(value);
+    }
+
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
+}
+
+/// `import "path" [as name]`: makes the definitions and constants of another
+/// file available as `name.item`, where `name` defaults to the file name
+/// without its extension.
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub path: String,
+    pub alias: Option<String>,
+    pub span: Span,
+}
+
+impl Import {
+    /// The namespace the imported items are qualified with.
+    pub fn get_namespace(&self) -> &str {
+        self.alias.as_deref().unwrap_or_else(|| {
+            std::path::Path::new(&self.path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default()
+        })
+    }
+}
+
+impl fmt::Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "import \"{}\"", self.path)?;
+        if let Some(alias) = &self.alias {
+            write!(f, " as {}", alias)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Ast {
     Definition(Function),
+    Extern(Extern),
+    Const(Const),
+    Import(Import),
+}
+
+impl Ast {
+    pub fn get_name(&self) -> &str {
+        match self {
+            Ast::Definition(func) => func.get_function_name(),
+            Ast::Extern(ext) => ext.get_proto().get_name(),
+            Ast::Const(constant) => constant.get_name(),
+            Ast::Import(import) => import.get_namespace(),
+        }
+    }
+
+    /// Gives the item another name, keeping the name it is imported from the
+    /// host for an extern.
+    pub fn set_name(&mut self, name: String) {
+        match self {
+            Ast::Definition(func) => func.proto.name = name,
+            Ast::Extern(ext) => ext.proto.name = name,
+            Ast::Const(constant) => constant.name = name,
+            Ast::Import(import) => import.alias = Some(name),
+        }
+    }
+
+    /// Prototype of a definition or extern.
+    pub fn get_proto(&self) -> Option<&Prototype> {
+        match self {
+            Ast::Definition(func) => Some(func.get_proto()),
+            Ast::Extern(ext) => Some(ext.get_proto()),
+            Ast::Const(_) | Ast::Import(_) => None,
+        }
+    }
+
+    pub fn get_attributes(&self) -> &[Attribute] {
+        match self {
+            Ast::Definition(func) => func.get_attributes(),
+            Ast::Extern(_) | Ast::Import(_) => &[],
+            Ast::Const(constant) => constant.get_attributes(),
+        }
+    }
+
+    /// Drops every attribute called `name`.
+    pub fn remove_attributes(&mut self, name: &str) {
+        match self {
+            Ast::Definition(func) => func.attributes.retain(|attr| attr.name != name),
+            Ast::Const(constant) => constant.attributes.retain(|attr| attr.name != name),
+            Ast::Extern(_) | Ast::Import(_) => {}
+        }
+    }
+
+    pub fn get_span(&self) -> Span {
+        match self {
+            Ast::Definition(func) => func.get_span(),
+            Ast::Extern(ext) => ext.get_span(),
+            Ast::Const(constant) => constant.get_span(),
+            Ast::Import(import) => import.span,
+        }
+    }
+
+    /// Body of a definition or value of a constant.
+    pub fn get_expr(&self) -> Option<&ExprNode> {
+        match self {
+            Ast::Definition(func) => Some(func.get_body()),
+            Ast::Const(constant) => Some(constant.get_expr()),
+            Ast::Extern(_) | Ast::Import(_) => None,
+        }
+    }
+
+    pub fn get_expr_mut(&mut self) -> Option<&mut ExprNode> {
+        match self {
+            Ast::Definition(func) => Some(func.get_body_mut()),
+            Ast::Const(constant) => Some(constant.get_expr_mut()),
+            Ast::Extern(_) | Ast::Import(_) => None,
+        }
+    }
+
+    pub fn as_definition(&self) -> Option<&Function> {
+        match self {
+            Ast::Definition(func) => Some(func),
+            _ => None,
+        }
+    }
+
+    pub fn as_const(&self) -> Option<&Const> {
+        match self {
+            Ast::Const(constant) => Some(constant),
+            _ => None,
+        }
+    }
 }
@@ -26,15 +26,12 @@ impl Char {
     }
 
     pub fn is_eof(&self) -> bool {
-        match self.value {
-            Utf8Char::Char(_) => false,
-            _ => true,
-        }
+        !matches!(self.value, Utf8Char::Char(_))
     }
 
     pub fn is_digit(&self) -> bool {
         match self.value {
-            Utf8Char::Char(ch) => ch.is_digit(10),
+            Utf8Char::Char(ch) => ch.is_ascii_digit(),
             _ => false,
         }
     }
@@ -1,47 +1,85 @@
-use crate::ast::{Ast, Function};
+pub mod encoder;
+pub mod function_code;
+pub mod instruction;
+pub mod lower;
+pub mod runtime;
+
+use crate::ast::Ast;
+use encoder::Encoder;
+use function_code::{FunctionCode, ModuleCode};
+use instruction::Instruction;
 use std::collections::HashMap;
-use std::fs::File;
 use std::io::prelude::*;
 
-pub struct CodeGenerator<'a> {
-    asts: &'a Vec<Ast>,
-    target: File,
+pub struct CodeGenerator<W: Write> {
+    module: ModuleCode,
+    target: W,
 }
 
-impl<'a> CodeGenerator<'a> {
-    pub fn new(asts: &'a Vec<Ast>, target: File) -> Self {
-        Self { asts, target }
+impl<W: Write> CodeGenerator<W> {
+    pub fn new(asts: &[Ast], target: W) -> Self {
+        Self::from_module(ModuleCode::new(asts, Default::default()), target)
+    }
+
+    /// Writes a module lowered already, e.g. with other options, see
+    /// `lower_program`.
+    pub fn from_module(module: ModuleCode, target: W) -> Self {
+        Self { module, target }
+    }
+
+    pub fn into_target(self) -> W {
+        self.target
     }
 
     pub fn run(&mut self) -> std::io::Result<()> {
-        let mut func_names = vec![];
+        // Taken out while writing, as `write` borrows the generator.
+        let module = std::mem::take(&mut self.module);
 
         self.open_module()?;
-        for ast in self.asts.iter() {
-            self.to_wat(ast)?;
-            let Ast::Definition(func) = ast;
-            func_names.push(func.get_function_name());
+        for import in module.imports.iter() {
+            self.write(&import.to_wat())?;
+        }
+        for func in module.functions.iter() {
+            self.def_to_wat(func)?;
+        }
+        for global in module.globals.iter() {
+            self.write(&global.to_wat())?;
+        }
+
+        let exported: Vec<(&str, &str)> = module
+            .functions
+            .iter()
+            .filter_map(|func| Some((func.export.as_deref()?, func.name.as_str())))
+            .collect();
+        self.export_functions(&exported)?;
+        for global in module.globals.iter() {
+            if let Some(export) = &global.export {
+                self.write(&format!(
+                    "(export \"{}\" (global ${}))\n",
+                    export, global.name
+                ))?;
+            }
         }
-        self.export_functions(&func_names)?;
         self.close_module()?;
 
+        self.module = module;
         Ok(())
     }
 
-    fn to_wat(&mut self, ast: &Ast) -> std::io::Result<()> {
-        match ast {
-            Ast::Definition(def_node) => self.def_to_wat(def_node),
-        }
+    /// Encodes the same module `run` writes as text into binary WebAssembly.
+    pub fn to_wasm(&self) -> std::io::Result<Vec<u8>> {
+        Encoder::from_module(self.module.clone()).encode()
     }
 
-    fn def_to_wat(&mut self, function: &Function) -> std::io::Result<()> {
+    fn def_to_wat(&mut self, function: &FunctionCode) -> std::io::Result<()> {
         self.write(function.to_wat().join("").as_str())?;
         Ok(())
     }
 
-    fn export_functions(&mut self, func_names: &Vec<&str>) -> std::io::Result<()> {
-        for name in func_names {
-            self.write(format!("(export \"{}\" (func ${}))\n", name, name).as_str())?;
+    /// Writes an export for every pair of export name and function name.
+    fn export_functions(&mut self, exports: &[(&str, &str)]) -> std::io::Result<()> {
+        for (export, name) in exports {
+            self.write(format!("(export \"{}\" (func ${}))\n", export, name).as_str())?;
         }
 
         Ok(())
@@ -57,21 +95,22 @@ impl<'a> CodeGenerator<'a> {
         Ok(())
     }
 
-    pub fn builtin_funcs() -> HashMap<&'a str, &'a str> {
-        let mut funcs = HashMap::new();
-        funcs.insert("sqrt", "f32.sqrt");
-        funcs.insert("ceil", "f32.ceil");
-        funcs.insert("floor", "f32.floor");
-        funcs.insert("trunc", "f32.trunc");
-        funcs.insert("nearest", "f32.nearest");
-        funcs.insert("abs", "f32.abs");
-        funcs.insert("neg", "f32.neg");
-
-        funcs
-    }
-
     fn write(&mut self, line: &str) -> std::io::Result<()> {
         self.target.write_all(line.as_bytes())?;
         Ok(())
     }
 }
+
+/// Functions implemented by a single WebAssembly instruction.
+pub fn builtin_funcs() -> HashMap<&'static str, Instruction> {
+    let mut funcs = HashMap::new();
+    funcs.insert("sqrt", Instruction::F32Sqrt);
+    funcs.insert("ceil", Instruction::F32Ceil);
+    funcs.insert("floor", Instruction::F32Floor);
+    funcs.insert("trunc", Instruction::F32Trunc);
+    funcs.insert("nearest", Instruction::F32Nearest);
+    funcs.insert("abs", Instruction::F32Abs);
+    funcs.insert("neg", Instruction::F32Neg);
+
+    funcs
+}
@@ -0,0 +1,543 @@
+use super::function_code::{FunctionCode, FunctionImport, GlobalCode, LowerOptions, ModuleCode};
+use super::instruction::Instruction;
+use crate::ast::Ast;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+const MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
+const VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_GLOBAL: u8 = 6;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+
+const TYPE_FUNC: u8 = 0x60;
+const TYPE_F32: u8 = 0x7d;
+const EXPORT_FUNC: u8 = 0x00;
+const EXPORT_GLOBAL: u8 = 0x03;
+const IMPORT_FUNC: u8 = 0x00;
+const GLOBAL_IMMUTABLE: u8 = 0x00;
+
+/// Encodes the definitions into a binary WebAssembly module equivalent to
+/// the text produced by `CodeGenerator::run`.
+pub struct Encoder {
+    imports: Vec<FunctionImport>,
+    functions: Vec<FunctionCode>,
+    globals: Vec<GlobalCode>,
+    func_indices: HashMap<String, u32>,
+}
+
+impl Encoder {
+    pub fn new(asts: &[Ast]) -> Self {
+        Self::with_options(asts, LowerOptions::default())
+    }
+
+    /// Like `new`, lowering the functions with `options`, see
+    /// `lower_program`.
+    pub fn with_options(asts: &[Ast], options: LowerOptions) -> Self {
+        Self::from_module(ModuleCode::new(asts, options))
+    }
+
+    /// Encodes a module lowered already, e.g. the one written as text too.
+    pub fn from_module(module: ModuleCode) -> Self {
+        let ModuleCode {
+            imports,
+            functions,
+            globals,
+        } = module;
+
+        let mut func_indices = HashMap::new();
+        let names = imports
+            .iter()
+            .map(|import| &import.name)
+            .chain(functions.iter().map(|func| &func.name));
+        for (index, name) in names.enumerate() {
+            func_indices.entry(name.clone()).or_insert(index as u32);
+        }
+
+        Self {
+            imports,
+            functions,
+            globals,
+            func_indices,
+        }
+    }
+
+    pub fn encode(&self) -> std::io::Result<Vec<u8>> {
+        let mut module = vec![];
+        module.extend_from_slice(&MAGIC);
+        module.extend_from_slice(&VERSION);
+
+        let (types, import_types, type_indices) = self.function_types();
+
+        write_section(&mut module, SECTION_TYPE, &types);
+        if !self.imports.is_empty() {
+            write_section(
+                &mut module,
+                SECTION_IMPORT,
+                &self.import_entries(&import_types),
+            );
+        }
+        write_section(&mut module, SECTION_FUNCTION, &type_indices);
+        if !self.globals.is_empty() {
+            write_section(&mut module, SECTION_GLOBAL, &self.global_entries());
+        }
+        write_section(&mut module, SECTION_EXPORT, &self.exports());
+        write_section(&mut module, SECTION_CODE, &self.code()?);
+
+        Ok(module)
+    }
+
+    /// Returns the type section payload, the type index of every import and
+    /// the function section payload. Every parameter and result is `f32`, so
+    /// a signature is identified by its parameter count alone.
+    fn function_types(&self) -> (Vec<u8>, Vec<u32>, Vec<u8>) {
+        let mut arities: Vec<usize> = vec![];
+        let mut type_index = |arity: usize| match arities.iter().position(|&a| a == arity) {
+            Some(index) => index as u32,
+            None => {
+                arities.push(arity);
+                arities.len() as u32 - 1
+            }
+        };
+
+        let import_types = self
+            .imports
+            .iter()
+            .map(|import| type_index(import.arity))
+            .collect();
+
+        let mut type_indices = vec![];
+        write_u32(&mut type_indices, self.functions.len() as u32);
+        for func in self.functions.iter() {
+            write_u32(&mut type_indices, type_index(func.params.len()));
+        }
+
+        let mut types = vec![];
+        write_u32(&mut types, arities.len() as u32);
+        for arity in arities {
+            types.push(TYPE_FUNC);
+            write_u32(&mut types, arity as u32);
+            types.extend(std::iter::repeat_n(TYPE_F32, arity));
+            write_u32(&mut types, 1);
+            types.push(TYPE_F32);
+        }
+
+        (types, import_types, type_indices)
+    }
+
+    fn import_entries(&self, import_types: &[u32]) -> Vec<u8> {
+        let mut imports = vec![];
+
+        write_u32(&mut imports, self.imports.len() as u32);
+        for (import, type_index) in self.imports.iter().zip(import_types) {
+            write_name(&mut imports, &import.module);
+            write_name(&mut imports, &import.field);
+            imports.push(IMPORT_FUNC);
+            write_u32(&mut imports, *type_index);
+        }
+
+        imports
+    }
+
+    fn global_entries(&self) -> Vec<u8> {
+        let mut globals = vec![];
+
+        write_u32(&mut globals, self.globals.len() as u32);
+        for global in self.globals.iter() {
+            globals.push(TYPE_F32);
+            globals.push(GLOBAL_IMMUTABLE);
+            globals.push(Instruction::F32Const(0.0).opcode());
+            globals.extend_from_slice(&global.value.to_le_bytes());
+            globals.push(Instruction::End.opcode());
+        }
+
+        globals
+    }
+
+    fn exports(&self) -> Vec<u8> {
+        let mut exports = vec![];
+
+        let functions = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, func)| {
+                let index = self.imports.len() + index;
+                Some((func.export.as_deref()?, EXPORT_FUNC, index))
+            });
+        let globals = self
+            .globals
+            .iter()
+            .enumerate()
+            .filter_map(|(index, global)| Some((global.export.as_deref()?, EXPORT_GLOBAL, index)));
+        let exported: Vec<(&str, u8, usize)> = functions.chain(globals).collect();
+
+        write_u32(&mut exports, exported.len() as u32);
+        for (name, kind, index) in exported {
+            write_name(&mut exports, name);
+            exports.push(kind);
+            write_u32(&mut exports, index as u32);
+        }
+
+        exports
+    }
+
+    fn code(&self) -> std::io::Result<Vec<u8>> {
+        let mut code = vec![];
+
+        write_u32(&mut code, self.functions.len() as u32);
+        for func in self.functions.iter() {
+            let body = self.function_body(func)?;
+            write_u32(&mut code, body.len() as u32);
+            code.extend(body);
+        }
+
+        Ok(code)
+    }
+
+    fn function_body(&self, func: &FunctionCode) -> std::io::Result<Vec<u8>> {
+        let local_indices: HashMap<&str, u32> = func
+            .params
+            .iter()
+            .map(String::as_str)
+            .chain(func.locals.iter().map(|(name, _)| name.as_str()))
+            .enumerate()
+            .map(|(index, name)| (name, index as u32))
+            .collect();
+
+        let mut body = vec![];
+        // Consecutive locals of the same type are declared as one group.
+        let mut groups: Vec<(u32, u8)> = vec![];
+        for (_, ty) in func.locals.iter() {
+            match groups.last_mut() {
+                Some((count, group_ty)) if *group_ty == ty.to_wasm() => *count += 1,
+                _ => groups.push((1, ty.to_wasm())),
+            }
+        }
+        write_u32(&mut body, groups.len() as u32);
+        for (count, ty) in groups {
+            write_u32(&mut body, count);
+            body.push(ty);
+        }
+
+        for instr in func.instrs.iter() {
+            body.push(instr.opcode());
+            match instr {
+                Instruction::F32Const(value) => body.extend_from_slice(&value.to_le_bytes()),
+                Instruction::I32Const(value) => write_i32(&mut body, *value),
+                Instruction::LocalGet(name)
+                | Instruction::LocalSet(name)
+                | Instruction::LocalTee(name) => {
+                    let index = local_indices.get(name.as_str()).ok_or_else(|| {
+                        unresolved(format!(
+                            "Unknown variable '{}' in function '{}'",
+                            name, func.name
+                        ))
+                    })?;
+                    write_u32(&mut body, *index);
+                }
+                Instruction::GlobalGet(name) => {
+                    let index = self
+                        .globals
+                        .iter()
+                        .position(|global| &global.name == name)
+                        .ok_or_else(|| unresolved(format!("Unknown constant '{}'", name)))?;
+                    write_u32(&mut body, index as u32);
+                }
+                Instruction::Call(name) | Instruction::ReturnCall(name) => {
+                    let index = self
+                        .func_indices
+                        .get(name)
+                        .ok_or_else(|| unresolved(format!("Unknown function '{}'", name)))?;
+                    write_u32(&mut body, *index);
+                }
+                Instruction::If(ty) | Instruction::Loop(ty) => body.push(ty.to_wasm()),
+                Instruction::Br(depth) => write_u32(&mut body, *depth),
+                _ => {}
+            }
+        }
+        body.push(Instruction::End.opcode());
+
+        Ok(body)
+    }
+}
+
+fn unresolved(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+fn write_section(module: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    module.push(id);
+    write_u32(module, payload.len() as u32);
+    module.extend_from_slice(payload);
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) {
+    write_u32(buf, name.len() as u32);
+    buf.extend_from_slice(name.as_bytes());
+}
+
+/// Unsigned LEB128.
+pub fn write_u32(buf: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Signed LEB128.
+pub fn write_i32(buf: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let sign_bit_clear = byte & 0x40 == 0;
+        if (value == 0 && sign_bit_clear) || (value == -1 && !sign_bit_clear) {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_generator::CodeGenerator;
+    use crate::parser::Parser;
+    use std::io::Cursor;
+
+    fn encode_source(src: &str) -> Vec<u8> {
+        let mut parser = Parser::new(Cursor::new(src));
+        parser.main_loop();
+        assert!(!parser.get_error_logger().has_errors());
+
+        Encoder::new(parser.get_asts()).encode().unwrap()
+    }
+
+    fn leb128(value: u32) -> Vec<u8> {
+        let mut buf = vec![];
+        write_u32(&mut buf, value);
+        buf
+    }
+
+    #[test]
+    fn it_encodes_leb128() {
+        assert_eq!(leb128(0), vec![0x00]);
+        assert_eq!(leb128(127), vec![0x7f]);
+        assert_eq!(leb128(128), vec![0x80, 0x01]);
+        assert_eq!(leb128(624485), vec![0xe5, 0x8e, 0x26]);
+        assert_eq!(leb128(u32::MAX), vec![0xff, 0xff, 0xff, 0xff, 0x0f]);
+    }
+
+    #[test]
+    fn it_encodes_signed_leb128() {
+        let encode = |value| {
+            let mut buf = vec![];
+            write_i32(&mut buf, value);
+            buf
+        };
+
+        assert_eq!(encode(0), vec![0x00]);
+        assert_eq!(encode(1), vec![0x01]);
+        assert_eq!(encode(63), vec![0x3f]);
+        assert_eq!(encode(64), vec![0xc0, 0x00]);
+        assert_eq!(encode(-1), vec![0x7f]);
+        assert_eq!(encode(-64), vec![0x40]);
+        assert_eq!(encode(-65), vec![0xbf, 0x7f]);
+        assert_eq!(encode(i32::MIN), vec![0x80, 0x80, 0x80, 0x80, 0x78]);
+    }
+
+    #[test]
+    fn it_encodes_empty_module() {
+        assert_eq!(
+            encode_source(""),
+            vec![
+                0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+                0x01, 0x01, 0x00, // type section
+                0x03, 0x01, 0x00, // function section
+                0x07, 0x01, 0x00, // export section
+                0x0a, 0x01, 0x00, // code section
+            ]
+        );
+    }
+
+    #[test]
+    fn it_encodes_function() {
+        assert_eq!(
+            encode_source("def add(a b) a + b;"),
+            vec![
+                0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+                0x01, 0x07, 0x01, 0x60, 0x02, 0x7d, 0x7d, 0x01, 0x7d, // type section
+                0x03, 0x02, 0x01, 0x00, // function section
+                0x07, 0x07, 0x01, 0x03, b'a', b'd', b'd', 0x00, 0x00, // export section
+                0x0a, 0x09, 0x01, 0x07, 0x00, // code section, one body without locals
+                0x20, 0x00, 0x20, 0x01, 0x92, 0x0b, // local.get 0, local.get 1, f32.add
+            ]
+        );
+    }
+
+    #[test]
+    fn it_encodes_calls_if_and_constants() {
+        let wasm = encode_source("def one() 1; def f(x) if x < 2 then sqrt(x) else one();");
+
+        // Both signatures are shared by index: () -> f32 and (f32) -> f32.
+        assert_eq!(
+            &wasm[8..22],
+            &[0x01, 0x0a, 0x02, 0x60, 0x00, 0x01, 0x7d, 0x60, 0x01, 0x7d, 0x01, 0x7d, 0x03, 0x03]
+        );
+        assert!(wasm.ends_with(&[
+            0x20, 0x00, // local.get $x
+            0x43, 0x00, 0x00, 0x00, 0x40, // f32.const 2
+            0x5d, // f32.lt
+            0x04, 0x7d, // if (result f32)
+            0x20, 0x00, 0x91, // local.get $x, f32.sqrt
+            0x05, 0x10, 0x00, // else, call $one
+            0x0b, 0x0b, // end, end
+        ]));
+    }
+
+    #[test]
+    fn it_encodes_locals() {
+        let mut parser = Parser::new(Cursor::new(
+            "def f(x) let y = x in let x = y < 1 in let z = y in z;",
+        ));
+        parser.main_loop();
+        crate::type_checker::TypeChecker::new().run(parser.get_asts_mut());
+        let wasm = Encoder::new(parser.get_asts()).encode().unwrap();
+
+        assert!(wasm.ends_with(&[
+            0x03, 0x01, 0x7d, 0x01, 0x7f, 0x01, 0x7d, // locals: f32, i32, f32
+            0x20, 0x00, 0x21, 0x01, // local.get $x, local.set $y
+            0x20, 0x01, 0x43, 0x00, 0x00, 0x80, 0x3f, 0x5d, 0x21, 0x02, // local.set $x_1
+            0x20, 0x01, 0x21, 0x03, // local.get $y, local.set $z
+            0x20, 0x03, 0x0b, // local.get $z, end
+        ]));
+    }
+
+    #[test]
+    fn it_encodes_shared_subexpressions() {
+        let mut parser = Parser::new(Cursor::new("def f(x) x * x + x * x;"));
+        parser.main_loop();
+        let options = LowerOptions {
+            opt_level: 2,
+            ..LowerOptions::default()
+        };
+        let wasm = Encoder::with_options(parser.get_asts(), options)
+            .encode()
+            .unwrap();
+
+        assert!(wasm.ends_with(&[
+            0x01, 0x01, 0x7d, // locals: f32
+            0x20, 0x00, 0x20, 0x00, 0x94, 0x22, 0x01, // x * x, local.tee $__cse
+            0x20, 0x01, 0x92, 0x0b, // local.get $__cse, f32.add, end
+        ]));
+    }
+
+    #[test]
+    fn it_encodes_a_module_lowered_once() {
+        let mut parser = Parser::new(Cursor::new("def f(x) x * x + x * x; def g(x) f(x);"));
+        parser.main_loop();
+        let options = LowerOptions {
+            opt_level: 2,
+            tail_calls: true,
+        };
+        let module = ModuleCode::new(parser.get_asts(), options);
+        let generator = CodeGenerator::from_module(module.clone(), vec![]);
+
+        let wasm = Encoder::with_options(parser.get_asts(), options)
+            .encode()
+            .unwrap();
+        assert_eq!(Encoder::from_module(module).encode().unwrap(), wasm);
+        assert_eq!(generator.to_wasm().unwrap(), wasm);
+    }
+
+    #[test]
+    fn it_encodes_tail_calls() {
+        let mut parser = Parser::new(Cursor::new(
+            "def f(x) if x < 1 then g(x) else f(x - 1); def g(x) x;",
+        ));
+        parser.main_loop();
+        let options = LowerOptions {
+            opt_level: 1,
+            tail_calls: true,
+        };
+        let wasm = Encoder::with_options(parser.get_asts(), options)
+            .encode()
+            .unwrap();
+
+        assert!(wasm.ends_with(&[
+            0x00, 0x03, 0x7d, // no locals, loop (result f32)
+            0x20, 0x00, 0x43, 0x00, 0x00, 0x80, 0x3f, 0x5d, // x < 1
+            0x04, 0x7d, 0x20, 0x00, 0x12, 0x01, // if, local.get $x, return_call $g
+            0x05, 0x20, 0x00, 0x43, 0x00, 0x00, 0x80, 0x3f, 0x93, // else, x - 1
+            0x21, 0x00, 0x0c, 0x01, // local.set $x, br 1
+            0x0b, 0x0b, 0x0b, // end if, end loop, end
+            0x04, 0x00, 0x20, 0x00, 0x0b, // g
+        ]));
+    }
+
+    #[test]
+    fn it_encodes_imports() {
+        let wasm = encode_source("extern log(x) from \"console\"; def f(x) log(x);");
+
+        assert_eq!(
+            &wasm[8..],
+            &[
+                0x01, 0x06, 0x01, 0x60, 0x01, 0x7d, 0x01, 0x7d, // type section
+                0x02, 0x0f, 0x01, // import section
+                0x07, b'c', b'o', b'n', b's', b'o', b'l', b'e', 0x03, b'l', b'o', b'g', 0x00, 0x00,
+                0x03, 0x02, 0x01, 0x00, // function section
+                0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x01, // export section, f is function 1
+                0x0a, 0x08, 0x01, 0x06, 0x00, // code section
+                0x20, 0x00, 0x10, 0x00, 0x0b, // local.get $x, call $log
+            ]
+        );
+    }
+
+    #[test]
+    fn it_encodes_export_names() {
+        let wasm = encode_source("def one() 1; @export(\"two\") def f() one() + one();");
+
+        assert_eq!(
+            &wasm[20..30],
+            &[0x07, 0x07, 0x01, 0x03, b't', b'w', b'o', 0x00, 0x01, 0x0a]
+        );
+    }
+
+    #[test]
+    fn it_encodes_globals() {
+        let mut parser = Parser::new(Cursor::new("@export const one = 1; def f() one;"));
+        parser.main_loop();
+        crate::interpreter::evaluate_constants(parser.get_asts_mut());
+        let wasm = Encoder::new(parser.get_asts()).encode().unwrap();
+
+        assert_eq!(
+            &wasm[19..],
+            &[
+                0x06, 0x09, 0x01, 0x7d, 0x00, 0x43, 0x00, 0x00, 0x80, 0x3f,
+                0x0b, // global section
+                0x07, 0x07, 0x01, 0x03, b'o', b'n', b'e', 0x03, 0x00, // export section
+                0x0a, 0x09, 0x01, 0x07, 0x00, 0x43, 0x00, 0x00, 0x80, 0x3f,
+                0x0b, // f32.const 1
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_unknown_function() {
+        let mut parser = Parser::new(Cursor::new("def f(x) g(x);"));
+        parser.main_loop();
+
+        let err = Encoder::new(parser.get_asts()).encode().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
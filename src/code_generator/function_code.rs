@@ -0,0 +1,248 @@
+use super::instruction::Instruction;
+use super::lower::{lower_function, Constants, Locals};
+use super::runtime;
+use crate::ast::{Ast, Const, Extern, Function};
+use crate::optimizer::call_graph::CallGraph;
+use crate::optimizer::cse::Cse;
+use crate::optimizer::OptLevel;
+use crate::span::MAIN_FILE;
+use crate::types::Type;
+use std::collections::HashSet;
+
+/// A function ready to be written out: a definition of the program or a
+/// runtime helper added by the compiler. Parameters and the result are `f32`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCode {
+    pub name: String,
+    pub params: Vec<String>,
+    /// Locals declared after the parameters.
+    pub locals: Vec<(String, Type)>,
+    pub instrs: Vec<Instruction>,
+    /// Name of the export, if the function is exported.
+    pub export: Option<String>,
+}
+
+impl FunctionCode {
+    pub fn from_function(func: &Function, locals: Locals) -> Self {
+        let (instrs, locals) = lower_function(func, locals);
+
+        Self {
+            name: func.get_function_name().to_owned(),
+            params: func.get_params().iter().map(|p| p.name.clone()).collect(),
+            locals: locals.get_declared().clone(),
+            instrs,
+            export: func.get_export_name().map(str::to_owned),
+        }
+    }
+
+    pub fn to_wat(&self) -> Vec<String> {
+        let mut func = vec![format!("(func ${}", self.name)];
+
+        for param in self.params.iter() {
+            func.push(format!(" (param ${} f32)", param));
+        }
+        func.push(String::from(" (result f32)\n"));
+        for (name, ty) in self.locals.iter() {
+            func.push(format!("(local ${} {})\n", name, ty.to_wat()));
+        }
+        func.extend(self.instrs.iter().map(Instruction::to_wat));
+        func.push(String::from(")\n"));
+
+        func
+    }
+
+    fn callees(&self) -> impl Iterator<Item = &str> {
+        self.instrs.iter().filter_map(|instr| match instr {
+            Instruction::Call(name) | Instruction::ReturnCall(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// A host function declared with `extern`. Imports come before the
+/// functions of the module in the function index space.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionImport {
+    pub name: String,
+    pub module: String,
+    pub field: String,
+    pub arity: usize,
+}
+
+impl FunctionImport {
+    pub fn from_extern(ext: &Extern) -> Self {
+        Self {
+            name: ext.get_proto().get_name().to_owned(),
+            module: ext.get_module().to_owned(),
+            field: ext.get_field().to_owned(),
+            arity: ext.get_proto().get_params().len(),
+        }
+    }
+
+    pub fn to_wat(&self) -> String {
+        format!(
+            "(import \"{}\" \"{}\" (func ${}{} (result f32)))\n",
+            self.module,
+            self.field,
+            self.name,
+            " (param f32)".repeat(self.arity)
+        )
+    }
+}
+
+/// The `extern` declarations of the program, in source order.
+pub fn lower_imports(asts: &[Ast]) -> Vec<FunctionImport> {
+    asts.iter()
+        .filter_map(|ast| match ast {
+            Ast::Extern(ext) => Some(FunctionImport::from_extern(ext)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A `const` kept in an immutable global, initialized with its value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalCode {
+    pub name: String,
+    pub value: f32,
+    pub export: Option<String>,
+}
+
+impl GlobalCode {
+    pub fn from_const(constant: &Const) -> Self {
+        Self {
+            name: constant.get_name().to_owned(),
+            value: const_value(constant),
+            export: constant.get_export_name().map(str::to_owned),
+        }
+    }
+
+    pub fn to_wat(&self) -> String {
+        format!(
+            "(global ${} f32 ({}))\n",
+            self.name,
+            Instruction::F32Const(self.value).to_wat().trim_end()
+        )
+    }
+}
+
+/// The globals of the exported constants, in source order.
+pub fn lower_globals(asts: &[Ast]) -> Vec<GlobalCode> {
+    asts.iter()
+        .filter_map(Ast::as_const)
+        .filter(|constant| constant.get_export_name().is_some())
+        .map(GlobalCode::from_const)
+        .collect()
+}
+
+/// The value of every `const` of the program, which the functions use
+/// directly.
+pub fn lower_constants(asts: &[Ast]) -> Constants {
+    asts.iter()
+        .filter_map(Ast::as_const)
+        .map(|constant| (constant.get_name().to_owned(), const_value(constant)))
+        .collect()
+}
+
+fn const_value(constant: &Const) -> f32 {
+    constant
+        .get_value()
+        .expect("constants are evaluated before code generation")
+}
+
+/// Whether any definition or constant is marked `@export`.
+pub fn has_exports(asts: &[Ast]) -> bool {
+    asts.iter().any(|ast| {
+        ast.get_attributes()
+            .iter()
+            .any(|attr| attr.name == "export")
+    })
+}
+
+/// How `lower_program` generates the code of the functions.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowerOptions {
+    pub opt_level: OptLevel,
+    /// Whether calls in tail position use `return_call`.
+    pub tail_calls: bool,
+}
+
+/// Lowers the definitions, followed by the runtime helpers they call in the
+/// order they are first used. Definitions marked `@export` are exported; a
+/// program without any `@export` exports every definition under its own
+/// name.
+///
+/// From level 1 on, calls of a function to itself in tail position jump
+/// back to its start, and from level 2 on, the common subexpressions of
+/// every body are shared.
+pub fn lower_program(asts: &[Ast], options: LowerOptions) -> Vec<FunctionCode> {
+    let constants = lower_constants(asts);
+    // Calls of externs, and of definitions reaching one, are never shared.
+    let mut impure: HashSet<String> = lower_imports(asts)
+        .into_iter()
+        .map(|import| import.name)
+        .collect();
+    impure.extend(CallGraph::new(asts).reaching_externs());
+    let mut functions: Vec<FunctionCode> = asts
+        .iter()
+        .filter_map(Ast::as_definition)
+        .map(|func| {
+            let mut locals =
+                Locals::new(func.get_params(), &constants).with_return_calls(options.tail_calls);
+            if options.opt_level >= 1 {
+                locals = locals.with_tail_loop(func.get_function_name());
+            }
+            if options.opt_level >= 2 {
+                locals = locals.with_cse(Cse::new(impure.clone()));
+            }
+            FunctionCode::from_function(func, locals)
+        })
+        .collect();
+
+    if !has_exports(asts) {
+        // Imported definitions are only there for the main file to call.
+        let definitions = asts.iter().filter_map(Ast::as_definition);
+        for (code, func) in functions.iter_mut().zip(definitions) {
+            if func.get_span().file == MAIN_FILE {
+                code.export = Some(code.name.clone());
+            }
+        }
+    }
+
+    // Helpers can call helpers, so the ones just added are scanned as well.
+    let mut index = 0;
+    while index < functions.len() {
+        let missing: Vec<FunctionCode> = functions[index]
+            .callees()
+            .filter_map(runtime::helper)
+            .collect();
+
+        for helper in missing {
+            if !functions.iter().any(|func| func.name == helper.name) {
+                functions.push(helper);
+            }
+        }
+        index += 1;
+    }
+
+    functions
+}
+
+/// A program lowered into the parts of a WebAssembly module, which
+/// `CodeGenerator` writes as text and `Encoder` as binary.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModuleCode {
+    pub imports: Vec<FunctionImport>,
+    pub functions: Vec<FunctionCode>,
+    pub globals: Vec<GlobalCode>,
+}
+
+impl ModuleCode {
+    pub fn new(asts: &[Ast], options: LowerOptions) -> Self {
+        Self {
+            imports: lower_imports(asts),
+            functions: lower_program(asts, options),
+            globals: lower_globals(asts),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::types::Type;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    F32Const(f32),
+    I32Const(i32),
+    LocalGet(String),
+    LocalSet(String),
+    LocalTee(String),
+    GlobalGet(String),
+    Call(String),
+    ReturnCall(String),
+    If(Type),
+    Loop(Type),
+    Br(u32),
+    Else,
+    End,
+    F32Add,
+    F32Sub,
+    F32Mul,
+    F32Div,
+    F32Eq,
+    F32Ne,
+    F32Lt,
+    F32Gt,
+    F32Le,
+    F32Ge,
+    F32Neg,
+    F32Abs,
+    F32Sqrt,
+    F32Ceil,
+    F32Floor,
+    F32Trunc,
+    F32Nearest,
+    I32Or,
+    I32And,
+    I32Eq,
+    I32Ne,
+    I32Eqz,
+    F32ConvertI32U,
+}
+
+impl Instruction {
+    pub fn to_wat(&self) -> String {
+        match self {
+            Self::F32Const(value) => format!("f32.const {}\n", format_f32(*value)),
+            Self::I32Const(value) => format!("i32.const {}\n", value),
+            Self::LocalGet(name) => format!("local.get ${}\n", name),
+            Self::LocalSet(name) => format!("local.set ${}\n", name),
+            Self::LocalTee(name) => format!("local.tee ${}\n", name),
+            Self::GlobalGet(name) => format!("global.get ${}\n", name),
+            Self::Call(name) => format!("call ${}\n", name),
+            Self::ReturnCall(name) => format!("return_call ${}\n", name),
+            Self::If(ty) => format!("if (result {})\n", ty.to_wat()),
+            Self::Loop(ty) => format!("loop (result {})\n", ty.to_wat()),
+            Self::Br(depth) => format!("br {}\n", depth),
+            _ => format!("{}\n", self.mnemonic()),
+        }
+    }
+
+    /// Text name of an instruction that takes no immediate operands.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::F32Const(_) => "f32.const",
+            Self::I32Const(_) => "i32.const",
+            Self::LocalGet(_) => "local.get",
+            Self::LocalSet(_) => "local.set",
+            Self::LocalTee(_) => "local.tee",
+            Self::GlobalGet(_) => "global.get",
+            Self::Call(_) => "call",
+            Self::ReturnCall(_) => "return_call",
+            Self::If(_) => "if",
+            Self::Loop(_) => "loop",
+            Self::Br(_) => "br",
+            Self::Else => "else",
+            Self::End => "end",
+            Self::F32Add => "f32.add",
+            Self::F32Sub => "f32.sub",
+            Self::F32Mul => "f32.mul",
+            Self::F32Div => "f32.div",
+            Self::F32Eq => "f32.eq",
+            Self::F32Ne => "f32.ne",
+            Self::F32Lt => "f32.lt",
+            Self::F32Gt => "f32.gt",
+            Self::F32Le => "f32.le",
+            Self::F32Ge => "f32.ge",
+            Self::F32Neg => "f32.neg",
+            Self::F32Abs => "f32.abs",
+            Self::F32Sqrt => "f32.sqrt",
+            Self::F32Ceil => "f32.ceil",
+            Self::F32Floor => "f32.floor",
+            Self::F32Trunc => "f32.trunc",
+            Self::F32Nearest => "f32.nearest",
+            Self::I32Or => "i32.or",
+            Self::I32And => "i32.and",
+            Self::I32Eq => "i32.eq",
+            Self::I32Ne => "i32.ne",
+            Self::I32Eqz => "i32.eqz",
+            Self::F32ConvertI32U => "f32.convert_i32_u",
+        }
+    }
+
+    /// Binary opcode as defined by the WebAssembly core specification.
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Self::F32Const(_) => 0x43,
+            Self::I32Const(_) => 0x41,
+            Self::LocalGet(_) => 0x20,
+            Self::LocalSet(_) => 0x21,
+            Self::LocalTee(_) => 0x22,
+            Self::GlobalGet(_) => 0x23,
+            Self::Call(_) => 0x10,
+            Self::ReturnCall(_) => 0x12,
+            Self::If(_) => 0x04,
+            Self::Loop(_) => 0x03,
+            Self::Br(_) => 0x0c,
+            Self::Else => 0x05,
+            Self::End => 0x0b,
+            Self::F32Add => 0x92,
+            Self::F32Sub => 0x93,
+            Self::F32Mul => 0x94,
+            Self::F32Div => 0x95,
+            Self::F32Eq => 0x5b,
+            Self::F32Ne => 0x5c,
+            Self::F32Lt => 0x5d,
+            Self::F32Gt => 0x5e,
+            Self::F32Le => 0x5f,
+            Self::F32Ge => 0x60,
+            Self::F32Neg => 0x8c,
+            Self::F32Abs => 0x8b,
+            Self::F32Sqrt => 0x91,
+            Self::F32Ceil => 0x8d,
+            Self::F32Floor => 0x8e,
+            Self::F32Trunc => 0x8f,
+            Self::F32Nearest => 0x90,
+            Self::I32Or => 0x72,
+            Self::I32And => 0x71,
+            Self::I32Eq => 0x46,
+            Self::I32Ne => 0x47,
+            Self::I32Eqz => 0x45,
+            Self::F32ConvertI32U => 0xb3,
+        }
+    }
+}
+
+fn format_f32(value: f32) -> String {
+    if value.is_nan() {
+        String::from("nan")
+    } else if value.is_infinite() {
+        if value > 0.0 {
+            String::from("inf")
+        } else {
+            String::from("-inf")
+        }
+    } else {
+        format!("{}", value)
+    }
+}
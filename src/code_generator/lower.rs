@@ -0,0 +1,272 @@
+use super::builtin_funcs;
+use super::instruction::Instruction;
+use crate::ast::{ExprKind, ExprNode, Function, Parameter};
+use crate::lexer::numbered_name;
+use crate::operator::Operator;
+use crate::optimizer::cse::{Cse, TEMP_NAME};
+use crate::types::Type;
+use std::collections::HashMap;
+
+/// Values of the `const`s of the program, which code uses directly.
+pub type Constants = HashMap<String, f32>;
+
+/// WASM locals of a function body. Every `let` gets a local of its own, so a
+/// name bound more than once is declared as `$x`, `$x_1`, ..., see
+/// `lexer::numbered_name`.
+#[derive(Debug, Clone, Default)]
+pub struct Locals {
+    params: Vec<String>,
+    declared: Vec<(String, Type)>,
+    scope: Vec<(String, String)>,
+    constants: Constants,
+    /// Set when common subexpressions are shared.
+    cse: Option<Cse>,
+    /// Name of the function when its calls to itself in tail position jump
+    /// back to its start instead.
+    tail_loop: Option<String>,
+    /// Whether the other calls in tail position use `return_call`.
+    return_calls: bool,
+    /// Whether the expression being lowered is in tail position: its value
+    /// is the result of the function.
+    is_tail: bool,
+    /// Blocks the expression being lowered is nested in.
+    blocks: u32,
+    /// Whether a tail call jumps back to the start of the function.
+    loops: bool,
+}
+
+impl Locals {
+    pub fn new(params: &[Parameter], constants: &Constants) -> Self {
+        Self {
+            params: params.iter().map(|param| param.name.clone()).collect(),
+            constants: constants.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Shares the common subexpressions of the body, see `Cse`.
+    pub fn with_cse(mut self, cse: Cse) -> Self {
+        self.cse = Some(cse);
+        self
+    }
+
+    /// Turns the calls of `name` to itself in tail position into a jump back
+    /// to its start, which reuses its frame.
+    pub fn with_tail_loop(mut self, name: &str) -> Self {
+        self.tail_loop = Some(name.to_owned());
+        self
+    }
+
+    /// Makes the other calls in tail position with `return_call`, from the
+    /// WebAssembly tail call extension.
+    pub fn with_return_calls(mut self, return_calls: bool) -> Self {
+        self.return_calls = return_calls;
+        self
+    }
+
+    /// Locals in declaration order, after the parameters.
+    pub fn get_declared(&self) -> &Vec<(String, Type)> {
+        &self.declared
+    }
+
+    fn declare(&mut self, name: &str, ty: Type) -> String {
+        let is_taken = |locals: &Self, candidate: &str| {
+            locals.params.iter().any(|param| param == candidate)
+                || locals.declared.iter().any(|(local, _)| local == candidate)
+        };
+
+        let mut local = name.to_owned();
+        let mut suffix = 0;
+        while is_taken(self, &local) {
+            suffix += 1;
+            local = numbered_name(name, suffix);
+        }
+
+        self.declared.push((local.clone(), ty));
+        local
+    }
+
+    fn enter_branch(&self) -> usize {
+        self.cse.as_ref().map_or(0, Cse::enter_branch)
+    }
+
+    fn leave_branch(&mut self, mark: usize) {
+        if let Some(cse) = &mut self.cse {
+            cse.leave_branch(mark);
+        }
+    }
+
+    /// Names the locals of the shared subexpressions in `instrs`.
+    fn finish(&mut self, instrs: &mut Vec<Instruction>) {
+        if let Some(cse) = self.cse.take() {
+            cse.finish(instrs, |ty| self.declare(TEMP_NAME, ty));
+        }
+    }
+
+    /// Reads a variable: the innermost `let` binding it, else the parameter,
+    /// else the constant of the same name.
+    fn lookup(&self, name: &str) -> Instruction {
+        if let Some((_, local)) = self.scope.iter().rev().find(|(var, _)| var == name) {
+            return Instruction::LocalGet(local.clone());
+        }
+
+        match self.constants.get(name) {
+            Some(value) if !self.params.iter().any(|p| p == name) => Instruction::F32Const(*value),
+            _ => Instruction::LocalGet(name.to_owned()),
+        }
+    }
+}
+
+/// Lowers the expression, declaring a local in `locals` for every `let`.
+pub fn lower_expr(node: &ExprNode, locals: &mut Locals) -> Vec<Instruction> {
+    let is_shareable = match &node.kind {
+        ExprKind::BinaryExpr { op, .. } => !op.is_short_circuit(),
+        ExprKind::UnaryExpr { .. } | ExprKind::CallExpr { .. } => true,
+        _ => false,
+    };
+    // The value of a tail expression is returned, so it is never reused.
+    if !is_shareable || locals.cse.is_none() || locals.is_tail {
+        return lower_node(node, locals);
+    }
+
+    // The code without any sharing identifies the expression.
+    let cse = locals.cse.take();
+    let plain = lower_node(node, &mut locals.clone());
+    locals.cse = cse;
+    let cse = locals.cse.as_mut().expect("checked above");
+    if !cse.is_candidate(&plain) {
+        return lower_node(node, locals);
+    }
+    if let Some(get) = cse.reuse(&plain) {
+        return vec![get];
+    }
+
+    let mut instrs = lower_node(node, locals);
+    let cse = locals.cse.as_mut().expect("kept while lowering");
+    instrs.push(cse.keep(plain, node.ty));
+    instrs
+}
+
+/// Lowers `node` itself; `lower_expr` decides whether its value is shared.
+fn lower_node(node: &ExprNode, locals: &mut Locals) -> Vec<Instruction> {
+    let mut instrs = vec![];
+    let is_tail = std::mem::replace(&mut locals.is_tail, false);
+
+    match &node.kind {
+        // Booleans folded by the optimizer.
+        ExprKind::Number(number) if node.ty == Type::Bool => {
+            instrs.push(Instruction::I32Const(*number as i32))
+        }
+        ExprKind::Number(number) => instrs.push(Instruction::F32Const(*number as f32)),
+        // Boolean constants are stored as 1 or 0.
+        ExprKind::Variable(var) => match locals.lookup(var) {
+            Instruction::F32Const(value) if node.ty == Type::Bool => {
+                instrs.push(Instruction::I32Const(value as i32))
+            }
+            instr => instrs.push(instr),
+        },
+        // The right operand only runs when it decides the result.
+        ExprKind::BinaryExpr { op, lhs, rhs } if op.is_short_circuit() => {
+            instrs.append(&mut lower_expr(lhs, locals));
+            instrs.push(Instruction::If(Type::Bool));
+            locals.blocks += 1;
+            let mark = locals.enter_branch();
+            if *op == Operator::AndAlso {
+                instrs.append(&mut lower_expr(rhs, locals));
+                instrs.push(Instruction::Else);
+                instrs.push(Instruction::I32Const(0));
+            } else {
+                instrs.push(Instruction::I32Const(1));
+                instrs.push(Instruction::Else);
+                instrs.append(&mut lower_expr(rhs, locals));
+            }
+            locals.leave_branch(mark);
+            locals.blocks -= 1;
+            instrs.push(Instruction::End);
+        }
+        ExprKind::BinaryExpr { op, lhs, rhs } => {
+            instrs.append(&mut lower_expr(lhs, locals));
+            instrs.append(&mut lower_expr(rhs, locals));
+            instrs.push(op.to_instruction(lhs.ty));
+        }
+        ExprKind::UnaryExpr { op, rhs } => {
+            instrs.append(&mut lower_expr(rhs, locals));
+            instrs.push(op.to_instruction(rhs.ty));
+        }
+
+        ExprKind::CallExpr { callee, args } => {
+            let builtin_funcs = builtin_funcs();
+
+            for expr in args {
+                instrs.append(&mut lower_expr(expr, locals));
+            }
+            if let Some(instr) = builtin_funcs.get(callee.as_str()) {
+                instrs.push(instr.clone());
+            } else if is_tail && locals.tail_loop.as_ref() == Some(callee) {
+                // The arguments are on the stack, the last one on top.
+                for param in locals.params.iter().rev() {
+                    instrs.push(Instruction::LocalSet(param.clone()));
+                }
+                instrs.push(Instruction::Br(locals.blocks));
+                locals.loops = true;
+            } else if is_tail && locals.return_calls {
+                instrs.push(Instruction::ReturnCall(callee.clone()));
+            } else {
+                instrs.push(Instruction::Call(callee.clone()));
+            }
+        }
+        ExprKind::IfExpr {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            instrs.append(&mut lower_expr(cond, locals));
+            instrs.push(Instruction::If(node.ty));
+            locals.blocks += 1;
+            let mark = locals.enter_branch();
+            locals.is_tail = is_tail;
+            instrs.append(&mut lower_expr(then_branch, locals));
+            locals.leave_branch(mark);
+            instrs.push(Instruction::Else);
+            locals.is_tail = is_tail;
+            instrs.append(&mut lower_expr(else_branch, locals));
+            locals.leave_branch(mark);
+            locals.blocks -= 1;
+            instrs.push(Instruction::End);
+        }
+        ExprKind::LetExpr {
+            name, value, body, ..
+        } => {
+            instrs.append(&mut lower_expr(value, locals));
+            let local = locals.declare(name, value.ty);
+            instrs.push(Instruction::LocalSet(local.clone()));
+
+            locals.scope.push((name.clone(), local));
+            locals.is_tail = is_tail;
+            instrs.append(&mut lower_expr(body, locals));
+            locals.scope.pop();
+        }
+    };
+
+    instrs
+}
+
+/// Instructions of the body of `func` together with the locals they use, starting
+/// from `locals` made for the parameters.
+pub fn lower_function(func: &Function, mut locals: Locals) -> (Vec<Instruction>, Locals) {
+    locals.is_tail = true;
+    let mut instrs = lower_expr(func.get_body(), &mut locals);
+    locals.finish(&mut instrs);
+
+    // Tail calls to the function itself branch to this loop.
+    if locals.loops {
+        instrs.insert(0, Instruction::Loop(func.get_body().ty));
+        instrs.push(Instruction::End);
+    }
+
+    // Functions always return f32; a boolean body yields 1 or 0.
+    if func.get_body().ty == Type::Bool {
+        instrs.push(Instruction::F32ConvertI32U);
+    }
+    (instrs, locals)
+}
@@ -0,0 +1,118 @@
+use super::function_code::FunctionCode;
+use super::instruction::Instruction;
+use super::instruction::Instruction::*;
+use crate::types::Type;
+
+/// Name of the helper implementing `%`, reserved like the names of
+/// `lexer::numbered_name`.
+pub const MOD: &str = "__mod";
+/// Name of the helper implementing `^`.
+pub const POW: &str = "__pow";
+
+/// The runtime helper called `name`, if there is one.
+pub fn helper(name: &str) -> Option<FunctionCode> {
+    match name {
+        MOD => Some(mod_helper()),
+        POW => Some(pow_helper()),
+        _ => None,
+    }
+}
+
+fn get(name: &str) -> Instruction {
+    LocalGet(name.to_owned())
+}
+
+/// `a % b` is the floored modulo `a - b * floor(a / b)`, so the result has
+/// the sign of `b`.
+fn mod_helper() -> FunctionCode {
+    FunctionCode {
+        name: MOD.to_owned(),
+        params: vec![String::from("a"), String::from("b")],
+        locals: vec![],
+        instrs: vec![
+            get("a"),
+            get("a"),
+            get("b"),
+            F32Div,
+            F32Floor,
+            get("b"),
+            F32Mul,
+            F32Sub,
+        ],
+        export: None,
+    }
+}
+
+/// `x ^ n` raises `x` to `n` truncated towards zero, by squaring. Infinite
+/// and NaN exponents give NaN.
+fn pow_helper() -> FunctionCode {
+    let call = || Call(POW.to_owned());
+    let half_n = || vec![get("n"), F32Const(2.0), F32Div, F32Floor];
+
+    let mut instrs = vec![
+        // n - n is NaN unless n is finite.
+        get("n"),
+        get("n"),
+        F32Sub,
+        F32Const(0.0),
+        F32Ne,
+        If(Type::F32),
+        F32Const(f32::NAN),
+        Else,
+        get("n"),
+        F32Const(0.0),
+        F32Lt,
+        If(Type::F32),
+        // x ^ -n = 1 / x ^ n
+        F32Const(1.0),
+        get("x"),
+        get("n"),
+        F32Neg,
+        call(),
+        F32Div,
+        Else,
+        get("n"),
+        F32Const(1.0),
+        F32Lt,
+        If(Type::F32),
+        F32Const(1.0),
+        Else,
+        // h = x ^ floor(n / 2); h * h, times x when trunc(n) is odd.
+        get("x"),
+    ];
+    instrs.extend(half_n());
+    instrs.extend([
+        call(),
+        LocalSet(String::from("h")),
+        get("h"),
+        get("h"),
+        F32Mul,
+        get("n"),
+        F32Trunc,
+    ]);
+    instrs.extend(half_n());
+    instrs.extend([
+        F32Const(2.0),
+        F32Mul,
+        F32Sub,
+        F32Const(0.0),
+        F32Ne,
+        If(Type::F32),
+        get("x"),
+        Else,
+        F32Const(1.0),
+        End,
+        F32Mul,
+        End,
+        End,
+        End,
+    ]);
+
+    FunctionCode {
+        name: POW.to_owned(),
+        params: vec![String::from("x"), String::from("n")],
+        locals: vec![(String::from("h"), Type::F32)],
+        instrs,
+        export: None,
+    }
+}
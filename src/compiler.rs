@@ -0,0 +1,434 @@
+use crate::ast::Ast;
+use crate::code_generator::encoder::Encoder;
+use crate::code_generator::function_code::{LowerOptions, ModuleCode};
+use crate::code_generator::CodeGenerator;
+use crate::diagnostic::{Diagnostic, Diagnostics, SourceFile};
+use crate::import::Loader;
+use crate::interpreter::evaluate_constants;
+use crate::optimizer::dead_code::{find_unused, remove_unreachable};
+use crate::optimizer::{optimize, OptLevel};
+use crate::resolver::Resolver;
+use crate::type_checker::TypeChecker;
+use std::path::PathBuf;
+
+/// Name diagnostics give the source unless `CompileOptions` names it.
+const DEFAULT_SOURCE_NAME: &str = "<source>";
+
+/// Settings for `compile`.
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    emit_wat: bool,
+    emit_wasm: bool,
+    source_name: String,
+    source_path: Option<PathBuf>,
+    opt_level: OptLevel,
+    tail_calls: bool,
+}
+
+impl CompileOptions {
+    pub fn new() -> Self {
+        Self {
+            emit_wat: true,
+            emit_wasm: true,
+            source_name: DEFAULT_SOURCE_NAME.to_owned(),
+            source_path: None,
+            opt_level: 0,
+            tail_calls: false,
+        }
+    }
+
+    /// How much the code is optimized, from 0 to `MAX_OPT_LEVEL`; higher
+    /// levels are treated as the highest.
+    pub fn opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Makes calls in tail position with `return_call`, which needs a host
+    /// supporting the WebAssembly tail call extension. Calls of a function to
+    /// itself become loops from level 1 on either way.
+    pub fn tail_calls(mut self, tail_calls: bool) -> Self {
+        self.tail_calls = tail_calls;
+        self
+    }
+
+    /// Where the source was read from. Its imports are relative to this
+    /// path instead of the current directory, and diagnostics name it.
+    pub fn source_path(mut self, path: &str) -> Self {
+        self.source_name = path.to_owned();
+        self.source_path = Some(PathBuf::from(path));
+        self
+    }
+
+    /// Name diagnostics give the source.
+    pub fn source_name(mut self, name: &str) -> Self {
+        self.source_name = name.to_owned();
+        self
+    }
+
+    /// Whether `Artifact::wat` is produced.
+    pub fn emit_wat(mut self, emit_wat: bool) -> Self {
+        self.emit_wat = emit_wat;
+        self
+    }
+
+    /// Whether `Artifact::wasm` is produced.
+    pub fn emit_wasm(mut self, emit_wasm: bool) -> Self {
+        self.emit_wasm = emit_wasm;
+        self
+    }
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Output of a successful compilation. Outputs disabled in `CompileOptions`
+/// are left empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    pub wat: String,
+    pub wasm: Vec<u8>,
+    pub warnings: Vec<Diagnostic>,
+    /// The files the spans of the warnings point into.
+    pub files: Vec<SourceFile>,
+}
+
+/// A parsed program that passed name resolution and type checking.
+#[derive(Debug, Clone)]
+pub struct Program {
+    asts: Vec<Ast>,
+    warnings: Vec<Diagnostic>,
+    files: Vec<SourceFile>,
+}
+
+impl Program {
+    pub fn get_asts(&self) -> &Vec<Ast> {
+        &self.asts
+    }
+
+    pub fn get_warnings(&self) -> &Vec<Diagnostic> {
+        &self.warnings
+    }
+
+    /// The main file followed by the files it imports.
+    pub fn get_files(&self) -> &Vec<SourceFile> {
+        &self.files
+    }
+}
+
+/// Parses and checks `source`, stopping at the first phase that reports an
+/// error. Imports are relative to the current directory.
+pub fn check(source: &str) -> Result<Program, Diagnostics> {
+    check_with(source, &CompileOptions::default())
+}
+
+/// Like `check`, with the source named and located by `options`.
+pub fn check_with(source: &str, options: &CompileOptions) -> Result<Program, Diagnostics> {
+    let mut loader = Loader::new(&options.source_name, options.source_path.as_deref());
+    loader.run(source);
+    let files = loader.get_files().clone();
+
+    let mut warnings = vec![];
+    let mut asts = loader.get_asts().clone();
+    let result = collect(&mut warnings, loader.get_error_logger())
+        .and_then(|_| collect(&mut warnings, &Resolver::new(&asts).run()))
+        .and_then(|_| collect(&mut warnings, &TypeChecker::new().run(&mut asts)))
+        .and_then(|_| collect(&mut warnings, &evaluate_constants(&mut asts)))
+        .and_then(|_| collect(&mut warnings, &find_unused(&asts)));
+    if let Err(diagnostics) = result {
+        return Err(diagnostics.with_files(files));
+    }
+
+    Ok(Program {
+        asts,
+        warnings,
+        files,
+    })
+}
+
+/// Compiles `source` into WebAssembly text and binary.
+///
+/// ```
+/// use minilang::{compile, CompileOptions};
+///
+/// let artifact = compile("def sq(x) x * x;", &CompileOptions::default()).unwrap();
+/// assert!(artifact.wat.starts_with("(module\n(func $sq"));
+/// assert_eq!(&artifact.wasm[0..4], b"\0asm");
+/// ```
+pub fn compile(source: &str, options: &CompileOptions) -> Result<Artifact, Diagnostics> {
+    let mut program = check_with(source, options)?;
+    let warnings = optimize(&mut program.asts, options.opt_level);
+    program.warnings.extend(warnings);
+    remove_unreachable(&mut program.asts);
+    let lower_options = LowerOptions {
+        opt_level: options.opt_level,
+        tail_calls: options.tail_calls,
+    };
+    // Lowered once for both outputs.
+    let module = ModuleCode::new(program.get_asts(), lower_options);
+    let mut wat = String::new();
+    let mut wasm = vec![];
+
+    if options.emit_wat {
+        let mut generator = CodeGenerator::from_module(module.clone(), vec![]);
+        generator
+            .run()
+            .map_err(|err| internal_error(err, &program))?;
+        wat = String::from_utf8(generator.into_target()).expect("generated WAT is UTF-8");
+    }
+    if options.emit_wasm {
+        wasm = Encoder::from_module(module)
+            .encode()
+            .map_err(|err| internal_error(err, &program))?;
+    }
+
+    Ok(Artifact {
+        wat,
+        wasm,
+        warnings: program.warnings,
+        files: program.files,
+    })
+}
+
+/// Moves warnings and notes into `warnings` and fails if there are errors.
+fn collect(warnings: &mut Vec<Diagnostic>, diagnostics: &[Diagnostic]) -> Result<(), Diagnostics> {
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        let mut all = warnings.clone();
+        all.extend_from_slice(diagnostics);
+        return Err(Diagnostics::new(all));
+    }
+
+    warnings.extend_from_slice(diagnostics);
+    Ok(())
+}
+
+/// Code generation only fails if an earlier pass let an invalid program
+/// through.
+fn internal_error(err: std::io::Error, program: &Program) -> Diagnostics {
+    Diagnostics::new(vec![Diagnostic::error(&format!(
+        "Internal compiler error: {}",
+        err
+    ))])
+    .with_files(program.files.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_compiles_to_wat_and_wasm() {
+        let artifact = compile("def inc(x) x + 1;", &CompileOptions::default()).unwrap();
+
+        assert_eq!(
+            artifact.wat,
+            "(module\n(func $inc (param $x f32) (result f32)\n\
+             local.get $x\nf32.const 1\nf32.add\n)\n\
+             (export \"inc\" (func $inc))\n)\n"
+        );
+        assert_eq!(&artifact.wasm[0..8], b"\0asm\x01\0\0\0");
+        assert!(artifact.warnings.is_empty());
+    }
+
+    #[test]
+    fn it_declares_locals_for_let_bindings() {
+        let artifact = compile(
+            "def f(x) let d = x * x in let x = d < 1 in if x then d else 0;",
+            &CompileOptions::default(),
+        )
+        .unwrap();
+
+        assert!(artifact.wat.starts_with(
+            "(module\n(func $f (param $x f32) (result f32)\n\
+             (local $d f32)\n(local $x_1 i32)\n\
+             local.get $x\nlocal.get $x\nf32.mul\nlocal.set $d\n\
+             local.get $d\nf32.const 1\nf32.lt\nlocal.set $x_1\n\
+             local.get $x_1\nif (result f32)\n"
+        ));
+    }
+
+    #[test]
+    fn it_lowers_short_circuit_operators_to_if() {
+        let artifact = compile(
+            "def f(x) x >= 0 and not (x <= 1) or x != 2;",
+            &CompileOptions::default().emit_wasm(false),
+        )
+        .unwrap();
+
+        assert_eq!(
+            artifact.wat,
+            "(module\n(func $f (param $x f32) (result f32)\n\
+             local.get $x\nf32.const 0\nf32.ge\n\
+             if (result i32)\n\
+             local.get $x\nf32.const 1\nf32.le\ni32.eqz\n\
+             else\ni32.const 0\nend\n\
+             if (result i32)\ni32.const 1\n\
+             else\nlocal.get $x\nf32.const 2\nf32.ne\nend\n\
+             f32.convert_i32_u\n)\n\
+             (export \"f\" (func $f))\n)\n"
+        );
+    }
+
+    #[test]
+    fn it_adds_runtime_helpers_only_when_used() {
+        let options = CompileOptions::default().emit_wasm(false);
+
+        let artifact = compile("def f(x) x * x;", &options).unwrap();
+        assert!(!artifact.wat.contains("__"));
+
+        let artifact = compile("def f(x) x % 2 + x % 3; def g(x) f(x) ^ 2;", &options).unwrap();
+        assert!(artifact.wat.contains(
+            "(func $__mod (param $a f32) (param $b f32) (result f32)\n\
+             local.get $a\nlocal.get $a\nlocal.get $b\nf32.div\nf32.floor\n\
+             local.get $b\nf32.mul\nf32.sub\n)\n"
+        ));
+        assert_eq!(artifact.wat.matches("(func $__mod").count(), 1);
+        assert!(artifact
+            .wat
+            .contains("(func $__pow (param $x f32) (param $n f32) (result f32)\n(local $h f32)\n"));
+        assert!(artifact
+            .wat
+            .ends_with("(export \"f\" (func $f))\n(export \"g\" (func $g))\n)\n"));
+    }
+
+    #[test]
+    fn it_skips_disabled_outputs() {
+        let options = CompileOptions::new().emit_wat(false);
+        let artifact = compile("def one() 1;", &options).unwrap();
+
+        assert!(artifact.wat.is_empty());
+        assert!(!artifact.wasm.is_empty());
+    }
+
+    #[test]
+    fn it_returns_diagnostics() {
+        let diagnostics = compile("def f(x) y;", &CompileOptions::default()).unwrap_err();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Some("E0104"));
+        assert_eq!(
+            diagnostics.to_string(),
+            "error[E0104]: Undefined variable 'y', line 1, column 10"
+        );
+    }
+
+    #[test]
+    fn it_stops_at_first_failing_phase() {
+        let diagnostics =
+            compile("def f(x) x +; def g() h();", &CompileOptions::default()).unwrap_err();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Some("E0001"));
+    }
+
+    #[test]
+    fn it_imports_externs() {
+        let artifact = compile(
+            "extern log(x) from \"console\"; extern def random() as \"rand\"; def f(x) log(x) + random();",
+            &CompileOptions::default(),
+        )
+        .unwrap();
+
+        assert!(artifact.wat.starts_with(
+            "(module\n\
+             (import \"console\" \"log\" (func $log (param f32) (result f32)))\n\
+             (import \"env\" \"rand\" (func $random (result f32)))\n\
+             (func $f (param $x f32) (result f32)\n"
+        ));
+        assert!(artifact.wat.ends_with("(export \"f\" (func $f))\n)\n"));
+    }
+
+    #[test]
+    fn it_exports_marked_definitions() {
+        let artifact = compile(
+            "def discr(a b c) b*b - 4*a*c; @export(\"hasRoots\") def roots(a b c) discr(a, b, c) >= 0; @export def sq(x) x*x;",
+            &CompileOptions::default(),
+        )
+        .unwrap();
+
+        assert!(artifact
+            .wat
+            .ends_with("(export \"hasRoots\" (func $roots))\n(export \"sq\" (func $sq))\n)\n"));
+        assert!(artifact.wat.contains("(func $discr"));
+    }
+
+    #[test]
+    fn it_inlines_constants_and_keeps_exported_ones_in_globals() {
+        let artifact = compile(
+            "const pi = 3.5; const area = sq(pi); @export(\"g\") const g = pi / 2; def sq(x) x * x; @export def f(x) x * pi + area + g;",
+            &CompileOptions::default().emit_wasm(false),
+        )
+        .unwrap();
+
+        assert!(artifact.wat.contains(
+            "local.get $x\nf32.const 3.5\nf32.mul\nf32.const 12.25\nf32.add\nf32.const 1.75\nf32.add\n"
+        ));
+        assert!(artifact.wat.ends_with(
+            "(global $g f32 (f32.const 1.75))\n(export \"f\" (func $f))\n(export \"g\" (global $g))\n)\n"
+        ));
+        assert!(!artifact.wat.contains("(func $sq"));
+    }
+
+    #[test]
+    fn it_reads_boolean_constants_as_booleans() {
+        let artifact = compile(
+            "const on = 1 < 2; @export const big = sq(3) > 5; def sq(x) x * x; \
+             @export def f(x) if on and big then x else 0;",
+            &CompileOptions::default().emit_wasm(false),
+        )
+        .unwrap();
+
+        assert!(artifact
+            .wat
+            .contains("i32.const 1\nif (result i32)\ni32.const 1\nelse\n"));
+    }
+
+    #[test]
+    fn it_reports_constants_it_cannot_compute() {
+        let diagnostics = check("def f(x) f(x); const x = f(1);").unwrap_err();
+
+        assert_eq!(diagnostics[0].code, Some("E0112"));
+    }
+
+    #[test]
+    fn it_compiles_imported_files() {
+        let dir = std::env::temp_dir().join(format!("minilang-compile-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("lib/geometry.ml"),
+            "const pi = 3.5;\n@export def area(r) pi * sq(r);\ndef sq(x) x * x;\ndef bad(x) y;",
+        )
+        .unwrap();
+        let main = dir.join("main.ml");
+        let options = CompileOptions::default()
+            .emit_wasm(false)
+            .source_path(main.to_str().unwrap());
+
+        let diagnostics = compile("import \"lib/geometry.ml\" as g;", &options).unwrap_err();
+        assert_eq!(diagnostics[0].code, Some("E0104"));
+        let file = diagnostics[0].span.unwrap().file;
+        assert!(diagnostics.get_files()[file].name.ends_with("geometry.ml"));
+
+        std::fs::write(
+            dir.join("lib/geometry.ml"),
+            "const pi = 3.5;\n@export def area(r) pi * sq(r);\ndef sq(x) x * x;",
+        )
+        .unwrap();
+        let artifact = compile(
+            "import \"lib/geometry.ml\" as g;\ndef f(r) g.area(r);",
+            &options,
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let wat = artifact.unwrap().wat;
+        assert!(wat.contains(
+            "(func $g.area (param $r f32) (result f32)\n\
+             f32.const 3.5\nlocal.get $r\ncall $g.sq\nf32.mul\n)\n"
+        ));
+        assert!(wat.contains("call $g.area\n"));
+        assert!(wat.ends_with(")\n(export \"f\" (func $f))\n)\n"));
+    }
+}
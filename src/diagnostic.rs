@@ -0,0 +1,411 @@
+use crate::span::Span;
+use std::fmt;
+use std::ops::Deref;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Note => "note",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Self::Error => RED,
+            Self::Warning => YELLOW,
+            Self::Note => GREEN,
+        }
+    }
+}
+
+/// A message about the source program, optionally pointing at a span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Option<Span>,
+    pub label: Option<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: &str) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.to_owned(),
+            span: None,
+            label: None,
+            help: None,
+        }
+    }
+
+    pub fn error(message: &str) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: &str) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn note(message: &str) -> Self {
+        Self::new(Severity::Note, message)
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Text printed next to the underlined span.
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.help = Some(help.to_owned());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.severity.as_str())?;
+        if let Some(code) = self.code {
+            write!(f, "[{}]", code)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(span) = self.span {
+            write!(f, ", line {}, column {}", span.line, span.column)?;
+        }
+        Ok(())
+    }
+}
+
+/// A source file of the program, indexed by the `file` of the spans in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    /// Name shown in diagnostics.
+    pub name: String,
+    pub source: String,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, source: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            source: source.to_owned(),
+        }
+    }
+}
+
+/// Diagnostics returned by a failed compilation, in the order they were
+/// reported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+    files: Vec<SourceFile>,
+}
+
+impl Diagnostics {
+    pub fn new(diagnostics: Vec<Diagnostic>) -> Self {
+        Self {
+            diagnostics,
+            files: vec![],
+        }
+    }
+
+    /// The files the spans of the diagnostics point into.
+    pub fn with_files(mut self, files: Vec<SourceFile>) -> Self {
+        self.files = files;
+        self
+    }
+
+    pub fn get_files(&self) -> &Vec<SourceFile> {
+        &self.files
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    /// Renders every diagnostic with `renderer`, separated by blank lines.
+    pub fn render(&self, renderer: &Renderer) -> String {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| renderer.render(diagnostic))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl Deref for Diagnostics {
+    type Target = [Diagnostic];
+
+    fn deref(&self) -> &Self::Target {
+        &self.diagnostics
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.diagnostics.iter().map(|d| d.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+const TAB_WIDTH: usize = 4;
+
+/// Renders diagnostics in the style of rustc:
+///
+/// ```text
+/// error[E0001]: expected identifier or number
+///  --> source.ml:2:7
+///   |
+/// 2 |   x + ;
+///   |       ^ expected an expression
+/// ```
+pub struct Renderer<'a> {
+    source: &'a str,
+    file_name: &'a str,
+    colored: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(source: &'a str, file_name: &'a str) -> Self {
+        Self {
+            source,
+            file_name,
+            colored: false,
+        }
+    }
+
+    pub fn colored(mut self, colored: bool) -> Self {
+        self.colored = colored;
+        self
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        let severity = diagnostic.severity;
+
+        out.push_str(&self.paint(severity.color(), severity.as_str()));
+        if let Some(code) = diagnostic.code {
+            out.push_str(&self.paint(severity.color(), &format!("[{}]", code)));
+        }
+        out.push_str(&self.paint(BOLD, &format!(": {}", diagnostic.message)));
+        out.push('\n');
+
+        let gutter_width = diagnostic
+            .span
+            .map_or(1, |span| span.line.to_string().len());
+        let gutter = " ".repeat(gutter_width);
+        let bar = self.paint(BLUE, "|");
+
+        match diagnostic.span {
+            Some(span) => {
+                out.push_str(&format!(
+                    "{}{} {}:{}:{}\n",
+                    gutter,
+                    self.paint(BLUE, "-->"),
+                    self.file_name,
+                    span.line,
+                    span.column
+                ));
+                out.push_str(&format!("{} {}\n", gutter, bar));
+
+                let line = self.line_text(span.line);
+                out.push_str(&format!(
+                    "{} {} {}\n",
+                    self.paint(BLUE, &span.line.to_string()),
+                    bar,
+                    expand_tabs(line)
+                ));
+
+                let (offset, width) = underline(line, span);
+                let mut marker = self.paint(severity.color(), &"^".repeat(width));
+                if let Some(label) = &diagnostic.label {
+                    marker.push(' ');
+                    marker.push_str(&self.paint(severity.color(), label));
+                }
+                out.push_str(&format!(
+                    "{} {} {}{}\n",
+                    gutter,
+                    bar,
+                    " ".repeat(offset),
+                    marker
+                ));
+            }
+            None => {
+                out.push_str(&format!(
+                    "{}{} {}\n",
+                    gutter,
+                    self.paint(BLUE, "-->"),
+                    self.file_name
+                ));
+            }
+        }
+
+        if let Some(help) = &diagnostic.help {
+            out.push_str(&format!("{} {}\n", gutter, bar));
+            out.push_str(&format!(
+                "{} {} {}\n",
+                gutter,
+                self.paint(BLUE, "="),
+                self.paint(BOLD, &format!("help: {}", help))
+            ));
+        }
+
+        out
+    }
+
+    fn line_text(&self, line: usize) -> &str {
+        self.source
+            .split('\n')
+            .nth(line - 1)
+            .unwrap_or("")
+            .trim_end_matches('\r')
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.colored {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+fn expand_tabs(line: &str) -> String {
+    line.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|ch| if ch == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+/// Returns the display offset and width of the carets under `line` for
+/// `span`. Spans running past the end of the line are cut at the line end,
+/// and empty spans get a single caret.
+fn underline(line: &str, span: Span) -> (usize, usize) {
+    let prefix: String = line.chars().take(span.column - 1).collect();
+    let rest: String = line.chars().skip(span.column - 1).collect();
+
+    let mut len = 0;
+    let mut width = 0;
+    for ch in rest.chars() {
+        if len >= span.len() {
+            break;
+        }
+        len += ch.len_utf8();
+        width += display_width(&ch.to_string());
+    }
+
+    (display_width(&prefix), width.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_error_with_snippet() {
+        let source = "def f(x)\n  x + ;\n";
+        let diagnostic = Diagnostic::error("Expected identifier or number")
+            .with_code("E0001")
+            .with_span(Span::new(15, 16, 2, 7))
+            .with_label("expected an expression");
+
+        assert_eq!(
+            Renderer::new(source, "main.ml").render(&diagnostic),
+            "error[E0001]: Expected identifier or number\n \
+             --> main.ml:2:7\n  \
+             |\n\
+             2 |   x + ;\n  \
+             |       ^ expected an expression\n"
+        );
+    }
+
+    #[test]
+    fn it_renders_warning_with_help_and_wide_span() {
+        let source = "def f(x)\n\tx + y;";
+        let diagnostic = Diagnostic::warning("Suspicious expression")
+            .with_span(Span::new(10, 15, 2, 2))
+            .with_help("simplify it");
+
+        assert_eq!(
+            Renderer::new(source, "main.ml").render(&diagnostic),
+            "warning: Suspicious expression\n \
+             --> main.ml:2:2\n  \
+             |\n\
+             2 |     x + y;\n  \
+             |     ^^^^^\n  \
+             |\n  \
+             = help: simplify it\n"
+        );
+    }
+
+    #[test]
+    fn it_renders_note_without_span() {
+        let diagnostic = Diagnostic::note("Nothing to do");
+
+        assert_eq!(
+            Renderer::new("", "main.ml").render(&diagnostic),
+            "note: Nothing to do\n --> main.ml\n"
+        );
+    }
+
+    #[test]
+    fn it_renders_colors() {
+        let diagnostic = Diagnostic::error("Missing ';'").with_span(Span::new(0, 1, 1, 1));
+        let rendered = Renderer::new("x", "main.ml")
+            .colored(true)
+            .render(&diagnostic);
+
+        assert!(rendered.starts_with("\x1b[1;31merror\x1b[0m"));
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"));
+    }
+
+    #[test]
+    fn it_formats_single_line() {
+        let diagnostic = Diagnostic::error("Missing ';'")
+            .with_code("E0010")
+            .with_span(Span::new(4, 5, 3, 2));
+
+        assert_eq!(
+            diagnostic.to_string(),
+            "error[E0010]: Missing ';', line 3, column 2"
+        );
+    }
+}
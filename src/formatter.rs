@@ -0,0 +1,384 @@
+use crate::ast::{
+    Ast, Attribute, Const, ExprKind, ExprNode, Extern, Function, Import, DEFAULT_IMPORT_MODULE,
+};
+use crate::diagnostic::Diagnostics;
+use crate::operator::{Associativity, Operator};
+use crate::parser::Parser;
+use crate::span::Span;
+
+const INDENT: &str = "  ";
+
+/// Reformats a program in the canonical style:
+///
+/// ```text
+/// def root1(a b c)
+///   if discr(a, b, c) < 0
+///   then 0
+///   else (-b + sqrt(discr(a, b, c))) / (2 * a);
+/// ```
+///
+/// Comments are kept. A comment inside a definition is moved above it, and
+/// one following a definition on the same line stays at the end of it.
+pub fn format_source(source: &str) -> Result<String, Diagnostics> {
+    let mut parser = Parser::new(source.as_bytes());
+    parser.main_loop();
+
+    if parser.get_error_logger().has_errors() {
+        return Err(Diagnostics::new(parser.get_error_logger().to_vec()));
+    }
+
+    Ok(Formatter::new(source, parser.get_comments()).format(parser.get_asts()))
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: &'a [(Span, String)],
+    next_comment: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, comments: &'a [(Span, String)]) -> Self {
+        Self {
+            source,
+            comments,
+            next_comment: 0,
+        }
+    }
+
+    fn format(&mut self, asts: &[Ast]) -> String {
+        let mut out = String::new();
+
+        for (index, ast) in asts.iter().enumerate() {
+            if index > 0 {
+                out.push('\n');
+            }
+
+            while let Some((span, text)) = self.comments.get(self.next_comment) {
+                if span.start > ast.get_span().end {
+                    break;
+                }
+                out.push_str(text);
+                out.push('\n');
+                self.next_comment += 1;
+            }
+
+            out.push_str(&match ast {
+                Ast::Definition(func) => self.format_function(func),
+                Ast::Extern(ext) => format_extern(ext),
+                Ast::Const(constant) => self.format_const(constant),
+                Ast::Import(import) => format_import(import),
+            });
+            out.push(';');
+
+            if let Some((span, text)) = self.comments.get(self.next_comment) {
+                let between = &self.source[ast.get_span().end..span.start];
+                if !between.contains('\n') {
+                    out.push(' ');
+                    out.push_str(text);
+                    self.next_comment += 1;
+                }
+            }
+            out.push('\n');
+        }
+
+        if self.next_comment < self.comments.len() && !out.is_empty() {
+            out.push('\n');
+        }
+        for (_, text) in &self.comments[self.next_comment..] {
+            out.push_str(text);
+            out.push('\n');
+        }
+
+        out
+    }
+
+    fn format_const(&self, constant: &Const) -> String {
+        format!(
+            "{}const {} = {}",
+            format_attributes(constant.get_attributes()),
+            constant.get_name(),
+            self.format_expr(constant.get_expr())
+        )
+    }
+
+    fn format_function(&self, func: &Function) -> String {
+        let mut out = format_attributes(func.get_attributes());
+        out.push_str(&format!("def {}\n", func.get_proto()));
+        let mut body = func.get_body();
+        // Text starting the line of `body`; `else ` for an `else if` chain.
+        let mut prefix = String::from(INDENT);
+
+        loop {
+            match &body.kind {
+                ExprKind::LetExpr {
+                    name,
+                    value,
+                    body: rest,
+                    ..
+                } if prefix == INDENT => {
+                    out.push_str(&format!(
+                        "{}let {} = {} in\n",
+                        INDENT,
+                        name,
+                        self.format_expr(value)
+                    ));
+                    body = rest;
+                }
+                ExprKind::IfExpr {
+                    cond,
+                    then_branch,
+                    else_branch,
+                } => {
+                    out.push_str(&format!("{}if {}\n", prefix, self.format_expr(cond)));
+                    out.push_str(&format!(
+                        "{}then {}\n",
+                        INDENT,
+                        self.format_expr(then_branch)
+                    ));
+                    prefix = format!("{}else ", INDENT);
+                    body = else_branch;
+                }
+                _ => {
+                    out.push_str(&format!("{}{}", prefix, self.format_expr(body)));
+                    break;
+                }
+            }
+        }
+
+        out
+    }
+
+    fn format_expr(&self, node: &ExprNode) -> String {
+        match &node.kind {
+            // As written, so `0x10` or `1_000` keep their spelling.
+            ExprKind::Number(_) => self.source[node.span.start..node.span.end].to_owned(),
+            ExprKind::Variable(name) => name.clone(),
+            ExprKind::CallExpr { callee, args } => {
+                let args: Vec<String> = args.iter().map(|arg| self.format_expr(arg)).collect();
+                format!("{}({})", callee, args.join(", "))
+            }
+            ExprKind::UnaryExpr { op, rhs } => {
+                // Keywords like `not` need a space before their operand.
+                let space = if op.symbol().ends_with(char::is_alphabetic) {
+                    " "
+                } else {
+                    ""
+                };
+                match rhs.kind {
+                    ExprKind::Number(_) | ExprKind::Variable(_) | ExprKind::CallExpr { .. } => {
+                        format!("{}{}{}", op.symbol(), space, self.format_expr(rhs))
+                    }
+                    _ => format!("{}{}({})", op.symbol(), space, self.format_expr(rhs)),
+                }
+            }
+            ExprKind::BinaryExpr { op, lhs, rhs } => format!(
+                "{} {} {}",
+                self.format_operand(lhs, *op, false),
+                op.symbol(),
+                self.format_operand(rhs, *op, true)
+            ),
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => format!(
+                "if {} then {} else {}",
+                self.format_expr(cond),
+                self.format_expr(then_branch),
+                self.format_expr(else_branch)
+            ),
+            ExprKind::LetExpr {
+                name, value, body, ..
+            } => format!(
+                "let {} = {} in {}",
+                name,
+                self.format_expr(value),
+                self.format_expr(body)
+            ),
+        }
+    }
+
+    /// Formats an operand of the binary operator `parent`, adding parentheses
+    /// where the parser would otherwise group it differently.
+    fn format_operand(&self, node: &ExprNode, parent: Operator, is_rhs: bool) -> String {
+        let needs_parens = match &node.kind {
+            ExprKind::BinaryExpr { op, .. } => {
+                op.precedence() < parent.precedence()
+                    || (op.precedence() == parent.precedence()
+                        && match parent.associativity() {
+                            Associativity::Left => is_rhs,
+                            Associativity::Right => !is_rhs,
+                            Associativity::None => true,
+                        })
+            }
+            ExprKind::UnaryExpr { op, .. } => op.precedence() < parent.precedence(),
+            // `if` and `let` extend over the whole expression after them.
+            ExprKind::IfExpr { .. } | ExprKind::LetExpr { .. } => true,
+            _ => false,
+        };
+
+        if needs_parens {
+            format!("({})", self.format_expr(node))
+        } else {
+            self.format_expr(node)
+        }
+    }
+}
+
+fn format_extern(ext: &Extern) -> String {
+    let mut out = format!("extern {}", ext.get_proto());
+
+    if ext.get_module() != DEFAULT_IMPORT_MODULE {
+        out.push_str(&format!(" from \"{}\"", ext.get_module()));
+    }
+    if ext.get_field() != ext.get_proto().get_name() {
+        out.push_str(&format!(" as \"{}\"", ext.get_field()));
+    }
+
+    out
+}
+
+fn format_import(import: &Import) -> String {
+    import.to_string()
+}
+
+fn format_attributes(attributes: &[Attribute]) -> String {
+    attributes
+        .iter()
+        .map(|attr| format!("{}\n", attr))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(src: &str) -> String {
+        format_source(src).unwrap()
+    }
+
+    #[test]
+    fn it_formats_definitions() {
+        assert_eq!(
+            format("def   discr(a b c)b*b-4*a*c;def fib(x) if (x==1)|(x==2) then 1 else fib(x-1)+fib(x-2);"),
+            "def discr(a b c)\n  b * b - 4 * a * c;\n\n\
+             def fib(x)\n  if (x == 1) | (x == 2)\n  then 1\n  else fib(x - 1) + fib(x - 2);\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_required_parentheses() {
+        assert_eq!(
+            format("def f(a b c) (a - (b - c)) / (a * (b / c)) - -(a + b) + -a * -b;"),
+            "def f(a b c)\n  (a - (b - c)) / (a * (b / c)) - -(a + b) + -a * -b;\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_comments() {
+        assert_eq!(
+            format("# header\ndef f(x) # inside\n  x; # trailing\n# footer"),
+            "# header\n# inside\ndef f(x)\n  x; # trailing\n\n# footer\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_let_bindings() {
+        assert_eq!(
+            format("def f(a b) let d = a*b in let e = if d < 0 then 0 else d in e+1;"),
+            "def f(a b)\n  let d = a * b in\n  let e = if d < 0 then 0 else d in\n  e + 1;\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_nested_ifs() {
+        assert_eq!(
+            format("def f(x) if x < 0 then 0 else if x < 1 then 1 + if x < 0.5 then 1 else 2 else f(if x > 2 then 2 else x);"),
+            "def f(x)\n  if x < 0\n  then 0\n  else if x < 1\n  then 1 + (if x < 0.5 then 1 else 2)\n  \
+             else f(if x > 2 then 2 else x);\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_logic_operators() {
+        assert_eq!(
+            format("def f(x) !(x<=1) and x>=0 or not x!=2;"),
+            "def f(x)\n  not (x <= 1) and x >= 0 or not (x <> 2);\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_power_operands() {
+        assert_eq!(
+            format("def f(a b c) (-a)^2 + -a**2 + a^b^c + (a^b)^c + a % b * c;"),
+            "def f(a b c)\n  (-a) ^ 2 + -(a ^ 2) + a ^ b ^ c + (a ^ b) ^ c + a % b * c;\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_number_literals_as_written() {
+        let source = "def f(x)\n  0x10 + 0b1010_0000 * 1_000_000 - 1e30 + 2.5E-3 + 0.50 + x;\n";
+
+        assert_eq!(format(source), source);
+        assert_eq!(format(&format(source)), source);
+        assert_eq!(
+            format("const big=0xFF_FF;def g(x)-1_0*x;"),
+            "const big = 0xFF_FF;\n\ndef g(x)\n  -1_0 * x;\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_parenthesized_literals() {
+        assert_eq!(
+            format("def f(x) x * ( 2 # two\n) + ((0x1_0));"),
+            "# two\ndef f(x)\n  x * 2 + 0x1_0;\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_externs() {
+        assert_eq!(
+            format("extern def random();extern print(x) from \"env\" as \"log\";extern log(x)from\"console\";"),
+            "extern random();\n\nextern print(x) as \"log\";\n\nextern log(x) from \"console\";\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_imports() {
+        assert_eq!(
+            format("import\"lib/geometry.ml\";import \"stats.ml\"as s;def f(x) s.mean(x, geometry.pi);"),
+            "import \"lib/geometry.ml\";\n\nimport \"stats.ml\" as s;\n\ndef f(x)\n  s.mean(x, geometry.pi);\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_attributes() {
+        assert_eq!(
+            format("@export(\"root\")  @export def f(x) x;"),
+            "@export(\"root\")\n@export\ndef f(x)\n  x;\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_constants() {
+        assert_eq!(
+            format("const g=9.81;@export const tau=2*pi;"),
+            "const g = 9.81;\n\n@export\nconst tau = 2 * pi;\n"
+        );
+    }
+
+    #[test]
+    fn it_is_idempotent() {
+        let once = format(
+            "def root1(a b c) if discr(a, b, c) < 0 then 0 else (-b + sqrt(discr(a, b, c)))/(2*a);
+             def f(x) if x < 0 then 0 else if x < 1 then 1 + if x < 0.5 then 1 else 2 else 3;",
+        );
+
+        assert_eq!(format(&once), once);
+    }
+
+    #[test]
+    fn it_reports_syntax_errors() {
+        assert!(format_source("def f(x) x +;").is_err());
+    }
+}
@@ -0,0 +1,503 @@
+use crate::ast::{Ast, ExprKind, ExprNode, Import};
+use crate::diagnostic::{Diagnostic, SourceFile};
+use crate::error_logger::ErrorLogger;
+use crate::lexer::numbered_name;
+use crate::parser::Parser;
+use crate::prelude;
+use crate::span::{FileId, MAIN_FILE};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Parses a program together with the files it imports, transitively, and
+/// merges their items into one list.
+///
+/// The items of an imported file are renamed to `prefix.name`, where the
+/// prefix is the namespace the file was first imported under, so two files
+/// importing the same library share its items. Inside each file, `alias.name`
+/// and references to the file's own items are rewritten to these names.
+/// Imported items come before the items of the files importing them.
+pub struct Loader {
+    files: Vec<SourceFile>,
+    /// Directory the imports of each file are relative to.
+    dirs: Vec<PathBuf>,
+    /// Prefix of the items of each file, empty for the main file.
+    prefixes: Vec<String>,
+    ids: HashMap<PathBuf, FileId>,
+    /// Files whose imports are being loaded, the innermost last.
+    loading: Vec<FileId>,
+    asts: Vec<Ast>,
+    err_logger: ErrorLogger,
+}
+
+impl Loader {
+    /// `path` is where the main file is read from. Without it, its imports
+    /// are relative to the current directory.
+    pub fn new(name: &str, path: Option<&Path>) -> Self {
+        let mut ids = HashMap::new();
+        let dir = match path {
+            Some(path) => {
+                if let Ok(canonical) = fs::canonicalize(path) {
+                    ids.insert(canonical, MAIN_FILE);
+                }
+                path.parent().unwrap_or(Path::new("")).to_path_buf()
+            }
+            None => PathBuf::new(),
+        };
+
+        Self {
+            files: vec![SourceFile::new(name, "")],
+            dirs: vec![dir],
+            prefixes: vec![String::new()],
+            ids,
+            loading: vec![],
+            asts: vec![],
+            err_logger: ErrorLogger::new(),
+        }
+    }
+
+    /// Loads `source` and its imports, then the prelude functions they use.
+    pub fn run(&mut self, source: &str) {
+        self.files[MAIN_FILE].source = source.to_owned();
+        self.load_file(MAIN_FILE, source);
+
+        let file = self.files.len();
+        if prelude::link(&mut self.asts, &mut [], &[], file) {
+            self.files
+                .push(SourceFile::new(prelude::NAME, prelude::SOURCE));
+        }
+    }
+
+    pub fn get_asts(&self) -> &Vec<Ast> {
+        &self.asts
+    }
+
+    pub fn get_files(&self) -> &Vec<SourceFile> {
+        &self.files
+    }
+
+    pub fn get_error_logger(&self) -> &ErrorLogger {
+        &self.err_logger
+    }
+
+    fn load_file(&mut self, file: FileId, source: &str) {
+        let mut parser = Parser::new(source.as_bytes()).in_file(file);
+        parser.main_loop();
+        for diagnostic in parser.get_error_logger().iter() {
+            self.err_logger.push_diagnostic(diagnostic.clone());
+        }
+        let asts = std::mem::take(parser.get_asts_mut());
+
+        self.loading.push(file);
+        let mut namespaces = HashMap::new();
+        for ast in asts.iter() {
+            if let Ast::Import(import) = ast {
+                self.load_import(file, import, &mut namespaces);
+            }
+        }
+        self.loading.pop();
+
+        let renamer = Renamer::new(&self.prefixes[file], &asts, namespaces);
+        for mut ast in asts {
+            if matches!(ast, Ast::Import(_)) {
+                continue;
+            }
+            // Only the main program decides what the module exports.
+            if file != MAIN_FILE {
+                ast.remove_attributes("export");
+            }
+            renamer.rename(&mut ast);
+            self.asts.push(ast);
+        }
+    }
+
+    /// Loads the file `import` refers to unless it already is, and maps the
+    /// namespace of the import to the prefix of its items.
+    fn load_import(
+        &mut self,
+        file: FileId,
+        import: &Import,
+        namespaces: &mut HashMap<String, String>,
+    ) {
+        let namespace = import.get_namespace();
+        if !is_namespace(namespace) {
+            self.err_logger.push_diagnostic(
+                Diagnostic::error(&format!("Invalid namespace '{}'", namespace))
+                    .with_code("E0115")
+                    .with_span(import.span)
+                    .with_help("name it with `as`, e.g. `import \"my-lib.ml\" as lib`"),
+            );
+            return;
+        }
+        if namespaces.contains_key(namespace) {
+            self.err_logger.push_diagnostic(
+                Diagnostic::error(&format!(
+                    "Namespace '{}' is imported more than once",
+                    namespace
+                ))
+                .with_code("E0116")
+                .with_span(import.span)
+                .with_help("give one of the imports another name with `as`"),
+            );
+            return;
+        }
+
+        let path = self.dirs[file].join(&import.path);
+        let canonical = match fs::canonicalize(&path) {
+            Ok(canonical) => canonical,
+            Err(err) => {
+                self.push_read_error(import, &path, err);
+                return;
+            }
+        };
+
+        let id = match self.ids.get(&canonical) {
+            Some(&id) => {
+                if let Some(start) = self.loading.iter().position(|&f| f == id) {
+                    self.push_cycle_error(import, start);
+                    return;
+                }
+                id
+            }
+            None => {
+                let source = match fs::read_to_string(&canonical) {
+                    Ok(source) => source,
+                    Err(err) => {
+                        self.push_read_error(import, &path, err);
+                        return;
+                    }
+                };
+
+                let id = self.files.len();
+                self.files
+                    .push(SourceFile::new(&path.display().to_string(), &source));
+                self.dirs
+                    .push(canonical.parent().unwrap_or(Path::new("")).to_path_buf());
+                self.prefixes.push(self.unique_prefix(namespace));
+                self.ids.insert(canonical, id);
+                self.load_file(id, &source);
+                id
+            }
+        };
+
+        namespaces.insert(namespace.to_owned(), self.prefixes[id].clone());
+    }
+
+    /// `namespace`, or `namespace_1`, `namespace_2`, ... if another file
+    /// already uses it, see `lexer::numbered_name`.
+    fn unique_prefix(&self, namespace: &str) -> String {
+        let mut prefix = namespace.to_owned();
+        let mut suffix = 0;
+        while self.prefixes.contains(&prefix) {
+            suffix += 1;
+            prefix = numbered_name(namespace, suffix);
+        }
+        prefix
+    }
+
+    fn push_read_error(&mut self, import: &Import, path: &Path, err: std::io::Error) {
+        self.err_logger.push_diagnostic(
+            Diagnostic::error(&format!("Cannot read '{}': {}", path.display(), err))
+                .with_code("E0114")
+                .with_span(import.span),
+        );
+    }
+
+    /// Reports an import of `self.loading[start]`, which is still loading.
+    fn push_cycle_error(&mut self, import: &Import, start: usize) {
+        let mut chain: Vec<&str> = self.loading[start..]
+            .iter()
+            .map(|&file| self.files[file].name.as_str())
+            .collect();
+        chain.push(&self.files[self.loading[start]].name);
+
+        let diagnostic = Diagnostic::error(&format!("Import cycle: {}", chain.join(" -> ")))
+            .with_code("E0113")
+            .with_span(import.span)
+            .with_label("imported again here");
+        self.err_logger.push_diagnostic(diagnostic);
+    }
+}
+
+/// A namespace must be a name that can be written before `.` in the source.
+fn is_namespace(namespace: &str) -> bool {
+    let mut chars = namespace.chars();
+    chars.next().is_some_and(|ch| ch.is_alphabetic()) && chars.all(char::is_alphanumeric)
+}
+
+/// Rewrites the names of one file to the names of the merged program.
+pub struct Renamer<'a> {
+    prefix: &'a str,
+    /// Names of the items of the file.
+    items: HashSet<String>,
+    /// Prefix of the items of each file imported by the file.
+    namespaces: HashMap<String, String>,
+}
+
+impl<'a> Renamer<'a> {
+    /// `namespaces` maps the namespaces of the imports of the file to the
+    /// prefixes of the imported items.
+    pub fn new(prefix: &'a str, asts: &[Ast], namespaces: HashMap<String, String>) -> Self {
+        Self {
+            prefix,
+            items: asts
+                .iter()
+                .filter(|ast| !matches!(ast, Ast::Import(_)))
+                .map(|ast| ast.get_name().to_owned())
+                .collect(),
+            namespaces,
+        }
+    }
+
+    pub fn rename(&self, ast: &mut Ast) {
+        if !self.prefix.is_empty() {
+            ast.set_name(format!("{}.{}", self.prefix, ast.get_name()));
+        }
+
+        match ast {
+            Ast::Definition(func) => {
+                let mut scope: Vec<String> = func
+                    .get_params()
+                    .iter()
+                    .map(|param| param.name.clone())
+                    .collect();
+                self.rename_expr(func.get_body_mut(), &mut scope);
+            }
+            Ast::Const(constant) => self.rename_expr(constant.get_expr_mut(), &mut vec![]),
+            Ast::Extern(_) | Ast::Import(_) => {}
+        }
+    }
+
+    /// `scope` holds the parameters and let bindings, which shadow constants.
+    fn rename_expr(&self, node: &mut ExprNode, scope: &mut Vec<String>) {
+        match &mut node.kind {
+            ExprKind::Number(_) => {}
+            ExprKind::Variable(name) => {
+                if !scope.contains(name) {
+                    self.qualify(name);
+                }
+            }
+            ExprKind::BinaryExpr { lhs, rhs, .. } => {
+                self.rename_expr(lhs, scope);
+                self.rename_expr(rhs, scope);
+            }
+            ExprKind::UnaryExpr { rhs, .. } => self.rename_expr(rhs, scope),
+            ExprKind::CallExpr { callee, args } => {
+                self.qualify(callee);
+                for arg in args.iter_mut() {
+                    self.rename_expr(arg, scope);
+                }
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.rename_expr(cond, scope);
+                self.rename_expr(then_branch, scope);
+                self.rename_expr(else_branch, scope);
+            }
+            ExprKind::LetExpr {
+                name, value, body, ..
+            } => {
+                self.rename_expr(value, scope);
+                scope.push(name.clone());
+                self.rename_expr(body, scope);
+                scope.pop();
+            }
+        }
+    }
+
+    /// Names of unknown namespaces are left alone for the resolver to
+    /// report.
+    fn qualify(&self, name: &mut String) {
+        if let Some((namespace, item)) = name.split_once('.') {
+            if let Some(prefix) = self.namespaces.get(namespace) {
+                *name = format!("{}.{}", prefix, item);
+            }
+        } else if !self.prefix.is_empty() && self.items.contains(name.as_str()) {
+            *name = format!("{}.{}", self.prefix, name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Writes `files` into a fresh temporary directory and loads the first.
+    fn load(files: &[(&str, &str)]) -> Loader {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "minilang-import-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+
+        let (name, source) = files[0];
+        let mut loader = Loader::new(name, Some(&dir.join(name)));
+        loader.run(source);
+        fs::remove_dir_all(dir).unwrap();
+        loader
+    }
+
+    /// Names of the variables and functions `node` refers to.
+    fn references(node: &ExprNode, out: &mut Vec<String>) {
+        match &node.kind {
+            ExprKind::Number(_) => {}
+            ExprKind::Variable(name) => out.push(name.clone()),
+            ExprKind::BinaryExpr { lhs, rhs, .. } => {
+                references(lhs, out);
+                references(rhs, out);
+            }
+            ExprKind::UnaryExpr { rhs, .. } => references(rhs, out),
+            ExprKind::CallExpr { callee, args } => {
+                out.push(callee.clone());
+                args.iter().for_each(|arg| references(arg, out));
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                references(cond, out);
+                references(then_branch, out);
+                references(else_branch, out);
+            }
+            ExprKind::LetExpr { value, body, .. } => {
+                references(value, out);
+                references(body, out);
+            }
+        }
+    }
+
+    fn body_references(loader: &Loader, index: usize) -> Vec<String> {
+        let mut out = vec![];
+        let func = loader.get_asts()[index].as_definition().unwrap();
+        references(func.get_body(), &mut out);
+        out
+    }
+
+    fn names(loader: &Loader) -> Vec<&str> {
+        loader.get_asts().iter().map(Ast::get_name).collect()
+    }
+
+    fn codes(loader: &Loader) -> Vec<&str> {
+        loader
+            .get_error_logger()
+            .iter()
+            .filter_map(|diagnostic| diagnostic.code)
+            .collect()
+    }
+
+    #[test]
+    fn it_namespaces_imported_items() {
+        let loader = load(&[
+            (
+                "main.ml",
+                "import \"lib/geometry.ml\";\ndef area(r) geometry.area(r) + geometry.pi;",
+            ),
+            (
+                "lib/geometry.ml",
+                "const pi = 3.14;\n@export def area(r) let pi = 3 in pi * sq(r);\ndef sq(x) x * x;",
+            ),
+        ]);
+
+        assert!(codes(&loader).is_empty());
+        assert_eq!(
+            names(&loader),
+            vec!["geometry.pi", "geometry.area", "geometry.sq", "area"]
+        );
+        assert_eq!(body_references(&loader, 1), vec!["pi", "geometry.sq", "r"]);
+        assert!(loader.get_asts()[1].get_attributes().is_empty());
+        assert_eq!(
+            body_references(&loader, 3),
+            vec!["geometry.area", "r", "geometry.pi"]
+        );
+        assert!(loader.get_files()[1].name.ends_with("lib/geometry.ml"));
+    }
+
+    #[test]
+    fn it_loads_shared_imports_once() {
+        let loader = load(&[
+            (
+                "main.ml",
+                "import \"util.ml\";\nimport \"a.ml\";\ndef f(x) util.id(x) + a.g(x);",
+            ),
+            (
+                "a.ml",
+                "import \"lib/util.ml\";\nimport \"util.ml\" as top;\ndef g(x) util.id(top.id(x));",
+            ),
+            ("util.ml", "def id(x) x;"),
+            ("lib/util.ml", "def id(x) x;"),
+        ]);
+
+        assert!(codes(&loader).is_empty());
+        assert_eq!(names(&loader), vec!["util.id", "util_1.id", "a.g", "f"]);
+        assert_eq!(
+            body_references(&loader, 2),
+            vec!["util_1.id", "util.id", "x"]
+        );
+        assert_eq!(
+            body_references(&loader, 3),
+            vec!["util.id", "x", "a.g", "x"]
+        );
+        assert_eq!(loader.get_files().len(), 4);
+    }
+
+    #[test]
+    fn it_reports_import_cycles() {
+        let loader = load(&[
+            ("main.ml", "import \"a.ml\";"),
+            ("a.ml", "import \"b.ml\";"),
+            ("b.ml", "def f() 1;\nimport \"a.ml\";"),
+        ]);
+
+        assert_eq!(codes(&loader), vec!["E0113"]);
+        let error = &loader.get_error_logger()[0];
+        let (a, b) = (&loader.get_files()[1].name, &loader.get_files()[2].name);
+        assert_eq!(
+            error.message,
+            format!("Import cycle: {} -> {} -> {}", a, b, a)
+        );
+        assert_eq!(error.span.unwrap().file, 2);
+        assert_eq!(error.span.unwrap().line, 2);
+    }
+
+    #[test]
+    fn it_reports_errors_in_the_imported_file() {
+        let loader = load(&[
+            ("main.ml", "import \"missing.ml\";\nimport \"lib.ml\";"),
+            ("lib.ml", "def f(x) x +;"),
+        ]);
+
+        assert_eq!(codes(&loader), vec!["E0114", "E0001"]);
+        let spans: Vec<_> = loader
+            .get_error_logger()
+            .iter()
+            .map(|diagnostic| diagnostic.span.unwrap().file)
+            .collect();
+        assert_eq!(spans, vec![MAIN_FILE, 1]);
+    }
+
+    #[test]
+    fn it_checks_namespaces() {
+        let loader = load(&[
+            (
+                "main.ml",
+                "import \"my-lib.ml\";\nimport \"a.ml\" as m;\nimport \"b.ml\" as m;",
+            ),
+            ("my-lib.ml", ""),
+            ("a.ml", ""),
+            ("b.ml", ""),
+        ]);
+
+        assert_eq!(codes(&loader), vec!["E0115", "E0116"]);
+    }
+}
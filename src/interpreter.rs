@@ -0,0 +1,724 @@
+use crate::ast::{Ast, Const, ExprKind, ExprNode, Function};
+use crate::code_generator::builtin_funcs;
+use crate::code_generator::instruction::Instruction;
+use crate::diagnostic::Diagnostic;
+use crate::error_logger::ErrorLogger;
+use crate::operator::Operator;
+use crate::types::Type;
+use std::collections::HashMap;
+use std::fmt;
+
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+/// Recursion limit while computing constants, and the stack of the thread
+/// computing them, which is large enough for it.
+const CONST_MAX_DEPTH: usize = 1_000;
+const CONST_STACK_SIZE: usize = 64 * 1024 * 1024;
+/// Limit of tail calls in a row while computing constants, which ends
+/// endless loops like `def f(x) f(x);`.
+const CONST_MAX_TAIL_CALLS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Number(f32),
+    Bool(bool),
+}
+
+impl Value {
+    /// Converts the value the same way a function result is converted in
+    /// the generated WebAssembly: booleans become 1 or 0.
+    pub fn as_number(self) -> f32 {
+        match self {
+            Value::Number(number) => number,
+            Value::Bool(true) => 1.0,
+            Value::Bool(false) => 0.0,
+        }
+    }
+
+    fn as_bool(self) -> bool {
+        match self {
+            Value::Bool(value) => value,
+            Value::Number(number) => number != 0.0,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RuntimeError {
+    UnknownFunction(String),
+    UnknownVariable(String),
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    StackOverflow(usize),
+    TooManyTailCalls(usize),
+    MissingHostFunction(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownFunction(name) => write!(f, "Unknown function '{}'", name),
+            Self::UnknownVariable(name) => write!(f, "Unknown variable '{}'", name),
+            Self::ArityMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Function '{}' takes {} argument(s) but {} were supplied",
+                name, expected, found
+            ),
+            Self::StackOverflow(depth) => {
+                write!(f, "Maximum recursion depth of {} exceeded", depth)
+            }
+            Self::TooManyTailCalls(count) => {
+                write!(f, "Maximum of {} tail calls in a row exceeded", count)
+            }
+            Self::MissingHostFunction(name) => {
+                write!(f, "No host function provided for extern '{}'", name)
+            }
+        }
+    }
+}
+
+/// Evaluates definitions directly, without generating WebAssembly.
+///
+/// The program is expected to have passed the resolver and the type checker.
+/// Every call nests on the Rust stack, so very deep recursion needs both a
+/// larger `max_depth` and a thread with a large enough stack. Calls of
+/// definitions in tail position do not nest, so tail recursion of any depth
+/// runs, and by default without end if it never stops.
+pub struct Interpreter<'a> {
+    functions: HashMap<&'a str, &'a Function>,
+    /// Arity of every `extern`, which calls one of `host_functions`.
+    externs: HashMap<&'a str, usize>,
+    host_functions: HashMap<String, HostFunction>,
+    constants: HashMap<&'a str, &'a Const>,
+    /// Values of the constants computed so far.
+    const_values: HashMap<&'a str, f32>,
+    builtin_funcs: HashMap<&'a str, Instruction>,
+    max_depth: usize,
+    depth: usize,
+    max_tail_calls: usize,
+}
+
+/// Result of an expression in tail position.
+enum Tail<'n> {
+    Value(Value),
+    /// A call of the definition with these arguments, which gives the result.
+    Call(&'n str, Vec<Value>),
+}
+
+/// Implementation of an `extern` for the interpreter, which receives the
+/// arguments of the call.
+pub type HostFunction = fn(&[f32]) -> f32;
+
+impl<'a> Interpreter<'a> {
+    pub fn new(asts: &'a [Ast]) -> Self {
+        let functions = asts
+            .iter()
+            .filter_map(Ast::as_definition)
+            .map(|func| (func.get_function_name(), func))
+            .collect();
+        let externs = asts
+            .iter()
+            .filter_map(|ast| match ast {
+                Ast::Extern(ext) => Some((
+                    ext.get_proto().get_name(),
+                    ext.get_proto().get_params().len(),
+                )),
+                _ => None,
+            })
+            .collect();
+        let constants = asts
+            .iter()
+            .filter_map(Ast::as_const)
+            .map(|constant| (constant.get_name(), constant))
+            .collect();
+
+        Self {
+            functions,
+            externs,
+            host_functions: HashMap::new(),
+            constants,
+            const_values: HashMap::new(),
+            builtin_funcs: builtin_funcs(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            depth: 0,
+            max_tail_calls: usize::MAX,
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Fails a call that makes more than `max_tail_calls` tail calls in a
+    /// row.
+    pub fn with_max_tail_calls(mut self, max_tail_calls: usize) -> Self {
+        self.max_tail_calls = max_tail_calls;
+        self
+    }
+
+    /// Provides the function called by the `extern` named `name`.
+    pub fn with_host_function(mut self, name: &str, function: HostFunction) -> Self {
+        self.host_functions.insert(name.to_owned(), function);
+        self
+    }
+
+    /// Calls a definition or builtin by name.
+    pub fn call(&mut self, name: &str, args: &[f32]) -> Result<f32, RuntimeError> {
+        let args: Vec<Value> = args.iter().map(|&arg| Value::Number(arg)).collect();
+        Ok(self.call_function(name, args)?.as_number())
+    }
+
+    /// Value of the constant `name`, computed on first use. Booleans are 1 or
+    /// 0 like function results.
+    pub fn constant(&mut self, name: &str) -> Result<f32, RuntimeError> {
+        if let Some(value) = self.const_values.get(name) {
+            return Ok(*value);
+        }
+
+        let constant = *self
+            .constants
+            .get(name)
+            .ok_or_else(|| RuntimeError::UnknownVariable(name.to_owned()))?;
+        let value = self.eval(constant.get_expr(), &HashMap::new())?.as_number();
+        self.const_values.insert(constant.get_name(), value);
+
+        Ok(value)
+    }
+
+    /// Evaluates a standalone expression, such as one typed into the REPL.
+    pub fn eval_expression(&mut self, node: &ExprNode) -> Result<Value, RuntimeError> {
+        self.eval(node, &HashMap::new())
+    }
+
+    fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        if let Some(instr) = self.builtin_funcs.get(name) {
+            check_arity(name, 1, args.len())?;
+            return Ok(Value::Number(eval_builtin(instr, args[0].as_number())));
+        }
+        if let Some(&arity) = self.externs.get(name) {
+            check_arity(name, arity, args.len())?;
+            let function = self
+                .host_functions
+                .get(name)
+                .ok_or_else(|| RuntimeError::MissingHostFunction(name.to_owned()))?;
+            let args: Vec<f32> = args.iter().map(|arg| arg.as_number()).collect();
+            return Ok(Value::Number(function(&args)));
+        }
+
+        if self.depth >= self.max_depth {
+            return Err(RuntimeError::StackOverflow(self.max_depth));
+        }
+
+        // A call in tail position replaces the running one instead of
+        // nesting, so tail recursion runs in constant depth.
+        let (mut name, mut args) = (name, args);
+        let mut tail_calls = 0;
+        loop {
+            let func = *self
+                .functions
+                .get(name)
+                .ok_or_else(|| RuntimeError::UnknownFunction(name.to_owned()))?;
+            check_arity(name, func.get_params().len(), args.len())?;
+
+            let env: HashMap<&str, Value> = func
+                .get_params()
+                .iter()
+                .map(|param| param.name.as_str())
+                .zip(args)
+                .collect();
+
+            self.depth += 1;
+            let result = self.eval_tail(func.get_body(), &env);
+            self.depth -= 1;
+
+            match result? {
+                Tail::Value(value) => return Ok(Value::Number(value.as_number())),
+                Tail::Call(_, _) if tail_calls == self.max_tail_calls => {
+                    return Err(RuntimeError::TooManyTailCalls(self.max_tail_calls))
+                }
+                Tail::Call(callee, values) => {
+                    (name, args) = (callee, values);
+                    tail_calls += 1;
+                }
+            }
+        }
+    }
+
+    /// Evaluates an expression in tail position, returning a call of a
+    /// definition there without making it.
+    fn eval_tail<'n>(
+        &mut self,
+        node: &'n ExprNode,
+        env: &HashMap<&'n str, Value>,
+    ) -> Result<Tail<'n>, RuntimeError> {
+        match &node.kind {
+            ExprKind::CallExpr { callee, args } if self.functions.contains_key(callee.as_str()) => {
+                let mut values = vec![];
+                for arg in args {
+                    values.push(self.eval(arg, env)?);
+                }
+                Ok(Tail::Call(callee, values))
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if self.eval(cond, env)?.as_bool() {
+                    self.eval_tail(then_branch, env)
+                } else {
+                    self.eval_tail(else_branch, env)
+                }
+            }
+            ExprKind::LetExpr {
+                name, value, body, ..
+            } => {
+                let value = self.eval(value, env)?;
+                let mut env = env.clone();
+                env.insert(name.as_str(), value);
+                self.eval_tail(body, &env)
+            }
+            _ => Ok(Tail::Value(self.eval(node, env)?)),
+        }
+    }
+
+    fn eval<'n>(
+        &mut self,
+        node: &'n ExprNode,
+        env: &HashMap<&'n str, Value>,
+    ) -> Result<Value, RuntimeError> {
+        match &node.kind {
+            // Booleans folded by the optimizer.
+            ExprKind::Number(number) if node.ty == Type::Bool => Ok(Value::Bool(*number != 0.0)),
+            ExprKind::Number(number) => Ok(Value::Number(*number as f32)),
+            ExprKind::Variable(name) => match env.get(name.as_str()) {
+                Some(value) => Ok(*value),
+                None => {
+                    let value = self.constant(name)?;
+                    if node.ty == Type::Bool {
+                        Ok(Value::Bool(value != 0.0))
+                    } else {
+                        Ok(Value::Number(value))
+                    }
+                }
+            },
+            ExprKind::BinaryExpr { op, lhs, rhs } if op.is_short_circuit() => {
+                let lhs = self.eval(lhs, env)?.as_bool();
+                if lhs == (*op == Operator::OrElse) {
+                    Ok(Value::Bool(lhs))
+                } else {
+                    self.eval(rhs, env)
+                }
+            }
+            ExprKind::BinaryExpr { op, lhs, rhs } => {
+                let lhs = self.eval(lhs, env)?;
+                let rhs = self.eval(rhs, env)?;
+                Ok(eval_binary_op(*op, lhs, rhs))
+            }
+            ExprKind::UnaryExpr { op, rhs } => {
+                let value = self.eval(rhs, env)?;
+                Ok(match op {
+                    Operator::Not => Value::Bool(!value.as_bool()),
+                    _ => Value::Number(-value.as_number()),
+                })
+            }
+            ExprKind::CallExpr { callee, args } => {
+                let mut values = vec![];
+                for arg in args {
+                    values.push(self.eval(arg, env)?);
+                }
+                self.call_function(callee, values)
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if self.eval(cond, env)?.as_bool() {
+                    self.eval(then_branch, env)
+                } else {
+                    self.eval(else_branch, env)
+                }
+            }
+            ExprKind::LetExpr {
+                name, value, body, ..
+            } => {
+                let value = self.eval(value, env)?;
+                let mut env = env.clone();
+                env.insert(name.as_str(), value);
+                self.eval(body, &env)
+            }
+        }
+    }
+}
+
+/// Computes every `const` of a checked program and stores its value, which
+/// code generation needs. Constants can call definitions but not externs,
+/// whose implementation is only known at run time.
+pub fn evaluate_constants(asts: &mut [Ast]) -> ErrorLogger {
+    let mut err_logger = ErrorLogger::new();
+
+    let program: &[Ast] = asts;
+    let results: Vec<Result<f32, RuntimeError>> = std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(CONST_STACK_SIZE)
+            .spawn_scoped(scope, || {
+                let mut interpreter = Interpreter::new(program)
+                    .with_max_depth(CONST_MAX_DEPTH)
+                    .with_max_tail_calls(CONST_MAX_TAIL_CALLS);
+                program
+                    .iter()
+                    .filter_map(Ast::as_const)
+                    .map(|constant| interpreter.constant(constant.get_name()))
+                    .collect()
+            })
+            .expect("failed to spawn a thread for constants")
+            .join()
+            .expect("constant evaluation panicked")
+    });
+
+    let mut values = vec![];
+    for (constant, result) in program.iter().filter_map(Ast::as_const).zip(results) {
+        match result {
+            Ok(value) => values.push(Some(value)),
+            Err(err) => {
+                let mut diagnostic = Diagnostic::error(&format!(
+                    "Cannot compute constant '{}': {}",
+                    constant.get_name(),
+                    err
+                ))
+                .with_code("E0112")
+                .with_span(constant.get_expr().span)
+                .with_label("evaluated at compile time");
+                if let RuntimeError::MissingHostFunction(_) = err {
+                    diagnostic = diagnostic.with_help(
+                        "constants are computed when compiling, so they cannot call externs; \
+                         pass the value as a parameter instead",
+                    );
+                }
+                err_logger.push_diagnostic(diagnostic);
+                values.push(None);
+            }
+        }
+    }
+
+    let constants = asts.iter_mut().filter_map(|ast| match ast {
+        Ast::Const(constant) => Some(constant),
+        _ => None,
+    });
+    for (constant, value) in constants.zip(values) {
+        if let Some(value) = value {
+            constant.set_value(value);
+        }
+    }
+
+    err_logger
+}
+
+fn check_arity(name: &str, expected: usize, found: usize) -> Result<(), RuntimeError> {
+    if expected != found {
+        return Err(RuntimeError::ArityMismatch {
+            name: name.to_owned(),
+            expected,
+            found,
+        });
+    }
+    Ok(())
+}
+
+/// Applies a binary operator that does not short-circuit.
+pub fn eval_binary_op(op: Operator, lhs: Value, rhs: Value) -> Value {
+    match (op, lhs, rhs) {
+        (Operator::Equal, Value::Bool(a), Value::Bool(b)) => Value::Bool(a == b),
+        (Operator::NotEq, Value::Bool(a), Value::Bool(b)) => Value::Bool(a != b),
+        (Operator::Or, a, b) => Value::Bool(a.as_bool() | b.as_bool()),
+        (Operator::And, a, b) => Value::Bool(a.as_bool() & b.as_bool()),
+        (op, a, b) => {
+            let (a, b) = (a.as_number(), b.as_number());
+            match op {
+                Operator::Plus => Value::Number(a + b),
+                Operator::Minus => Value::Number(a - b),
+                Operator::Mul => Value::Number(a * b),
+                Operator::Div => Value::Number(a / b),
+                Operator::Mod => Value::Number(floored_mod(a, b)),
+                Operator::Pow => Value::Number(pow(a, b)),
+                Operator::Less => Value::Bool(a < b),
+                Operator::Greater => Value::Bool(a > b),
+                Operator::Equal => Value::Bool(a == b),
+                Operator::NotEq => Value::Bool(a != b),
+                Operator::LessEq => Value::Bool(a <= b),
+                Operator::GreaterEq => Value::Bool(a >= b),
+                Operator::Neg
+                | Operator::Not
+                | Operator::Or
+                | Operator::And
+                | Operator::AndAlso
+                | Operator::OrElse => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Computes `a % b` like the `runtime::MOD` helper of generated modules.
+fn floored_mod(a: f32, b: f32) -> f32 {
+    a - (a / b).floor() * b
+}
+
+/// Computes `x ^ n` like the `runtime::POW` helper of generated modules, so
+/// both round the same way.
+fn pow(x: f32, n: f32) -> f32 {
+    if !n.is_finite() {
+        f32::NAN
+    } else if n < 0.0 {
+        1.0 / pow(x, -n)
+    } else if n < 1.0 {
+        1.0
+    } else {
+        let half = (n / 2.0).floor();
+        let h = pow(x, half);
+        let odd = n.trunc() - half * 2.0 != 0.0;
+        h * h * if odd { x } else { 1.0 }
+    }
+}
+
+/// Computes the builtin function implemented by `instr`.
+pub fn eval_builtin(instr: &Instruction, x: f32) -> f32 {
+    match instr {
+        Instruction::F32Sqrt => x.sqrt(),
+        Instruction::F32Ceil => x.ceil(),
+        Instruction::F32Floor => x.floor(),
+        Instruction::F32Trunc => x.trunc(),
+        Instruction::F32Nearest => x.round_ties_even(),
+        Instruction::F32Abs => x.abs(),
+        Instruction::F32Neg => -x,
+        _ => unreachable!("{:?} is not a builtin function", instr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::type_checker::TypeChecker;
+    use std::io::Cursor;
+
+    fn parse(src: &str) -> Parser<Cursor<&str>> {
+        let mut parser = Parser::new(Cursor::new(src));
+        parser.main_loop();
+        assert!(!parser.get_error_logger().has_errors());
+        assert!(!TypeChecker::new().run(parser.get_asts_mut()).has_errors());
+        parser
+    }
+
+    #[test]
+    fn it_evaluates_recursion() {
+        let parser = parse("def fib(x) if (x == 1) | (x == 2) then 1 else fib(x-1) + fib(x-2);");
+        let mut interpreter = Interpreter::new(parser.get_asts());
+
+        assert_eq!(interpreter.call("fib", &[10.0]), Ok(55.0));
+    }
+
+    #[test]
+    fn it_evaluates_builtins_and_nested_calls() {
+        let parser = parse(
+            "def discr(a b c) b*b - 4*a*c;
+             def root1(a b c)
+               if discr(a, b, c) < 0
+               then 0
+               else (-b + sqrt(discr(a, b, c)))/(2*a);
+             def round(x) nearest(x) + floor(-x) + abs(neg(x));",
+        );
+        let mut interpreter = Interpreter::new(parser.get_asts());
+
+        assert_eq!(interpreter.call("root1", &[1.0, -3.0, 2.0]), Ok(2.0));
+        assert_eq!(interpreter.call("root1", &[1.0, 0.0, 1.0]), Ok(0.0));
+        assert_eq!(interpreter.call("round", &[2.5]), Ok(2.0 - 3.0 + 2.5));
+        assert_eq!(interpreter.call("sqrt", &[9.0]), Ok(3.0));
+    }
+
+    #[test]
+    fn it_calls_host_functions() {
+        let parser = parse("extern hypot(a b) as \"hyp\"; extern random(); def f(x) hypot(x, 4) + 1; def g() random();");
+        let mut interpreter = Interpreter::new(parser.get_asts())
+            .with_host_function("hypot", |args| args[0].hypot(args[1]));
+
+        assert_eq!(interpreter.call("f", &[3.0]), Ok(6.0));
+        assert_eq!(
+            interpreter.call("g", &[]),
+            Err(RuntimeError::MissingHostFunction(String::from("random")))
+        );
+    }
+
+    #[test]
+    fn it_evaluates_constants() {
+        let mut parser = parse(
+            "const tau = 2 * pi; const pi = 3.5; const big = sq(tau) > 10;
+             extern random(); const noise = random();
+             def sq(x) x * x; def f(x) x + tau + (if big then 1 else 0);",
+        );
+        let mut interpreter = Interpreter::new(parser.get_asts());
+        assert_eq!(interpreter.call("f", &[1.0]), Ok(9.0));
+
+        let errors = evaluate_constants(parser.get_asts_mut());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "Cannot compute constant 'noise': No host function provided for extern 'random'"
+        );
+        assert!(errors[0].help.is_some());
+
+        let values: Vec<_> = parser
+            .get_asts()
+            .iter()
+            .filter_map(Ast::as_const)
+            .map(Const::get_value)
+            .collect();
+        assert_eq!(values, vec![Some(7.0), Some(3.5), Some(1.0), None]);
+    }
+
+    #[test]
+    fn it_converts_bool_results() {
+        let parser = parse("def lt(a b) a < b; def same(a b) (a < 1) == (b < 1);");
+        let mut interpreter = Interpreter::new(parser.get_asts());
+
+        assert_eq!(interpreter.call("lt", &[1.0, 2.0]), Ok(1.0));
+        assert_eq!(interpreter.call("same", &[0.0, 2.0]), Ok(0.0));
+    }
+
+    #[test]
+    fn it_evaluates_let_bindings() {
+        let parser = parse(
+            "def f(x) let x = x + 1 in let y = x * 2 in y + x;
+             def g(x) let c = x < 0 in if c then 0 - x else x;",
+        );
+        let mut interpreter = Interpreter::new(parser.get_asts());
+
+        assert_eq!(interpreter.call("f", &[1.0]), Ok(6.0));
+        assert_eq!(interpreter.call("g", &[-3.0]), Ok(3.0));
+    }
+
+    #[test]
+    fn it_evaluates_nested_ifs() {
+        let parser = parse(
+            "def sign(x) if x < 0 then 0 - 1 else if x == 0 then 0 else 1;
+             def f(x) 10 * (1 + if x < 0 then 0 else x) + sign(if x > 5 then x else 0 - x);",
+        );
+        let mut interpreter = Interpreter::new(parser.get_asts());
+
+        assert_eq!(interpreter.call("sign", &[-2.0]), Ok(-1.0));
+        assert_eq!(interpreter.call("sign", &[0.0]), Ok(0.0));
+        assert_eq!(interpreter.call("f", &[-2.0]), Ok(11.0));
+        assert_eq!(interpreter.call("f", &[2.0]), Ok(29.0));
+        assert_eq!(interpreter.call("f", &[7.0]), Ok(81.0));
+    }
+
+    #[test]
+    fn it_short_circuits_logic_operators() {
+        let parser = parse(
+            "def forever(x) 1 + forever(x);
+             def f(x) x > 0 or forever(x) > 0;
+             def g(x) x > 0 and forever(x) > 0;
+             def h(x) not (x <= 1) and x >= 0 or x != 2;",
+        );
+        let mut interpreter = Interpreter::new(parser.get_asts()).with_max_depth(100);
+
+        assert_eq!(interpreter.call("f", &[1.0]), Ok(1.0));
+        assert_eq!(interpreter.call("g", &[-1.0]), Ok(0.0));
+        assert_eq!(
+            interpreter.call("f", &[-1.0]),
+            Err(RuntimeError::StackOverflow(100))
+        );
+        assert_eq!(interpreter.call("h", &[2.0]), Ok(1.0));
+        assert_eq!(interpreter.call("h", &[1.0]), Ok(1.0));
+    }
+
+    #[test]
+    fn it_evaluates_modulo_and_power() {
+        let parser = parse("def mod(a b) a % b; def pow(x n) x ^ n; def f(x) -x ^ 2 + 2 ^ 3 ^ 2;");
+        let mut interpreter = Interpreter::new(parser.get_asts());
+
+        assert_eq!(interpreter.call("mod", &[7.0, 3.0]), Ok(1.0));
+        assert_eq!(interpreter.call("mod", &[-7.0, 3.0]), Ok(2.0));
+        assert_eq!(interpreter.call("mod", &[7.0, -3.0]), Ok(-2.0));
+        assert_eq!(interpreter.call("mod", &[5.5, 2.0]), Ok(1.5));
+        assert_eq!(interpreter.call("pow", &[2.0, 10.0]), Ok(1024.0));
+        assert_eq!(interpreter.call("pow", &[-2.0, 3.0]), Ok(-8.0));
+        assert_eq!(interpreter.call("pow", &[2.0, -2.0]), Ok(0.25));
+        assert_eq!(interpreter.call("pow", &[2.0, 3.9]), Ok(8.0));
+        assert_eq!(interpreter.call("pow", &[2.0, 0.5]), Ok(1.0));
+        assert!(interpreter
+            .call("pow", &[2.0, f32::INFINITY])
+            .unwrap()
+            .is_nan());
+        assert_eq!(interpreter.call("f", &[3.0]), Ok(-9.0 + 512.0));
+    }
+
+    #[test]
+    fn it_limits_recursion_depth() {
+        let parser = parse("def sum(x) if x == 1 then 1 else sum(x-1) + x;");
+        let mut interpreter = Interpreter::new(parser.get_asts()).with_max_depth(100);
+
+        assert_eq!(interpreter.call("sum", &[100.0]), Ok(5050.0));
+        assert_eq!(
+            interpreter.call("sum", &[101.0]),
+            Err(RuntimeError::StackOverflow(100))
+        );
+    }
+
+    #[test]
+    fn it_runs_tail_calls_in_constant_depth() {
+        let parser = parse(
+            "def sum(n acc) if n == 0 then acc else sum(n - 1, acc + n);
+             def even(n) if n == 0 then 1 else let m = n - 1 in odd(m);
+             def odd(n) if n == 0 then 0 else even(n - 1);
+             def forever(x) forever(x);",
+        );
+        let mut interpreter = Interpreter::new(parser.get_asts());
+        let expected = (1..=1_000_000).rev().fold(0.0f32, |acc, n| acc + n as f32);
+
+        assert_eq!(interpreter.call("sum", &[1e6, 0.0]), Ok(expected));
+        assert_eq!(interpreter.call("even", &[100_001.0]), Ok(0.0));
+
+        let mut interpreter = interpreter.with_max_tail_calls(1000);
+        assert_eq!(
+            interpreter.call("forever", &[1.0]),
+            Err(RuntimeError::TooManyTailCalls(1000))
+        );
+        assert_eq!(interpreter.call("sum", &[1000.0, 0.0]), Ok(500_500.0));
+    }
+
+    #[test]
+    fn it_reports_bad_calls() {
+        let parser = parse("def f(x) x;");
+        let mut interpreter = Interpreter::new(parser.get_asts());
+
+        assert_eq!(
+            interpreter.call("g", &[]),
+            Err(RuntimeError::UnknownFunction(String::from("g")))
+        );
+        assert_eq!(
+            interpreter.call("f", &[1.0, 2.0]),
+            Err(RuntimeError::ArityMismatch {
+                name: String::from("f"),
+                expected: 1,
+                found: 2
+            })
+        );
+    }
+}
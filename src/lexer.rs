@@ -1,4 +1,5 @@
 use crate::char::Char;
+use crate::span::{FileId, Span, MAIN_FILE};
 use crate::token::Token;
 use utf8_read::Char as Utf8Char;
 use utf8_read::Reader;
@@ -9,8 +10,22 @@ where
 {
     reader: Reader<T>,
     pub lexeme: String,
+    pub span: Span,
+    /// Comments skipped so far, including the leading `#`.
+    pub comments: Vec<(Span, String)>,
+    /// File the spans point into.
+    pub file: FileId,
     last_char: Char,
+    /// Character after `last_char`, once `peek_char` has read it.
+    peeked: Option<Utf8Char>,
+    // Position of `last_char`.
+    offset: usize,
     line: usize,
+    column: usize,
+    // Position of the character the reader returns next.
+    next_offset: usize,
+    next_line: usize,
+    next_column: usize,
 }
 
 impl<T> Lexer<T>
@@ -21,25 +36,37 @@ where
         let reader = Reader::new(src);
         let lexeme = String::new();
         let last_char = Char::new(Utf8Char::Char(' '));
-        let line = 1;
 
         Self {
             reader,
             lexeme,
+            span: Span::default(),
+            comments: vec![],
+            file: MAIN_FILE,
             last_char,
-            line,
+            peeked: None,
+            offset: 0,
+            line: 1,
+            column: 1,
+            next_offset: 0,
+            next_line: 1,
+            next_column: 1,
         }
     }
 
-    pub fn get_line_numer(&self) -> usize {
-        self.line
-    }
-
     pub fn get_token(&mut self) -> Token {
         self.lexeme.clear();
-        self.skip_whitespace();
+        self.skip_whitespace_and_comments();
+
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let token = self.scan_token();
+        self.span = Span::new(start, self.offset, line, column).in_file(self.file);
+
+        token
+    }
 
-        // identifier: [a-zA-Z][a-zA-Z0-9]*
+    fn scan_token(&mut self) -> Token {
+        // identifier: [a-zA-Z][a-zA-Z0-9]*(\.[a-zA-Z][a-zA-Z0-9]*)*
         if self.last_char.is_alphabetic() {
             self.get_identifier();
 
@@ -48,21 +75,26 @@ where
                 "if" => Token::If,
                 "else" => Token::Else,
                 "then" => Token::Then,
+                "let" => Token::Let,
+                "in" => Token::In,
+                "and" => Token::AndAlso,
+                "or" => Token::OrElse,
+                "not" => Token::Not,
+                "extern" => Token::Extern,
+                "const" => Token::Const,
+                "import" => Token::Import,
                 _ => Token::Identifier,
             };
         }
 
-        // Number: [0-9]?(.?[0-9])
-        if self.last_char.is_digit() {
+        // Numbers are validated by `parse_number`.
+        if self.last_char.is_digit() || self.last_char == '.' {
             self.get_number();
             return Token::Number;
         }
 
-        if self.last_char == '#' {
-            self.skip_comment();
-            if !self.last_char.is_eof() {
-                return self.get_token();
-            }
+        if self.last_char == '"' {
+            return self.get_string();
         }
 
         if self.last_char.is_eof() {
@@ -73,57 +105,133 @@ where
     }
 
     pub fn get_char(&mut self) {
+        self.offset = self.next_offset;
+        self.line = self.next_line;
+        self.column = self.next_column;
+
+        let utf8ch = match self.peeked.take() {
+            Some(utf8ch) => utf8ch,
+            None => self.read_char(),
+        };
+        self.last_char = Char::new(utf8ch);
+
+        if self.last_char.is_eof() {
+            return;
+        }
+
+        self.next_offset += self.last_char.as_char().len_utf8();
+        if self.last_char == '\n' {
+            self.next_line += 1;
+            self.next_column = 1;
+        } else {
+            self.next_column += 1;
+        }
+    }
+
+    /// The character after `last_char`, without moving past it.
+    fn peek_char(&mut self) -> Char {
+        let utf8ch = match self.peeked {
+            Some(utf8ch) => utf8ch,
+            None => {
+                let utf8ch = self.read_char();
+                self.peeked = Some(utf8ch);
+                utf8ch
+            }
+        };
+        Char::new(utf8ch)
+    }
+
+    fn read_char(&mut self) -> Utf8Char {
         match self.reader.next_char() {
-            Ok(utf8ch) => self.last_char = Char::new(utf8ch),
+            Ok(utf8ch) => utf8ch,
             Err(e) => panic!("{}", e),
         }
     }
 
-    fn skip_whitespace(&mut self) {
-        while self.last_char.is_whitespace() {
-            if self.last_char.is_newline() {
-                self.line += 1;
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            while self.last_char.is_whitespace() {
+                self.get_char();
             }
 
-            self.get_char();
+            if self.last_char != '#' {
+                break;
+            }
+            self.skip_comment();
         }
     }
 
+    /// Reads an identifier, which can be qualified by the namespace of an
+    /// import: `geometry.area`. A dot only continues it when a letter
+    /// follows.
     fn get_identifier(&mut self) {
         loop {
             self.lexeme.push(self.last_char.as_char());
             self.get_char();
-            if !self.last_char.is_alphanumeric() {
+            let is_qualified = self.last_char == '.' && self.peek_char().is_alphabetic();
+            if !self.last_char.is_alphanumeric() && !is_qualified {
                 break;
             }
         }
     }
 
+    /// Reads everything that could belong to a number literal, so that a
+    /// malformed literal like `0x1g` is reported as a whole instead of being
+    /// split into several tokens.
     fn get_number(&mut self) {
-        self.get_digits();
+        loop {
+            self.lexeme.push(self.last_char.as_char());
+            self.get_char();
 
-        if self.last_char == '.' {
-            self.get_digits();
+            let is_radix =
+                self.lexeme.len() > 1 && matches!(&self.lexeme[..2], "0x" | "0X" | "0b" | "0B");
+            let is_exponent_sign = (self.last_char == '+' || self.last_char == '-')
+                && !is_radix
+                && self.lexeme.ends_with(['e', 'E']);
+
+            if !(self.last_char.is_alphanumeric()
+                || self.last_char == '_'
+                || self.last_char == '.'
+                || is_exponent_sign)
+            {
+                break;
+            }
         }
     }
 
-    fn get_digits(&mut self) {
+    /// Reads a string literal into `lexeme`, without the quotes. Strings have
+    /// no escapes and end on the line they start.
+    fn get_string(&mut self) -> Token {
         loop {
-            self.lexeme.push(self.last_char.as_char());
             self.get_char();
-            if !self.last_char.is_digit() {
-                break;
+            if self.last_char == '"' {
+                self.get_char();
+                return Token::String;
+            }
+            if self.last_char.is_eof() || self.last_char.is_newline() {
+                return Token::UnterminatedString;
             }
+            self.lexeme.push(self.last_char.as_char());
         }
     }
 
     fn skip_comment(&mut self) {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let mut text = String::new();
+
         loop {
+            text.push(self.last_char.as_char());
             self.get_char();
-            if self.last_char.is_eof() || self.last_char == '\r' || self.last_char == '\n' {
+            if self.last_char.is_eof() || self.last_char.is_newline() {
                 break;
             }
         }
+
+        let text = text.trim_end().to_owned();
+        self.comments.push((
+            Span::new(start, self.offset, line, column).in_file(self.file),
+            text,
+        ));
     }
 
     fn other(&mut self) -> Token {
@@ -132,17 +240,47 @@ where
         let token = match self.last_char.as_char() {
             '(' => Token::Lpar,
             ')' => Token::Rpar,
-            '>' => Token::Greater,
+            '>' => {
+                self.get_char();
+                if self.last_char == '=' {
+                    Token::GreaterEq
+                } else {
+                    need_next_char = false;
+                    Token::Greater
+                }
+            }
             '<' => {
                 self.get_char();
                 if self.last_char == '>' {
                     Token::NotEq
+                } else if self.last_char == '=' {
+                    Token::LessEq
                 } else {
                     need_next_char = false;
                     Token::Less
                 }
             }
-            '*' => Token::Star,
+            '!' => {
+                self.get_char();
+                if self.last_char == '=' {
+                    Token::NotEq
+                } else {
+                    need_next_char = false;
+                    Token::Not
+                }
+            }
+            '*' => {
+                self.get_char();
+                if self.last_char == '*' {
+                    Token::Caret
+                } else {
+                    need_next_char = false;
+                    Token::Star
+                }
+            }
+            '%' => Token::Percent,
+            '@' => Token::At,
+            '^' => Token::Caret,
             '-' => Token::Minus,
             '/' => Token::Slash,
             ';' => Token::Semicolon,
@@ -156,7 +294,7 @@ where
                     Token::Equal
                 } else {
                     need_next_char = false;
-                    Token::None
+                    Token::Assign
                 }
             }
             _ => Token::InvalidChar,
@@ -169,6 +307,89 @@ where
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum NumberError {
+    Malformed,
+    OutOfRange,
+}
+
+/// Parses a number literal as read by the lexer:
+///
+/// - decimal with an optional fraction and exponent: `12`, `1.5`, `.5`,
+///   `1.`, `1e-6`, `2.5E+3`
+/// - hexadecimal and binary integers: `0x1F`, `0b1010`
+/// - `_` separating digits anywhere: `1_000_000`, `0xFF_FF`
+///
+/// Literals whose value cannot be represented by a non-zero finite `f32`
+/// are out of range.
+pub fn parse_number(literal: &str) -> Result<f64, NumberError> {
+    let (radix, digits) = match literal.get(..2) {
+        Some("0x" | "0X") => (16, &literal[2..]),
+        Some("0b" | "0B") => (2, &literal[2..]),
+        _ => (10, literal),
+    };
+
+    // Each `_` must sit between two digits.
+    let chars: Vec<char> = digits.chars().collect();
+    for (index, ch) in chars.iter().enumerate() {
+        if *ch == '_' {
+            let before = index.checked_sub(1).map(|i| chars[i]);
+            let after = chars.get(index + 1);
+            if !before.is_some_and(|c| c.is_digit(radix))
+                || !after.is_some_and(|c| c.is_digit(radix))
+            {
+                return Err(NumberError::Malformed);
+            }
+        }
+    }
+    let digits: String = digits.chars().filter(|ch| *ch != '_').collect();
+
+    let value = if radix == 10 {
+        if !is_decimal(&digits) {
+            return Err(NumberError::Malformed);
+        }
+        digits.parse::<f64>().map_err(|_| NumberError::Malformed)?
+    } else {
+        if digits.is_empty() || !digits.chars().all(|ch| ch.is_digit(radix)) {
+            return Err(NumberError::Malformed);
+        }
+        u128::from_str_radix(&digits, radix).map_err(|_| NumberError::OutOfRange)? as f64
+    };
+
+    let single = value as f32;
+    if single.is_infinite() || (single == 0.0 && value != 0.0) {
+        return Err(NumberError::OutOfRange);
+    }
+    Ok(value)
+}
+
+/// `digits [. digits] [e [+-] digits]` where the mantissa has at least one
+/// digit on either side of the point.
+fn is_decimal(text: &str) -> bool {
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(index) => (&text[..index], Some(&text[index + 1..])),
+        None => (text, None),
+    };
+
+    let (int, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let all_digits = |part: &str| part.chars().all(|ch| ch.is_ascii_digit());
+    let mantissa_ok = all_digits(int) && all_digits(fraction) && int.len() + fraction.len() > 0;
+
+    let exponent_ok = exponent.is_none_or(|exponent| {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        !exponent.is_empty() && all_digits(exponent)
+    });
+
+    mantissa_ok && exponent_ok
+}
+
+/// `name_n`, for names the compiler makes up. Identifiers cannot contain
+/// `_`, so names containing one, like these and the `__` runtime helpers,
+/// never clash with the names of the program.
+pub fn numbered_name(name: &str, n: usize) -> String {
+    format!("{}_{}", name, n)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -253,6 +474,95 @@ mod tests {
         assert_eq!(lexer.lexeme, "1234.");
     }
 
+    #[test]
+    fn it_reads_whole_number_literals() {
+        let mut lexer = lexer_with_source(".5 1_000 2.5e-3+1 0xFF-1 0x1g");
+
+        for lexeme in [".5", "1_000", "2.5e-3", "+", "1", "0xFF", "-", "1", "0x1g"] {
+            let token = lexer.get_token();
+            if token == Token::Number {
+                assert_eq!(lexer.lexeme, lexeme);
+            } else {
+                assert_eq!(token.describe(), format!("`{}`", lexeme));
+            }
+        }
+    }
+
+    #[test]
+    fn it_parses_number_literals() {
+        assert_eq!(parse_number("12"), Ok(12.0));
+        assert_eq!(parse_number("1."), Ok(1.0));
+        assert_eq!(parse_number(".5"), Ok(0.5));
+        assert_eq!(parse_number("2.5E+3"), Ok(2500.0));
+        assert_eq!(parse_number("1e-6"), Ok(1e-6));
+        assert_eq!(parse_number("1_000_000"), Ok(1_000_000.0));
+        assert_eq!(parse_number("0xFF_FF"), Ok(65535.0));
+        assert_eq!(parse_number("0b1010"), Ok(10.0));
+    }
+
+    #[test]
+    fn it_rejects_malformed_number_literals() {
+        for literal in [
+            ".", "1.2.3", "1e", "1e+", "1_", "1__0", "_1", "1._5", "0x", "0x1g", "0b102", "12abc",
+        ] {
+            assert_eq!(
+                parse_number(literal),
+                Err(NumberError::Malformed),
+                "{}",
+                literal
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_number_literals_out_of_range() {
+        for literal in [
+            "1e39",
+            "1e-50",
+            "0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_F",
+        ] {
+            assert_eq!(
+                parse_number(literal),
+                Err(NumberError::OutOfRange),
+                "{}",
+                literal
+            );
+        }
+        assert_eq!(parse_number("0e-50"), Ok(0.0));
+    }
+
+    #[test]
+    fn it_reads_qualified_identifiers() {
+        let mut lexer = lexer_with_source("geometry.area(r) a.b2.c x.5 y. z..w");
+
+        assert_eq!(lexer.get_token(), Token::Identifier);
+        assert_eq!(lexer.lexeme, "geometry.area");
+        assert_eq!(lexer.get_token(), Token::Lpar);
+        lexer.get_token();
+        lexer.get_token();
+        assert_eq!(lexer.get_token(), Token::Identifier);
+        assert_eq!(lexer.lexeme, "a.b2.c");
+
+        let mut lexemes = vec![];
+        while lexer.get_token() != Token::Eof {
+            lexemes.push(lexer.lexeme.clone());
+        }
+        assert_eq!(lexemes, ["x", ".5", "y", ".", "z", "..w"]);
+    }
+
+    #[test]
+    fn it_parses_strings() {
+        let mut lexer = lexer_with_source(" \"console\" \"no end\n");
+
+        assert_eq!(lexer.get_token(), Token::String);
+        assert_eq!(lexer.lexeme, "console");
+        assert_eq!(lexer.span, Span::new(1, 10, 1, 2));
+
+        assert_eq!(lexer.get_token(), Token::UnterminatedString);
+        assert_eq!(lexer.lexeme, "no end");
+        assert_eq!(lexer.get_token(), Token::Eof);
+    }
+
     #[test]
     fn it_skips_comment() {
         let mut lexer =
@@ -264,6 +574,97 @@ mod tests {
         assert_eq!(lexer.lexeme, "123");
     }
 
+    #[test]
+    fn it_tracks_token_spans() {
+        let mut lexer = lexer_with_source("def f(x)\n  x <> 1.5;");
+        let expected = vec![
+            (Token::Define, Span::new(0, 3, 1, 1)),
+            (Token::Identifier, Span::new(4, 5, 1, 5)),
+            (Token::Lpar, Span::new(5, 6, 1, 6)),
+            (Token::Identifier, Span::new(6, 7, 1, 7)),
+            (Token::Rpar, Span::new(7, 8, 1, 8)),
+            (Token::Identifier, Span::new(11, 12, 2, 3)),
+            (Token::NotEq, Span::new(13, 15, 2, 5)),
+            (Token::Number, Span::new(16, 19, 2, 8)),
+            (Token::Semicolon, Span::new(19, 20, 2, 11)),
+            (Token::Eof, Span::new(20, 20, 2, 12)),
+        ];
+
+        for (token, span) in expected {
+            assert_eq!(lexer.get_token(), token);
+            assert_eq!(lexer.span, span);
+        }
+    }
+
+    #[test]
+    fn it_counts_columns_in_chars_and_offsets_in_bytes() {
+        let mut lexer = lexer_with_source("# 🙂 comment\r\nfoo # 👍\n  bar");
+
+        assert_eq!(lexer.get_token(), Token::Identifier);
+        assert_eq!(lexer.span, Span::new(16, 19, 2, 1));
+        assert_eq!(lexer.get_token(), Token::Identifier);
+        assert_eq!(lexer.span, Span::new(29, 32, 3, 3));
+    }
+
+    #[test]
+    fn it_collects_comments() {
+        let mut lexer = lexer_with_source("# first \nfoo # second\n");
+
+        assert_eq!(lexer.get_token(), Token::Identifier);
+        assert_eq!(lexer.get_token(), Token::Eof);
+        assert_eq!(
+            lexer.comments,
+            vec![
+                (Span::new(0, 8, 1, 1), String::from("# first")),
+                (Span::new(13, 21, 2, 5), String::from("# second")),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_parses_comparison_and_logic_operators() {
+        let mut lexer = lexer_with_source("< <= > >= == <> != = ! not and or");
+        let tokens = [
+            Token::Less,
+            Token::LessEq,
+            Token::Greater,
+            Token::GreaterEq,
+            Token::Equal,
+            Token::NotEq,
+            Token::NotEq,
+            Token::Assign,
+            Token::Not,
+            Token::Not,
+            Token::AndAlso,
+            Token::OrElse,
+            Token::Eof,
+        ];
+
+        for token in tokens {
+            assert_eq!(lexer.get_token(), token);
+        }
+    }
+
+    #[test]
+    fn it_parses_modulo_and_power_operators() {
+        let mut lexer = lexer_with_source("a % b ^ c ** d * e");
+        let tokens = [
+            Token::Identifier,
+            Token::Percent,
+            Token::Identifier,
+            Token::Caret,
+            Token::Identifier,
+            Token::Caret,
+            Token::Identifier,
+            Token::Star,
+            Token::Identifier,
+        ];
+
+        for token in tokens {
+            assert_eq!(lexer.get_token(), token);
+        }
+    }
+
     #[test]
     fn it_returns_invalid_char() {
         let mut lexer = lexer_with_source("$123");
@@ -1,8 +1,22 @@
-mod ast;
+pub mod ast;
 mod char;
 pub mod code_generator;
+mod compiler;
+pub mod diagnostic;
 mod error_logger;
-mod lexer;
+pub mod formatter;
+pub mod import;
+pub mod interpreter;
+pub mod lexer;
 mod operator;
+pub mod optimizer;
 pub mod parser;
-mod token;
+pub mod prelude;
+pub mod repl;
+pub mod resolver;
+pub mod span;
+pub mod token;
+pub mod type_checker;
+mod types;
+
+pub use compiler::{check, check_with, compile, Artifact, CompileOptions, Program};
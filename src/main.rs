@@ -1,39 +1,488 @@
-use minilang::code_generator::CodeGenerator;
+use minilang::ast::Ast;
+use minilang::diagnostic::{Diagnostic, Renderer, SourceFile};
+use minilang::formatter::format_source;
+use minilang::interpreter::Interpreter;
+use minilang::lexer::Lexer;
+use minilang::optimizer::{OptLevel, MAX_OPT_LEVEL};
 use minilang::parser::Parser;
-use std::process::Command;
+use minilang::repl::Repl;
+use minilang::span::MAIN_FILE;
+use minilang::token::Token;
+use minilang::{check_with, compile, CompileOptions};
 
 use std::env;
-use std::fs::File;
+use std::fs;
+use std::io::{IsTerminal, Read, Write};
+use std::path::Path;
+use std::process;
+use std::thread;
 
-fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        println!("Not enough arguments. Please specify input and output files names.");
-        return Ok(());
+const INTERPRETER_STACK_SIZE: usize = 512 * 1024 * 1024;
+
+const STDIN_NAME: &str = "<stdin>";
+
+const USAGE: &str = "\
+Usage: minilang <command> [options]
+
+Commands:
+  build <source> [--emit wat,wasm] [-o <output>] [-O <level>] [--tail-calls]
+                        compile to WebAssembly text and/or binary,
+                        optimized at level 0 (default) to 2, making
+                        tail calls with return_call if asked
+  check <source>        report diagnostics without generating code
+  run <source> <function> [args...]
+                        evaluate a function with the interpreter
+  fmt <source> [--write | --check]
+                        print the source in canonical format
+  tokens <source>       print the tokens produced by the lexer
+  ast <source>          print the syntax tree
+  repl                  start an interactive session
+  help                  show this message
+
+Use `-` as <source> to read from standard input.
+Exit status: 0 on success, 1 on errors in the program, 2 on invalid usage.
+";
+
+/// Exit status of a command.
+type Status = i32;
+
+const SUCCESS: Status = 0;
+const FAILURE: Status = 1;
+const USAGE_ERROR: Status = 2;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let rest = args.get(1..).unwrap_or_default();
+
+    let status = match args.first().map(String::as_str) {
+        Some("build") => build(rest),
+        Some("check") => check_command(rest),
+        Some("run") => run(rest),
+        Some("fmt") => fmt(rest),
+        Some("tokens") => tokens(rest),
+        Some("ast") => ast(rest),
+        Some("repl") => repl(rest),
+        Some("help" | "-h" | "--help") => {
+            print!("{}", USAGE);
+            Ok(SUCCESS)
+        }
+        Some(command) => Ok(usage_error(&format!("unknown command '{}'", command))),
+        None => Ok(usage_error("missing command")),
+    };
+
+    match status {
+        Ok(status) => process::exit(status),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(FAILURE);
+        }
+    }
+}
+
+/// `build <source> [--emit wat,wasm] [-o <output>] [-O <level>] [--tail-calls]`: writes `<output>.wat`
+/// and/or `<output>.wasm`, where `<output>` defaults to the source path. With
+/// a single output kind, `-o -` (or reading from stdin without `-o`) writes it
+/// to stdout.
+fn build(args: &[String]) -> std::io::Result<Status> {
+    let args = match Args::parse(args, &["--emit", "-o", "-O"], &["--tail-calls"]) {
+        Ok(args) => args,
+        Err(msg) => return Ok(usage_error(&msg)),
+    };
+    let Some(source_path) = args.single_positional() else {
+        return Ok(usage_error("build expects exactly one source"));
+    };
+
+    let (mut emit_wat, mut emit_wasm) = (false, false);
+    for kind in args.value("--emit").unwrap_or("wat,wasm").split(',') {
+        match kind.trim() {
+            "wat" => emit_wat = true,
+            "wasm" => emit_wasm = true,
+            other => return Ok(usage_error(&format!("unknown output kind '{}'", other))),
+        }
+    }
+
+    let opt_level = match args.value("-O").map(str::parse::<OptLevel>) {
+        None => 0,
+        Some(Ok(level)) if level <= MAX_OPT_LEVEL => level,
+        Some(_) => {
+            return Ok(usage_error(&format!(
+                "optimization level must be 0 to {}",
+                MAX_OPT_LEVEL
+            )))
+        }
+    };
+
+    let output = match args.value("-o") {
+        Some(output) => output,
+        None if source_path == "-" => "-",
+        None => source_path,
+    };
+    if output == "-" && emit_wat && emit_wasm {
+        return Ok(usage_error(
+            "writing to stdout needs a single output kind, e.g. --emit wat",
+        ));
+    }
+
+    let (source, name) = read_source(source_path)?;
+    let options = source_options(source_path, &name)
+        .emit_wat(emit_wat)
+        .emit_wasm(emit_wasm)
+        .opt_level(opt_level)
+        .tail_calls(args.flag("--tail-calls"));
+    let artifact = match compile(&source, &options) {
+        Ok(artifact) => artifact,
+        Err(diagnostics) => {
+            report(diagnostics.get_files(), &diagnostics);
+            return Ok(FAILURE);
+        }
+    };
+    report(&artifact.files, &artifact.warnings);
+
+    if output == "-" {
+        let mut stdout = std::io::stdout();
+        if emit_wat {
+            stdout.write_all(artifact.wat.as_bytes())?;
+        } else {
+            stdout.write_all(&artifact.wasm)?;
+        }
+        stdout.flush()?;
+    } else {
+        let output = Path::new(output);
+        if emit_wat {
+            fs::write(output.with_extension("wat"), artifact.wat)?;
+        }
+        if emit_wasm {
+            fs::write(output.with_extension("wasm"), artifact.wasm)?;
+        }
+    }
+
+    Ok(SUCCESS)
+}
+
+/// `check <source>`: prints the diagnostics only.
+fn check_command(args: &[String]) -> std::io::Result<Status> {
+    let Some(source_path) = single_source(args) else {
+        return Ok(usage_error("check expects exactly one source"));
+    };
+
+    let (source, name) = read_source(&source_path)?;
+    match check_with(&source, &source_options(&source_path, &name)) {
+        Ok(program) => {
+            report(program.get_files(), program.get_warnings());
+            Ok(SUCCESS)
+        }
+        Err(diagnostics) => {
+            report(diagnostics.get_files(), &diagnostics);
+            Ok(FAILURE)
+        }
+    }
+}
+
+/// `run <source> <function> [args...]`: evaluates a function with the
+/// interpreter and prints its result.
+fn run(args: &[String]) -> std::io::Result<Status> {
+    if args.len() < 2 {
+        return Ok(usage_error("run expects a source and a function name"));
+    }
+
+    let mut values = vec![];
+    for arg in &args[2..] {
+        match arg.parse::<f32>() {
+            Ok(value) => values.push(value),
+            Err(_) => {
+                return Ok(usage_error(&format!(
+                    "invalid argument '{}': expected a number",
+                    arg
+                )))
+            }
+        }
+    }
+
+    let (source, name) = read_source(&args[0])?;
+    let program = match check_with(&source, &source_options(&args[0], &name)) {
+        Ok(program) => program,
+        Err(diagnostics) => {
+            report(diagnostics.get_files(), &diagnostics);
+            return Ok(FAILURE);
+        }
+    };
+    report(program.get_files(), program.get_warnings());
+
+    let result = with_large_stack(|| Interpreter::new(program.get_asts()).call(&args[1], &values))?;
+
+    match result {
+        Ok(result) => {
+            println!("{}", result);
+            Ok(SUCCESS)
+        }
+        Err(err) => {
+            eprintln!("runtime error: {}", err);
+            Ok(FAILURE)
+        }
+    }
+}
+
+/// `fmt <source> [--write | --check]`: prints the formatted source, rewrites
+/// the file in place, or fails if the file is not formatted.
+fn fmt(args: &[String]) -> std::io::Result<Status> {
+    let args = match Args::parse(args, &[], &["--write", "--check"]) {
+        Ok(args) => args,
+        Err(msg) => return Ok(usage_error(&msg)),
+    };
+    let Some(source_path) = args.single_positional() else {
+        return Ok(usage_error("fmt expects exactly one source"));
+    };
+    let (write, check) = (args.flag("--write"), args.flag("--check"));
+    if write && check {
+        return Ok(usage_error("--write and --check cannot be combined"));
+    }
+    if write && source_path == "-" {
+        return Ok(usage_error("--write needs a file"));
+    }
+
+    let (source, name) = read_source(source_path)?;
+    let formatted = match format_source(&source) {
+        Ok(formatted) => formatted,
+        Err(diagnostics) => {
+            report(&[SourceFile::new(&name, &source)], &diagnostics);
+            return Ok(FAILURE);
+        }
+    };
+
+    if check {
+        if formatted != source {
+            eprintln!("{} is not formatted", name);
+            return Ok(FAILURE);
+        }
+    } else if write {
+        if formatted != source {
+            fs::write(source_path, formatted)?;
+        }
+    } else {
+        print!("{}", formatted);
+    }
+
+    Ok(SUCCESS)
+}
+
+/// `tokens <source>`: prints one token per line with its position, kind and
+/// text.
+fn tokens(args: &[String]) -> std::io::Result<Status> {
+    let Some(source_path) = single_source(args) else {
+        return Ok(usage_error("tokens expects exactly one source"));
+    };
+
+    let (source, _) = read_source(&source_path)?;
+    let mut lexer = Lexer::new(source.as_bytes());
+    let mut stdout = std::io::stdout().lock();
+
+    loop {
+        let token = lexer.get_token();
+        let span = lexer.span;
+        let text = source.get(span.start..span.end).unwrap_or("");
+        writeln!(
+            stdout,
+            "{}:{}\t{:?}\t{}",
+            span.line, span.column, token, text
+        )?;
+
+        if token == Token::Eof {
+            break;
+        }
     }
 
-    let src = File::open(args[1].as_str())?;
-    let target = File::create(args[2].as_str())?;
+    Ok(SUCCESS)
+}
 
-    let mut parser = Parser::new(src);
+/// `ast <source>`: prints the syntax tree of every definition. Only the parser
+/// runs, so the program does not need to be valid beyond its syntax.
+fn ast(args: &[String]) -> std::io::Result<Status> {
+    let Some(source_path) = single_source(args) else {
+        return Ok(usage_error("ast expects exactly one source"));
+    };
+
+    let (source, name) = read_source(&source_path)?;
+    let mut parser = Parser::new(source.as_bytes());
     parser.main_loop();
+    if report(
+        &[SourceFile::new(&name, &source)],
+        parser.get_error_logger(),
+    ) {
+        return Ok(FAILURE);
+    }
+
+    for ast in parser.get_asts() {
+        match ast {
+            Ast::Definition(func) => print!("{}", func.dump()),
+            Ast::Extern(ext) => {
+                let span = ext.get_span();
+                println!("Extern {} @{}:{}", ext.get_proto(), span.line, span.column);
+            }
+            Ast::Const(constant) => {
+                let span = constant.get_span();
+                let mut out = format!(
+                    "Const {} @{}:{}\n",
+                    constant.get_name(),
+                    span.line,
+                    span.column
+                );
+                constant.get_expr().dump(1, &mut out);
+                print!("{}", out);
+            }
+            Ast::Import(import) => {
+                let span = import.span;
+                println!(
+                    "Import \"{}\" as {} @{}:{}",
+                    import.path,
+                    import.get_namespace(),
+                    span.line,
+                    span.column
+                );
+            }
+        }
+    }
+
+    Ok(SUCCESS)
+}
+
+fn repl(args: &[String]) -> std::io::Result<Status> {
+    if !args.is_empty() {
+        return Ok(usage_error("repl takes no arguments"));
+    }
+
+    let colored = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    with_large_stack(|| {
+        Repl::new()
+            .colored(colored)
+            .run(std::io::stdin().lock(), &mut std::io::stdout())
+    })??;
+
+    Ok(SUCCESS)
+}
+
+/// Options and positional arguments of a command.
+struct Args {
+    positional: Vec<String>,
+    values: Vec<(String, String)>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    /// Splits `args` into positional arguments, options taking a value
+    /// (`-o out`, `--emit=wat`) and flags. A lone `-` is positional.
+    fn parse(args: &[String], with_value: &[&str], flags: &[&str]) -> Result<Self, String> {
+        let mut parsed = Self {
+            positional: vec![],
+            values: vec![],
+            flags: vec![],
+        };
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value)),
+                _ => (arg.as_str(), None),
+            };
+
+            if with_value.contains(&name) {
+                let value = match inline_value {
+                    Some(value) => value.to_owned(),
+                    None => args
+                        .next()
+                        .ok_or_else(|| format!("option '{}' needs a value", name))?
+                        .clone(),
+                };
+                parsed.values.push((name.to_owned(), value));
+            } else if flags.contains(&name) && inline_value.is_none() {
+                parsed.flags.push(name.to_owned());
+            } else if arg.starts_with('-') && arg != "-" {
+                return Err(format!("unknown option '{}'", arg));
+            } else {
+                parsed.positional.push(arg.clone());
+            }
+        }
+
+        Ok(parsed)
+    }
 
-    let err_logger = parser.get_error_logger();
+    /// The last value given for option `name`.
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
 
-    if err_logger.has_errors() {
-        for error in err_logger.iter() {
-            println!("SYNTAX ERROR: {}", error);
+    fn single_positional(&self) -> Option<&str> {
+        match self.positional.as_slice() {
+            [arg] => Some(arg),
+            _ => None,
         }
+    }
+}
+
+/// The source path of a command that takes no options besides it.
+fn single_source(args: &[String]) -> Option<String> {
+    let args = Args::parse(args, &[], &[]).ok()?;
+    args.single_positional().map(str::to_owned)
+}
+
+/// Reads `path`, or stdin if it is `-`. Returns the source and the name used
+/// in diagnostics.
+fn read_source(path: &str) -> std::io::Result<(String, String)> {
+    if path == "-" {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        return Ok((source, STDIN_NAME.to_owned()));
+    }
+
+    fs::read_to_string(path)
+        .map(|source| (source, path.to_owned()))
+        .map_err(|err| std::io::Error::new(err.kind(), format!("cannot read '{}': {}", path, err)))
+}
+
+/// Options naming the source read by `read_source`, whose imports are
+/// relative to it unless it is stdin.
+fn source_options(path: &str, name: &str) -> CompileOptions {
+    if path == "-" {
+        CompileOptions::new().source_name(name)
     } else {
-        // Generate WebAssembly text
-        CodeGenerator::new(parser.get_asts(), target).run()?;
-        // Generate binary WebAssembly
-        Command::new("sh")
-            .arg("-c")
-            .arg(format!("wat2wasm {}", args[2].as_str()))
-            .output()
-            .expect("failed to execute 'wat2wasm'");
+        CompileOptions::new().source_path(path)
+    }
+}
+
+fn usage_error(msg: &str) -> Status {
+    eprintln!("error: {}\n", msg);
+    eprint!("{}", USAGE);
+    USAGE_ERROR
+}
+
+/// Each interpreted call nests several Rust frames, so the interpreter runs on
+/// a thread whose stack can hold its default recursion depth.
+fn with_large_stack<T: Send>(f: impl FnOnce() -> T + Send) -> std::io::Result<T> {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(INTERPRETER_STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .map(|handle| handle.join().expect("interpreter thread panicked"))
+    })
+}
+
+/// Prints the diagnostics, each with the file its span points into, and
+/// returns true if any of them is an error.
+fn report(files: &[SourceFile], diagnostics: &[Diagnostic]) -> bool {
+    let colored = std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+
+    for diagnostic in diagnostics {
+        let file = &files[diagnostic.span.map_or(MAIN_FILE, |span| span.file)];
+        let renderer = Renderer::new(&file.source, &file.name).colored(colored);
+        eprintln!("{}", renderer.render(diagnostic));
     }
 
-    Ok(())
+    diagnostics.iter().any(Diagnostic::is_error)
 }
@@ -1,52 +1,205 @@
+use crate::code_generator::instruction::Instruction;
+use crate::code_generator::runtime;
 use crate::token::Token;
+use crate::types::Type;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operator {
     Plus,
     Minus,
     Mul,
     Div,
+    /// Floored modulo, the result has the sign of the divisor.
+    Mod,
+    /// Power with the exponent truncated towards zero; literal exponents
+    /// must be integers.
+    Pow,
     Or,
     And,
     Less,
     Greater,
     Equal,
     NotEq,
+    LessEq,
+    GreaterEq,
+    /// Short-circuiting `and`.
+    AndAlso,
+    /// Short-circuiting `or`.
+    OrElse,
     Neg,
+    Not,
 }
 
 impl Operator {
-    pub fn to_wat(&self) -> String {
+    /// Instruction implementing the operator for operands of type `operand`.
+    pub fn to_instruction(self, operand: Type) -> Instruction {
         match self {
-            Self::Plus => String::from("f32.add\n"),
-            Self::Minus => String::from("f32.sub\n"),
-            Self::Mul => String::from("f32.mul\n"),
-            Self::Div => String::from("f32.div\n"),
-            Self::Or => String::from("i32.or\n"),
-            Self::And => String::from("i32.and\n"),
-            Self::Greater => String::from("f32.gt\n"),
-            Self::Less => String::from("f32.lt\n"),
-            Self::Equal => String::from("f32.eq\n"),
-            Self::NotEq => String::from("f32.ne\n"),
-            Self::Neg => String::from("f32.neg\n"),
+            Self::Equal if operand == Type::Bool => Instruction::I32Eq,
+            Self::NotEq if operand == Type::Bool => Instruction::I32Ne,
+            Self::Plus => Instruction::F32Add,
+            Self::Minus => Instruction::F32Sub,
+            Self::Mul => Instruction::F32Mul,
+            Self::Div => Instruction::F32Div,
+            Self::Mod => Instruction::Call(runtime::MOD.to_owned()),
+            Self::Pow => Instruction::Call(runtime::POW.to_owned()),
+            Self::Or => Instruction::I32Or,
+            Self::And => Instruction::I32And,
+            Self::Greater => Instruction::F32Gt,
+            Self::Less => Instruction::F32Lt,
+            Self::Equal => Instruction::F32Eq,
+            Self::NotEq => Instruction::F32Ne,
+            Self::LessEq => Instruction::F32Le,
+            Self::GreaterEq => Instruction::F32Ge,
+            Self::Neg => Instruction::F32Neg,
+            Self::Not => Instruction::I32Eqz,
+            Self::AndAlso | Self::OrElse => {
+                unreachable!("short-circuit operators are lowered to `if`")
+            }
         }
     }
 }
 
-impl From<&Token> for Operator {
-    fn from(item: &Token) -> Self {
-        match *item {
-            Token::Plus => Operator::Plus,
-            Token::Minus => Operator::Minus,
-            Token::Star => Operator::Mul,
-            Token::Slash => Operator::Div,
-            Token::Or => Operator::Or,
-            Token::And => Operator::And,
-            Token::Less => Operator::Less,
-            Token::Greater => Operator::Greater,
-            Token::Equal => Operator::Equal,
-            Token::NotEq => Operator::NotEq,
-            _ => panic!("Unexpected token {:?}", item),
+impl Operator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Plus => "+",
+            Self::Minus => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "%",
+            Self::Pow => "^",
+            Self::Or => "|",
+            Self::And => "&",
+            Self::Less => "<",
+            Self::Greater => ">",
+            Self::Equal => "==",
+            Self::NotEq => "<>",
+            Self::LessEq => "<=",
+            Self::GreaterEq => ">=",
+            Self::AndAlso => "and",
+            Self::OrElse => "or",
+            Self::Neg => "-",
+            Self::Not => "not",
+        }
+    }
+
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            Self::Plus | Self::Minus | Self::Mul | Self::Div | Self::Mod | Self::Pow | Self::Neg
+        )
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Less | Self::Greater | Self::Equal | Self::NotEq | Self::LessEq | Self::GreaterEq
+        )
+    }
+
+    pub fn is_logical(&self) -> bool {
+        matches!(
+            self,
+            Self::Or | Self::And | Self::AndAlso | Self::OrElse | Self::Not
+        )
+    }
+
+    pub fn is_short_circuit(&self) -> bool {
+        matches!(self, Self::AndAlso | Self::OrElse)
+    }
+}
+
+impl Operator {
+    /// Binding strength, higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        OperatorInfo::of(self).precedence
+    }
+
+    pub fn associativity(self) -> Associativity {
+        OperatorInfo::of(self).associativity
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Associativity {
+    Left,
+    Right,
+    /// The operator cannot be chained, e.g. `a < b < c`.
+    None,
+}
+
+/// Entry of the operator table used by the parser and the formatter.
+#[derive(Debug)]
+pub struct OperatorInfo {
+    pub token: Token,
+    pub operator: Operator,
+    pub precedence: u8,
+    pub associativity: Associativity,
+}
+
+impl OperatorInfo {
+    const fn new(
+        token: Token,
+        operator: Operator,
+        precedence: u8,
+        associativity: Associativity,
+    ) -> Self {
+        Self {
+            token,
+            operator,
+            precedence,
+            associativity,
         }
     }
+
+    /// The binary operator written as `token`, if any.
+    pub fn binary(token: &Token) -> Option<&'static OperatorInfo> {
+        BINARY_OPERATORS.iter().find(|info| info.token == *token)
+    }
+
+    /// The prefix operator written as `token`, if any.
+    pub fn prefix(token: &Token) -> Option<&'static OperatorInfo> {
+        PREFIX_OPERATORS.iter().find(|info| info.token == *token)
+    }
+
+    fn of(operator: Operator) -> &'static OperatorInfo {
+        BINARY_OPERATORS
+            .iter()
+            .chain(PREFIX_OPERATORS.iter())
+            .find(|info| info.operator == operator)
+            .expect("every operator is in the operator table")
+    }
 }
+
+/// Binary operators, loosest binding first.
+static BINARY_OPERATORS: [OperatorInfo; 16] = [
+    OperatorInfo::new(Token::OrElse, Operator::OrElse, 1, Associativity::Left),
+    OperatorInfo::new(Token::AndAlso, Operator::AndAlso, 2, Associativity::Left),
+    OperatorInfo::new(Token::Less, Operator::Less, 4, Associativity::None),
+    OperatorInfo::new(Token::Greater, Operator::Greater, 4, Associativity::None),
+    OperatorInfo::new(Token::LessEq, Operator::LessEq, 4, Associativity::None),
+    OperatorInfo::new(
+        Token::GreaterEq,
+        Operator::GreaterEq,
+        4,
+        Associativity::None,
+    ),
+    OperatorInfo::new(Token::Equal, Operator::Equal, 4, Associativity::None),
+    OperatorInfo::new(Token::NotEq, Operator::NotEq, 4, Associativity::None),
+    OperatorInfo::new(Token::Plus, Operator::Plus, 5, Associativity::Left),
+    OperatorInfo::new(Token::Minus, Operator::Minus, 5, Associativity::Left),
+    OperatorInfo::new(Token::Or, Operator::Or, 5, Associativity::Left),
+    OperatorInfo::new(Token::Star, Operator::Mul, 6, Associativity::Left),
+    OperatorInfo::new(Token::Slash, Operator::Div, 6, Associativity::Left),
+    OperatorInfo::new(Token::Percent, Operator::Mod, 6, Associativity::Left),
+    OperatorInfo::new(Token::And, Operator::And, 6, Associativity::Left),
+    OperatorInfo::new(Token::Caret, Operator::Pow, 8, Associativity::Right),
+];
+
+/// Prefix operators. The operand of a prefix operator only extends over
+/// binary operators of a higher precedence, so `not a < b` negates the
+/// comparison while `-a < b` negates `a` and `-a ^ 2` negates the power.
+static PREFIX_OPERATORS: [OperatorInfo; 2] = [
+    OperatorInfo::new(Token::Not, Operator::Not, 3, Associativity::Right),
+    OperatorInfo::new(Token::Minus, Operator::Neg, 7, Associativity::Right),
+];
@@ -0,0 +1,224 @@
+pub mod call_graph;
+pub mod cse;
+pub mod dead_code;
+pub mod fold;
+pub mod inline;
+
+use crate::ast::Ast;
+use crate::diagnostic::Diagnostic;
+use call_graph::CallGraph;
+use fold::Folder;
+use inline::{Inliner, MAX_INLINE_SIZE};
+use std::collections::HashMap;
+
+/// How hard the optimizer works, from 0, which leaves the program as written,
+/// to `MAX_OPT_LEVEL`.
+pub type OptLevel = u8;
+
+pub const MAX_OPT_LEVEL: OptLevel = 2;
+
+/// Rewrites the definitions of a checked program, whose constants are
+/// computed, into equivalent ones that run faster, returning warnings.
+///
+/// Level 1 inlines the definitions marked `@inline`, folds constant
+/// subexpressions and simplifies identities. Level 2 also inlines small
+/// definitions not marked `@noinline`. Recursive definitions are never
+/// inlined. Code generation turns self tail calls into loops from level 1
+/// on and shares common subexpressions from level 2 on, see `Cse`.
+pub fn optimize(asts: &mut [Ast], level: OptLevel) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+    if level == 0 {
+        return warnings;
+    }
+
+    let constants: HashMap<String, f32> = asts
+        .iter()
+        .filter_map(Ast::as_const)
+        .filter_map(|constant| Some((constant.get_name().to_owned(), constant.get_value()?)))
+        .collect();
+    let definitions: HashMap<String, usize> = asts
+        .iter()
+        .enumerate()
+        .filter_map(|(index, ast)| {
+            Some((ast.as_definition()?.get_function_name().to_owned(), index))
+        })
+        .collect();
+
+    // Callees come first, so the bodies inlined are optimized already.
+    let graph = CallGraph::new(asts);
+    let mut inliner = Inliner::new();
+    for name in graph.get_order() {
+        let Ast::Definition(func) = &mut asts[definitions[name]] else {
+            unreachable!("the call graph only has definitions");
+        };
+        inliner.inline(func);
+        let params: Vec<String> = func
+            .get_params()
+            .iter()
+            .map(|param| param.name.clone())
+            .collect();
+        Folder::new(&constants, &params).fold(func.get_body_mut());
+
+        let inline = func.get_attribute("inline");
+        if graph.is_recursive(name) {
+            if let Some(attr) = inline {
+                warnings.push(
+                    Diagnostic::warning(&format!(
+                        "Function '{}' is recursive and cannot be inlined",
+                        name
+                    ))
+                    .with_span(attr.span)
+                    .with_label("ignored"),
+                );
+            }
+        } else if func.get_attribute("noinline").is_none()
+            && (inline.is_some() || level >= 2 && inline::size(func.get_body()) <= MAX_INLINE_SIZE)
+        {
+            inliner.add(func);
+        }
+    }
+
+    warnings
+}
+
+/// WAT of the function `name` of `source` compiled at `level`, for the
+/// optimizer tests.
+#[cfg(test)]
+pub fn function_wat(source: &str, name: &str, level: OptLevel) -> String {
+    use crate::{compile, CompileOptions};
+
+    let options = CompileOptions::default().emit_wasm(false).opt_level(level);
+    let wat = compile(source, &options).unwrap().wat;
+    let start = wat.find(&format!("(func ${} ", name)).unwrap();
+    let end = wat[start..].find("\n)\n").unwrap();
+    wat[start..start + end + 3].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::{check, compile, CompileOptions};
+
+    #[test]
+    fn it_folds_constant_subexpressions() {
+        let source = "const k = 4; def f(x) x * (2 * 3) + k / 8 - sqrt(16);";
+
+        assert_eq!(
+            function_wat(source, "f", 0),
+            "(func $f (param $x f32) (result f32)\n\
+             local.get $x\nf32.const 2\nf32.const 3\nf32.mul\nf32.mul\n\
+             f32.const 4\nf32.const 8\nf32.div\nf32.add\n\
+             f32.const 16\nf32.sqrt\nf32.sub\n)\n"
+        );
+        assert_eq!(
+            function_wat(source, "f", 1),
+            "(func $f (param $x f32) (result f32)\n\
+             local.get $x\nf32.const 6\nf32.mul\nf32.const 0.5\nf32.add\n\
+             f32.const 4\nf32.sub\n)\n"
+        );
+    }
+
+    #[test]
+    fn it_simplifies_identities() {
+        let source = "def f(x) let y = --x * 1 in (y - 0) / 1 + y ^ 1;";
+
+        assert_eq!(
+            function_wat(source, "f", 0),
+            "(func $f (param $x f32) (result f32)\n(local $y f32)\n\
+             local.get $x\nf32.neg\nf32.neg\nf32.const 1\nf32.mul\nlocal.set $y\n\
+             local.get $y\nf32.const 0\nf32.sub\nf32.const 1\nf32.div\n\
+             local.get $y\nf32.const 1\ncall $__pow\nf32.add\n)\n"
+        );
+        assert_eq!(
+            function_wat(source, "f", 1),
+            "(func $f (param $x f32) (result f32)\n(local $y f32)\n\
+             local.get $x\nlocal.set $y\nlocal.get $y\nlocal.get $y\nf32.add\n)\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_identities_that_do_not_hold_in_ieee_754() {
+        // x * 0 is NaN for infinite x and -0 for negative x, and x + 0 is 0
+        // for x = -0.
+        let source = "def f(x) x * 0 + (x + 0) + (x - x);";
+
+        assert_eq!(function_wat(source, "f", 1), function_wat(source, "f", 0));
+    }
+
+    #[test]
+    fn it_resolves_constant_conditions() {
+        let source =
+            "const debug = 0; def f(x) if debug > 0 or x < 0 then -x else let two = 1 + 1 in \
+                      if 1 < two and not (two == 3) then x * two else x;";
+
+        assert_eq!(
+            function_wat(source, "f", 1),
+            "(func $f (param $x f32) (result f32)\n\
+             local.get $x\nf32.const 0\nf32.lt\n\
+             if (result f32)\nlocal.get $x\nf32.neg\n\
+             else\nlocal.get $x\nf32.const 2\nf32.mul\nend\n)\n"
+        );
+    }
+
+    #[test]
+    fn it_folds_boolean_bodies() {
+        assert_eq!(
+            function_wat("def f() 2 < 3 and 1 <> 1;", "f", 1),
+            "(func $f (result f32)\ni32.const 0\nf32.convert_i32_u\n)\n"
+        );
+    }
+
+    #[test]
+    fn it_turns_self_tail_calls_into_loops() {
+        let source = "def sum(n acc) if n == 0 then acc else sum(n - 1, acc + n);
+                      def f(x) if x > 0 then let y = x - 1 in f(y) else f(x) + 1;";
+
+        assert_eq!(
+            function_wat(source, "sum", 1),
+            "(func $sum (param $n f32) (param $acc f32) (result f32)\n\
+             loop (result f32)\nlocal.get $n\nf32.const 0\nf32.eq\n\
+             if (result f32)\nlocal.get $acc\nelse\n\
+             local.get $n\nf32.const 1\nf32.sub\nlocal.get $acc\nlocal.get $n\nf32.add\n\
+             local.set $acc\nlocal.set $n\nbr 1\nend\nend\n)\n"
+        );
+        // f(x) + 1 is not a tail call.
+        let wat = compile(source, &CompileOptions::default().opt_level(1))
+            .unwrap()
+            .wat;
+        assert!(wat.contains(
+            "local.get $y\nlocal.set $x\nbr 1\nelse\nlocal.get $x\ncall $f\nf32.const 1\nf32.add\n"
+        ));
+        assert!(!function_wat(source, "sum", 0).contains("loop"));
+    }
+
+    #[test]
+    fn it_makes_tail_calls_with_return_call() {
+        let source = "def even(n) if n == 0 then 1 else odd(n - 1);
+                      def odd(n) if n == 0 then 0 else even(n - 1) * 1 + sqrt(n);";
+        let options = CompileOptions::default().emit_wasm(false).tail_calls(true);
+        let wat = compile(source, &options).unwrap().wat;
+
+        assert!(wat.contains("f32.sub\nreturn_call $odd\nend\n"));
+        assert!(wat.contains("call $even\n"));
+        assert!(!wat.contains("return_call $even"));
+    }
+
+    #[test]
+    fn it_preserves_results() {
+        let source = "def f(x y) exp(x) + sin(y) * pow(x, y) - hypot(x, 2 * y) / atan2(y, x) \
+                      + clamp(x ^ 0 * 1 + 0 - y, -1, 1) + sign(ln(x));";
+        let asts = check(source).unwrap().get_asts().clone();
+        let mut optimized = asts.clone();
+        optimize(&mut optimized, MAX_OPT_LEVEL);
+        let values = [0.0, -0.0, 0.5, -2.0, 3.0, 100.0, f32::INFINITY, f32::NAN];
+
+        for &x in &values {
+            for &y in &values {
+                let expected = Interpreter::new(&asts).call("f", &[x, y]).unwrap();
+                let found = Interpreter::new(&optimized).call("f", &[x, y]).unwrap();
+                assert_eq!(found.to_bits(), expected.to_bits(), "f({}, {})", x, y);
+            }
+        }
+    }
+}
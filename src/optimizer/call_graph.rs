@@ -0,0 +1,207 @@
+use crate::ast::{Ast, ExprKind, ExprNode};
+use std::collections::{HashMap, HashSet};
+
+/// Which definitions call which. Calls of builtins and externs are left out.
+pub struct CallGraph {
+    /// Callees of every definition, each once, in the order of their first
+    /// call.
+    callees: HashMap<String, Vec<String>>,
+    /// Every definition after the ones it calls, except along a cycle.
+    order: Vec<String>,
+    /// Definitions that can call themselves, directly or through others.
+    recursive: HashSet<String>,
+    /// Definitions that call an extern themselves.
+    calls_externs: HashSet<String>,
+}
+
+impl CallGraph {
+    pub fn new(asts: &[Ast]) -> Self {
+        let definitions: Vec<&str> = asts
+            .iter()
+            .filter_map(Ast::as_definition)
+            .map(|func| func.get_function_name())
+            .collect();
+
+        let externs: Vec<&str> = asts
+            .iter()
+            .filter_map(|ast| match ast {
+                Ast::Extern(ext) => Some(ext.get_proto().get_name()),
+                _ => None,
+            })
+            .collect();
+
+        let mut callees = HashMap::new();
+        let mut calls_externs = HashSet::new();
+        for func in asts.iter().filter_map(Ast::as_definition) {
+            let mut calls = vec![];
+            visit_calls(func.get_body(), &mut |callee| {
+                if externs.contains(&callee) {
+                    calls_externs.insert(func.get_function_name().to_owned());
+                } else if definitions.contains(&callee) && !calls.iter().any(|call| call == callee)
+                {
+                    calls.push(callee.to_owned());
+                }
+            });
+            callees.insert(func.get_function_name().to_owned(), calls);
+        }
+
+        let mut graph = Self {
+            callees,
+            order: vec![],
+            recursive: HashSet::new(),
+            calls_externs,
+        };
+        let mut visited = HashSet::new();
+        for name in definitions.iter() {
+            graph.visit(name, &mut visited);
+        }
+        graph.recursive = definitions
+            .iter()
+            .filter(|name| graph.reachable(graph.get_callees(name)).contains(**name))
+            .map(|name| name.to_string())
+            .collect();
+
+        graph
+    }
+
+    /// Definitions `name` calls directly.
+    pub fn get_callees(&self, name: &str) -> &[String] {
+        self.callees.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every definition, each after the ones it calls unless they call it
+    /// back.
+    pub fn get_order(&self) -> &Vec<String> {
+        &self.order
+    }
+
+    pub fn is_recursive(&self, name: &str) -> bool {
+        self.recursive.contains(name)
+    }
+
+    /// Definitions that call an extern, directly or through others, so two
+    /// calls of them can give different results.
+    pub fn reaching_externs(&self) -> HashSet<String> {
+        self.callees
+            .keys()
+            .filter(|name| {
+                self.reachable(std::slice::from_ref(name))
+                    .iter()
+                    .any(|reached| self.calls_externs.contains(reached))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The definitions in `roots` and every one they call, directly or not.
+    pub fn reachable(&self, roots: &[String]) -> HashSet<String> {
+        let mut reached = HashSet::new();
+        let mut pending: Vec<&str> = roots.iter().map(String::as_str).collect();
+
+        while let Some(name) = pending.pop() {
+            if reached.insert(name.to_owned()) {
+                pending.extend(self.get_callees(name).iter().map(String::as_str));
+            }
+        }
+        reached
+    }
+
+    fn visit(&mut self, name: &str, visited: &mut HashSet<String>) {
+        if !visited.insert(name.to_owned()) {
+            return;
+        }
+        for callee in self.get_callees(name).to_vec() {
+            self.visit(&callee, visited);
+        }
+        self.order.push(name.to_owned());
+    }
+}
+
+/// Calls `f` with the callee of every call in `node`.
+pub fn visit_calls(node: &ExprNode, f: &mut impl FnMut(&str)) {
+    match &node.kind {
+        ExprKind::Number(_) | ExprKind::Variable(_) => {}
+        ExprKind::BinaryExpr { lhs, rhs, .. } => {
+            visit_calls(lhs, f);
+            visit_calls(rhs, f);
+        }
+        ExprKind::UnaryExpr { rhs, .. } => visit_calls(rhs, f),
+        ExprKind::CallExpr { callee, args } => {
+            f(callee);
+            for arg in args {
+                visit_calls(arg, f);
+            }
+        }
+        ExprKind::IfExpr {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            visit_calls(cond, f);
+            visit_calls(then_branch, f);
+            visit_calls(else_branch, f);
+        }
+        ExprKind::LetExpr { value, body, .. } => {
+            visit_calls(value, f);
+            visit_calls(body, f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check;
+
+    fn graph(source: &str) -> CallGraph {
+        CallGraph::new(check(source).unwrap().get_asts())
+    }
+
+    #[test]
+    fn it_orders_callees_first() {
+        let graph = graph(
+            "def root1(a b c) (-b + sqrt(discr(a, b, c))) / (2 * a);
+             def discr(a b c) sq(b) - 4 * a * c + sq(b) * 0;
+             def sq(x) x * x;",
+        );
+
+        assert_eq!(graph.get_callees("root1"), ["discr"]);
+        assert_eq!(graph.get_callees("discr"), ["sq"]);
+        assert_eq!(graph.get_order(), &["sq", "discr", "root1"]);
+    }
+
+    #[test]
+    fn it_finds_recursion() {
+        let graph = graph(
+            "def fib(x) if x < 2 then x else fib(x - 1) + fib(x - 2);
+             def even(n) if n == 0 then 1 else odd(n - 1);
+             def odd(n) if n == 0 then 0 else even(n - 1);
+             def f(n) even(n) + fib(n);",
+        );
+
+        assert!(graph.is_recursive("fib"));
+        assert!(graph.is_recursive("even"));
+        assert!(graph.is_recursive("odd"));
+        assert!(!graph.is_recursive("f"));
+    }
+
+    #[test]
+    fn it_finds_reachable_definitions() {
+        let graph = graph("def f(x) g(x); def g(x) sqrt(x); def h(x) f(x); def u() 1;");
+        let reached = graph.reachable(&[String::from("f")]);
+
+        assert_eq!(reached.len(), 2);
+        assert!(reached.contains("f") && reached.contains("g"));
+    }
+
+    #[test]
+    fn it_finds_definitions_reaching_externs() {
+        let graph = graph(
+            "extern random(); def r() random(); def f(x) x + r(); def g(x) f(x) * 2; def h(x) x;",
+        );
+        let mut reaching: Vec<String> = graph.reaching_externs().into_iter().collect();
+        reaching.sort();
+
+        assert_eq!(reaching, ["f", "g", "r"]);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::code_generator::instruction::Instruction;
+use crate::types::Type;
+use std::collections::HashSet;
+
+/// Name of the locals holding shared subexpressions, reserved like the names
+/// of `lexer::numbered_name`.
+pub const TEMP_NAME: &str = "__cse";
+
+/// Common subexpression elimination while a function body is lowered.
+///
+/// A pure subexpression is stored with `local.tee` where it is first
+/// computed, and computing it again further on reads the local instead.
+/// What an `if` branch or the right operand of `and`/`or` computes is only
+/// available until its end, so no code runs that would not have run before.
+/// Calls of externs, and of the definitions that call one directly or not,
+/// are never shared; other calls of definitions are pure.
+#[derive(Debug, Clone, Default)]
+pub struct Cse {
+    /// Functions whose calls can give different results with the same
+    /// arguments.
+    impure: HashSet<String>,
+    /// Code of the subexpressions computed so far on the current path, with
+    /// the temporary holding each.
+    available: Vec<(Vec<Instruction>, usize)>,
+    /// Type of every temporary and whether it is read again.
+    temps: Vec<(Type, bool)>,
+}
+
+impl Cse {
+    pub fn new(impure: HashSet<String>) -> Self {
+        Self {
+            impure,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the expression lowered to `instrs` is worth sharing: it calls
+    /// a definition or takes more than a single operation on a value, and it
+    /// neither calls an impure function nor binds a `let`.
+    pub fn is_candidate(&self, instrs: &[Instruction]) -> bool {
+        let mut has_call = false;
+
+        for instr in instrs {
+            match instr {
+                Instruction::Call(name) if self.impure.contains(name) => return false,
+                Instruction::Call(_) => has_call = true,
+                Instruction::LocalSet(_) => return false,
+                _ => {}
+            }
+        }
+        has_call || instrs.len() > 2
+    }
+
+    /// Reads the temporary holding the value of `instrs` if it is available.
+    pub fn reuse(&mut self, instrs: &[Instruction]) -> Option<Instruction> {
+        let (_, temp) = self
+            .available
+            .iter()
+            .rev()
+            .find(|(code, _)| code == instrs)?;
+        self.temps[*temp].1 = true;
+        Some(Instruction::LocalGet(placeholder(*temp)))
+    }
+
+    /// Makes the value of `instrs` available, returning the instruction that
+    /// stores it while leaving it on the stack.
+    pub fn keep(&mut self, instrs: Vec<Instruction>, ty: Type) -> Instruction {
+        let temp = self.temps.len();
+        self.temps.push((ty, false));
+        self.available.push((instrs, temp));
+        Instruction::LocalTee(placeholder(temp))
+    }
+
+    /// Marks the start of code that only runs on some paths.
+    pub fn enter_branch(&self) -> usize {
+        self.available.len()
+    }
+
+    /// Forgets what was computed since `enter_branch` returned `mark`.
+    pub fn leave_branch(&mut self, mark: usize) {
+        self.available.truncate(mark);
+    }
+
+    /// Declares a local with `declare` for every temporary that is read
+    /// again and names it in `instrs`, dropping the stores nobody reads.
+    pub fn finish(self, instrs: &mut Vec<Instruction>, mut declare: impl FnMut(Type) -> String) {
+        let names: Vec<Option<String>> = self
+            .temps
+            .iter()
+            .map(|(ty, is_used)| is_used.then(|| declare(*ty)))
+            .collect();
+        let name = |local: &str| {
+            let temp: usize = local.strip_prefix('#')?.parse().ok()?;
+            Some(names[temp].clone())
+        };
+
+        instrs.retain_mut(|instr| match instr {
+            Instruction::LocalTee(local) | Instruction::LocalGet(local) => match name(local) {
+                Some(Some(name)) => {
+                    *local = name;
+                    true
+                }
+                Some(None) => false,
+                None => true,
+            },
+            _ => true,
+        });
+    }
+}
+
+/// Name of a temporary until `finish` knows which ones are kept.
+fn placeholder(temp: usize) -> String {
+    format!("#{}", temp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+    use crate::optimizer::function_wat;
+    use crate::check;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn instruction_count(wat: &str) -> usize {
+        wat.lines()
+            .filter(|line| !line.starts_with('(') && *line != ")")
+            .count()
+    }
+
+    const ROOTS: &str = "@noinline def discr(a b c) b * b - 4 * a * c;
+        def root2(a b c) if discr(a, b, c) < 0 then 0 else (-b - sqrt(discr(a, b, c))) / (2 * a);";
+
+    #[test]
+    fn it_shares_repeated_calls() {
+        assert_eq!(
+            function_wat(ROOTS, "root2", 2),
+            "(func $root2 (param $a f32) (param $b f32) (param $c f32) (result f32)\n\
+             (local $__cse f32)\n\
+             local.get $a\nlocal.get $b\nlocal.get $c\ncall $discr\nlocal.tee $__cse\n\
+             f32.const 0\nf32.lt\nif (result f32)\nf32.const 0\nelse\n\
+             local.get $b\nf32.neg\nlocal.get $__cse\nf32.sqrt\nf32.sub\n\
+             f32.const 2\nlocal.get $a\nf32.mul\nf32.div\nend\n)\n"
+        );
+        assert_eq!(
+            instruction_count(&function_wat(ROOTS, "root2", 1)) - 2,
+            instruction_count(&function_wat(ROOTS, "root2", 2))
+        );
+    }
+
+    #[test]
+    fn it_shares_nested_subexpressions() {
+        let source = "def f(x y) let s = x * y + 1 in sqrt(x * y + 1) * (x * y) + s;";
+
+        assert_eq!(
+            function_wat(source, "f", 2),
+            "(func $f (param $x f32) (param $y f32) (result f32)\n\
+             (local $s f32)\n(local $__cse f32)\n(local $__cse_1 f32)\n\
+             local.get $x\nlocal.get $y\nf32.mul\nlocal.tee $__cse\n\
+             f32.const 1\nf32.add\nlocal.tee $__cse_1\nlocal.set $s\n\
+             local.get $__cse_1\nf32.sqrt\nlocal.get $__cse\nf32.mul\n\
+             local.get $s\nf32.add\n)\n"
+        );
+    }
+
+    #[test]
+    fn it_only_shares_values_computed_on_every_path() {
+        // x * x is computed before the branches but x + 1 only in one of them.
+        let source = "def f(x) if x * x > 1 then x * x + (x + 1) else x + 1;";
+        let wat = function_wat(source, "f", 2);
+
+        assert_eq!(wat.matches("local.tee").count(), 1);
+        assert_eq!(wat.matches("f32.add").count(), 3);
+        assert_eq!(wat.matches("f32.mul").count(), 1);
+    }
+
+    #[test]
+    fn it_does_not_share_extern_calls() {
+        let source = "extern random(); def f() random() * 2 + random() * 2;";
+
+        assert_eq!(function_wat(source, "f", 2), function_wat(source, "f", 1));
+    }
+
+    #[test]
+    fn it_does_not_share_calls_reaching_externs() {
+        static CALLS: AtomicU32 = AtomicU32::new(0);
+        let source = "extern random(); @noinline def r() random(); def f() r() + r();";
+        let asts = check(source).unwrap().get_asts().clone();
+        let mut interpreter = Interpreter::new(&asts)
+            .with_host_function("random", |_| CALLS.fetch_add(1, Ordering::SeqCst) as f32);
+
+        // Every call of r reaches the host, 0 + 1, so the code keeps both.
+        assert_eq!(interpreter.call("f", &[]), Ok(1.0));
+        let wat = function_wat(source, "f", 2);
+        assert_eq!(
+            wat.matches("call $r\n").count(),
+            CALLS.load(Ordering::SeqCst) as usize
+        );
+        assert!(!wat.contains("local.tee"));
+    }
+}
@@ -0,0 +1,115 @@
+use super::call_graph::{self, CallGraph};
+use crate::ast::Ast;
+use crate::code_generator::function_code::has_exports;
+use crate::diagnostic::Diagnostic;
+use crate::span::MAIN_FILE;
+
+/// Definitions the module exports: the ones marked `@export`, or every
+/// definition of the main file if nothing is marked.
+pub fn exported_definitions(asts: &[Ast]) -> Vec<String> {
+    let has_exports = has_exports(asts);
+
+    asts.iter()
+        .filter_map(Ast::as_definition)
+        .filter(|func| {
+            if has_exports {
+                func.get_export_name().is_some()
+            } else {
+                func.get_span().file == MAIN_FILE
+            }
+        })
+        .map(|func| func.get_function_name().to_owned())
+        .collect()
+}
+
+/// Warns about the definitions of the main file that neither an exported
+/// definition nor a constant calls, directly or not. Unused definitions of
+/// imported files are expected and left alone.
+pub fn find_unused(asts: &[Ast]) -> Vec<Diagnostic> {
+    let graph = CallGraph::new(asts);
+    let mut roots = exported_definitions(asts);
+    for constant in asts.iter().filter_map(Ast::as_const) {
+        call_graph::visit_calls(constant.get_expr(), &mut |callee| {
+            roots.push(callee.to_owned())
+        });
+    }
+    let used = graph.reachable(&roots);
+
+    asts.iter()
+        .filter_map(Ast::as_definition)
+        .filter(|func| func.get_span().file == MAIN_FILE)
+        .filter(|func| !used.contains(func.get_function_name()))
+        .map(|func| {
+            Diagnostic::warning(&format!(
+                "Function '{}' is never used",
+                func.get_function_name()
+            ))
+            .with_span(func.get_proto().get_name_span())
+            .with_label("unused function")
+        })
+        .collect()
+}
+
+/// Drops the definitions no exported definition calls, directly or not,
+/// once constants are computed and nothing else needs them.
+pub fn remove_unreachable(asts: &mut Vec<Ast>) {
+    let reachable = CallGraph::new(asts).reachable(&exported_definitions(asts));
+
+    asts.retain(|ast| {
+        ast.as_definition()
+            .is_none_or(|func| reachable.contains(func.get_function_name()))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{check, compile, CompileOptions};
+
+    #[test]
+    fn it_warns_about_unused_functions() {
+        let source = "@export def f(x) g(x); def g(x) sqrt(x); def h(x) f(x) + u(x); def u(x) x;
+                      const k = v(2); def v(x) x * x;";
+        let program = check(source).unwrap();
+        let messages: Vec<&str> = program
+            .get_warnings()
+            .iter()
+            .map(|warning| warning.message.as_str())
+            .collect();
+
+        assert_eq!(
+            messages,
+            ["Function 'h' is never used", "Function 'u' is never used"]
+        );
+    }
+
+    #[test]
+    fn it_drops_unreachable_functions() {
+        let source = "@export def f(x) g(x) + k; def g(x) sqrt(x); def h(x) f(x);
+                      const k = v(2); def v(x) x * x;";
+        let options = CompileOptions::default().emit_wasm(false);
+        let artifact = compile(source, &options).unwrap();
+
+        assert!(artifact.wat.contains("(func $f") && artifact.wat.contains("(func $g"));
+        assert!(!artifact.wat.contains("(func $h") && !artifact.wat.contains("(func $v"));
+        assert_eq!(artifact.warnings.len(), 1);
+    }
+
+    #[test]
+    fn it_drops_functions_left_unused_by_inlining() {
+        let source = "@export def f(x) sq(x) + 1; @inline def sq(x) x * x;";
+        let options = CompileOptions::default().emit_wasm(false).opt_level(1);
+        let artifact = compile(source, &options).unwrap();
+
+        assert!(!artifact.wat.contains("(func $sq"));
+        assert!(artifact.warnings.is_empty());
+    }
+
+    #[test]
+    fn it_keeps_every_main_function_without_exports() {
+        let source = "def f(x) x; def g(x) x;";
+        let artifact = compile(source, &CompileOptions::default().emit_wasm(false)).unwrap();
+
+        assert!(artifact.wat.contains("(func $f") && artifact.wat.contains("(func $g"));
+        assert!(artifact.warnings.is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::ast::{ExprKind, ExprNode};
+use crate::code_generator::builtin_funcs;
+use crate::interpreter::{eval_binary_op, eval_builtin, Value};
+use crate::operator::Operator;
+use crate::types::Type;
+use std::collections::HashMap;
+
+/// Folds the constant subexpressions of a function body and simplifies
+/// identities, bottom up.
+///
+/// Values are computed in `f32` exactly as at run time, and an identity is
+/// only applied if it holds for every `f32`, including NaN, infinities and
+/// -0: `x * 1` becomes `x`, but `x * 0` and `x + 0` stay. Operands are only
+/// dropped if they call nothing, so a call that would not return is kept.
+/// A folded boolean is a `Number` of type `Bool`, 1 or 0.
+pub struct Folder<'a> {
+    constants: &'a HashMap<String, f32>,
+    /// Parameters and `let`s in scope, innermost last, with their value if
+    /// it is known.
+    scope: Vec<(String, Option<f32>)>,
+}
+
+impl<'a> Folder<'a> {
+    pub fn new(constants: &'a HashMap<String, f32>, params: &[String]) -> Self {
+        Self {
+            constants,
+            scope: params.iter().map(|param| (param.clone(), None)).collect(),
+        }
+    }
+
+    pub fn fold(&mut self, node: &mut ExprNode) {
+        match &mut node.kind {
+            ExprKind::Number(_) => {}
+            ExprKind::Variable(name) => {
+                if let Some(value) = self.lookup(name) {
+                    node.kind = ExprKind::Number(value as f64);
+                }
+            }
+            ExprKind::BinaryExpr { lhs, rhs, .. } => {
+                self.fold(lhs);
+                self.fold(rhs);
+            }
+            ExprKind::UnaryExpr { rhs, .. } => self.fold(rhs),
+            ExprKind::CallExpr { args, .. } => {
+                for arg in args.iter_mut() {
+                    self.fold(arg);
+                }
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.fold(cond);
+                self.fold(then_branch);
+                self.fold(else_branch);
+            }
+            ExprKind::LetExpr {
+                name, value, body, ..
+            } => {
+                self.fold(value);
+                self.scope.push((name.clone(), number(value)));
+                self.fold(body);
+                self.scope.pop();
+            }
+        }
+
+        if let Some(simpler) = simplify(node) {
+            *node = simpler;
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<f32> {
+        match self.scope.iter().rev().find(|(var, _)| var == name) {
+            Some((_, value)) => *value,
+            None => self.constants.get(name).copied(),
+        }
+    }
+}
+
+/// The node replacing `node`, whose operands are already folded, if it can
+/// be simplified.
+fn simplify(node: &mut ExprNode) -> Option<ExprNode> {
+    let ty = node.ty;
+    let span = node.span;
+    let constant = |value: Value| {
+        let number = match value {
+            Value::Number(number) => number,
+            Value::Bool(value) => f32::from(u8::from(value)),
+        };
+        let mut node = ExprNode::create_number(number as f64, span);
+        node.ty = ty;
+        node
+    };
+
+    match &mut node.kind {
+        ExprKind::BinaryExpr { op, lhs, rhs } => {
+            if let (Some(a), Some(b)) = (value(lhs), value(rhs)) {
+                if !op.is_short_circuit() {
+                    return Some(constant(eval_binary_op(*op, a, b)));
+                }
+            }
+            simplify_binary(*op, lhs, rhs)
+        }
+        ExprKind::UnaryExpr { op, rhs } => match (value(rhs), &mut rhs.kind) {
+            (Some(Value::Number(number)), _) => Some(constant(Value::Number(-number))),
+            (Some(Value::Bool(value)), _) => Some(constant(Value::Bool(!value))),
+            // --x and not not x
+            (None, ExprKind::UnaryExpr { op: inner, rhs }) if inner == op => Some(take(rhs)),
+            (None, _) => None,
+        },
+        ExprKind::CallExpr { callee, args } => {
+            let instr = builtin_funcs().get(callee.as_str())?.clone();
+            let x = number(&args[0])?;
+            Some(constant(Value::Number(eval_builtin(&instr, x))))
+        }
+        ExprKind::IfExpr {
+            cond,
+            then_branch,
+            else_branch,
+        } => match value(cond)? {
+            Value::Bool(true) => Some(take(then_branch)),
+            _ => Some(take(else_branch)),
+        },
+        // The value was substituted for every use of the variable.
+        ExprKind::LetExpr { value, body, .. } if number(value).is_some() => Some(take(body)),
+        _ => None,
+    }
+}
+
+/// Identities of `lhs op rhs` where at most one operand is constant.
+fn simplify_binary(op: Operator, lhs: &mut ExprNode, rhs: &mut ExprNode) -> Option<ExprNode> {
+    let is = |node: &ExprNode, expected: f32| {
+        number(node)
+            .is_some_and(|n| n == expected && n.is_sign_negative() == expected.is_sign_negative())
+    };
+    let is_bool = |node: &ExprNode, expected: bool| matches!(value(node), Some(Value::Bool(b)) if b == expected);
+
+    match op {
+        Operator::Plus if is(lhs, -0.0) => Some(take(rhs)),
+        Operator::Plus | Operator::Minus
+            if is(rhs, if op == Operator::Plus { -0.0 } else { 0.0 }) =>
+        {
+            Some(take(lhs))
+        }
+        Operator::Mul if is(lhs, 1.0) => Some(take(rhs)),
+        Operator::Mul | Operator::Div | Operator::Pow if is(rhs, 1.0) => Some(take(lhs)),
+        Operator::Pow if is(rhs, 0.0) && !lhs.calls_definitions() => {
+            Some(ExprNode::create_number(1.0, lhs.span.to(rhs.span)))
+        }
+
+        // The right operand of `and` and `or` only runs if the left one does
+        // not decide the result.
+        Operator::AndAlso if is_bool(lhs, true) => Some(take(rhs)),
+        Operator::AndAlso if is_bool(lhs, false) => Some(take(lhs)),
+        Operator::OrElse if is_bool(lhs, false) => Some(take(rhs)),
+        Operator::OrElse if is_bool(lhs, true) => Some(take(lhs)),
+
+        Operator::AndAlso | Operator::And if is_bool(rhs, true) => Some(take(lhs)),
+        Operator::OrElse | Operator::Or if is_bool(rhs, false) => Some(take(lhs)),
+        Operator::And if is_bool(lhs, true) => Some(take(rhs)),
+        Operator::Or if is_bool(lhs, false) => Some(take(rhs)),
+        Operator::AndAlso | Operator::And if is_bool(rhs, false) && !lhs.calls_definitions() => {
+            Some(take(rhs))
+        }
+        Operator::OrElse | Operator::Or if is_bool(rhs, true) && !lhs.calls_definitions() => {
+            Some(take(rhs))
+        }
+        Operator::And if is_bool(lhs, false) && !rhs.calls_definitions() => Some(take(lhs)),
+        Operator::Or if is_bool(lhs, true) && !rhs.calls_definitions() => Some(take(lhs)),
+        _ => None,
+    }
+}
+
+/// The value of a folded constant.
+fn value(node: &ExprNode) -> Option<Value> {
+    match node.kind {
+        ExprKind::Number(number) if node.ty == Type::Bool => Some(Value::Bool(number != 0.0)),
+        ExprKind::Number(number) => Some(Value::Number(number as f32)),
+        _ => None,
+    }
+}
+
+fn number(node: &ExprNode) -> Option<f32> {
+    match value(node)? {
+        Value::Number(number) => Some(number),
+        Value::Bool(value) => Some(f32::from(u8::from(value))),
+    }
+}
+
+/// Moves `node` out, leaving a placeholder in the tree being replaced.
+fn take(node: &mut ExprNode) -> ExprNode {
+    let placeholder = ExprNode::create_number(0.0, node.span);
+    std::mem::replace(node, placeholder)
+}
@@ -0,0 +1,364 @@
+use crate::ast::{ExprKind, ExprNode, Function};
+use crate::lexer::numbered_name;
+use crate::types::Type;
+use std::collections::HashMap;
+
+/// Bodies of at most this many nodes are inlined from level 2 on.
+pub const MAX_INLINE_SIZE: usize = 12;
+
+/// Replaces calls of definitions with their bodies.
+///
+/// Each argument is bound by a `let` named `callee.param_n`, see
+/// `lexer::numbered_name`, so it is computed once and before the body like
+/// in a call. A number or a variable the body does not bind is used in
+/// place of its parameter instead. Nothing is inlined where it would see a
+/// caller's variable instead of the constant of the same name.
+#[derive(Default)]
+pub struct Inliner {
+    /// Parameters and body of every definition added so far.
+    functions: HashMap<String, (Vec<String>, ExprNode)>,
+    /// Number of `let`s introduced so far, which makes their names unique.
+    bindings: usize,
+}
+
+impl Inliner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inlines the calls of `func` from now on. It must not call itself,
+    /// directly or through other definitions.
+    pub fn add(&mut self, func: &Function) {
+        let params = func
+            .get_params()
+            .iter()
+            .map(|param| param.name.clone())
+            .collect();
+        let mut body = func.get_body().clone();
+
+        // A boolean body returns 1 or 0, like a call.
+        if body.ty == Type::Bool {
+            let span = body.span;
+            body = ExprNode::create_if_then_else(
+                body,
+                ExprNode::create_number(1.0, span),
+                ExprNode::create_number(0.0, span),
+                span,
+            );
+        }
+        self.functions
+            .insert(func.get_function_name().to_owned(), (params, body));
+    }
+
+    /// Inlines the calls of added definitions in the body of `func`.
+    pub fn inline(&mut self, func: &mut Function) {
+        let mut scope: Vec<String> = func
+            .get_params()
+            .iter()
+            .map(|param| param.name.clone())
+            .collect();
+        self.inline_expr(func.get_body_mut(), &mut scope);
+    }
+
+    /// `scope` holds the variables visible at `node`.
+    fn inline_expr(&mut self, node: &mut ExprNode, scope: &mut Vec<String>) {
+        match &mut node.kind {
+            ExprKind::Number(_) | ExprKind::Variable(_) => {}
+            ExprKind::BinaryExpr { lhs, rhs, .. } => {
+                self.inline_expr(lhs, scope);
+                self.inline_expr(rhs, scope);
+            }
+            ExprKind::UnaryExpr { rhs, .. } => self.inline_expr(rhs, scope),
+            ExprKind::CallExpr { callee, args } => {
+                for arg in args.iter_mut() {
+                    self.inline_expr(arg, scope);
+                }
+                if let Some(body) = self.expand(callee, args, scope) {
+                    *node = body;
+                }
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.inline_expr(cond, scope);
+                self.inline_expr(then_branch, scope);
+                self.inline_expr(else_branch, scope);
+            }
+            ExprKind::LetExpr {
+                name, value, body, ..
+            } => {
+                self.inline_expr(value, scope);
+                scope.push(name.clone());
+                self.inline_expr(body, scope);
+                scope.pop();
+            }
+        }
+    }
+
+    /// Body of the call `callee(args)`, if it is inlined.
+    fn expand(
+        &mut self,
+        callee: &str,
+        args: &mut [ExprNode],
+        scope: &[String],
+    ) -> Option<ExprNode> {
+        let (params, body) = self.functions.get(callee)?;
+
+        let mut free = vec![];
+        free_variables(body, &mut params.clone(), &mut free);
+        if free.iter().any(|name| scope.contains(name)) {
+            return None;
+        }
+
+        let mut bound = vec![];
+        let_names(body, &mut bound);
+        let mut body = body.clone();
+        let mut replacements = vec![];
+        let mut lets = vec![];
+        for (param, arg) in params.iter().zip(args.iter_mut()) {
+            let arg = std::mem::replace(arg, ExprNode::create_number(0.0, arg.span));
+            match &arg.kind {
+                ExprKind::Number(_) => replacements.push((param.clone(), arg)),
+                ExprKind::Variable(name) if !bound.contains(name) => {
+                    replacements.push((param.clone(), arg))
+                }
+                _ => {
+                    self.bindings += 1;
+                    let name = numbered_name(&format!("{}.{}", callee, param), self.bindings);
+                    let variable = ExprNode::create_variable(name.clone(), arg.span);
+                    replacements.push((param.clone(), variable));
+                    lets.push((name, arg));
+                }
+            }
+        }
+
+        substitute(&mut body, &replacements);
+        for (name, value) in lets.into_iter().rev() {
+            let (ty, span) = (body.ty, body.span);
+            body = ExprNode::create_let(name, value.span, value, body, span);
+            body.ty = ty;
+        }
+        Some(body)
+    }
+}
+
+/// Number of nodes in the expression.
+pub fn size(node: &ExprNode) -> usize {
+    1 + match &node.kind {
+        ExprKind::Number(_) | ExprKind::Variable(_) => 0,
+        ExprKind::BinaryExpr { lhs, rhs, .. } => size(lhs) + size(rhs),
+        ExprKind::UnaryExpr { rhs, .. } => size(rhs),
+        ExprKind::CallExpr { args, .. } => args.iter().map(size).sum(),
+        ExprKind::IfExpr {
+            cond,
+            then_branch,
+            else_branch,
+        } => size(cond) + size(then_branch) + size(else_branch),
+        ExprKind::LetExpr { value, body, .. } => size(value) + size(body),
+    }
+}
+
+/// Adds the variables `node` uses without binding them, other than those in
+/// `bound`, to `free`.
+fn free_variables(node: &ExprNode, bound: &mut Vec<String>, free: &mut Vec<String>) {
+    match &node.kind {
+        ExprKind::Number(_) => {}
+        ExprKind::Variable(name) => {
+            if !bound.contains(name) && !free.contains(name) {
+                free.push(name.clone());
+            }
+        }
+        ExprKind::BinaryExpr { lhs, rhs, .. } => {
+            free_variables(lhs, bound, free);
+            free_variables(rhs, bound, free);
+        }
+        ExprKind::UnaryExpr { rhs, .. } => free_variables(rhs, bound, free),
+        ExprKind::CallExpr { args, .. } => {
+            for arg in args {
+                free_variables(arg, bound, free);
+            }
+        }
+        ExprKind::IfExpr {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            free_variables(cond, bound, free);
+            free_variables(then_branch, bound, free);
+            free_variables(else_branch, bound, free);
+        }
+        ExprKind::LetExpr {
+            name, value, body, ..
+        } => {
+            free_variables(value, bound, free);
+            bound.push(name.clone());
+            free_variables(body, bound, free);
+            bound.pop();
+        }
+    }
+}
+
+/// Adds the names bound by the `let`s of `node` to `names`.
+fn let_names(node: &ExprNode, names: &mut Vec<String>) {
+    match &node.kind {
+        ExprKind::Number(_) | ExprKind::Variable(_) => {}
+        ExprKind::BinaryExpr { lhs, rhs, .. } => {
+            let_names(lhs, names);
+            let_names(rhs, names);
+        }
+        ExprKind::UnaryExpr { rhs, .. } => let_names(rhs, names),
+        ExprKind::CallExpr { args, .. } => {
+            for arg in args {
+                let_names(arg, names);
+            }
+        }
+        ExprKind::IfExpr {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            let_names(cond, names);
+            let_names(then_branch, names);
+            let_names(else_branch, names);
+        }
+        ExprKind::LetExpr {
+            name, value, body, ..
+        } => {
+            names.push(name.clone());
+            let_names(value, names);
+            let_names(body, names);
+        }
+    }
+}
+
+/// Replaces the variables named in `replacements` where they are not
+/// shadowed by a `let`.
+fn substitute(node: &mut ExprNode, replacements: &[(String, ExprNode)]) {
+    match &mut node.kind {
+        ExprKind::Number(_) => {}
+        ExprKind::Variable(name) => {
+            if let Some((_, replacement)) = replacements.iter().find(|(var, _)| var == name) {
+                *node = replacement.clone();
+            }
+        }
+        ExprKind::BinaryExpr { lhs, rhs, .. } => {
+            substitute(lhs, replacements);
+            substitute(rhs, replacements);
+        }
+        ExprKind::UnaryExpr { rhs, .. } => substitute(rhs, replacements),
+        ExprKind::CallExpr { args, .. } => {
+            for arg in args.iter_mut() {
+                substitute(arg, replacements);
+            }
+        }
+        ExprKind::IfExpr {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            substitute(cond, replacements);
+            substitute(then_branch, replacements);
+            substitute(else_branch, replacements);
+        }
+        ExprKind::LetExpr {
+            name, value, body, ..
+        } => {
+            substitute(value, replacements);
+            let unshadowed: Vec<(String, ExprNode)> = replacements
+                .iter()
+                .filter(|(var, _)| var != name)
+                .cloned()
+                .collect();
+            substitute(body, &unshadowed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+    use crate::optimizer::function_wat;
+    use crate::optimizer::optimize;
+    use crate::{check, compile, CompileOptions};
+
+    #[test]
+    fn it_inlines_small_functions() {
+        let source = "def discr(a b c) b * b - 4 * a * c;
+                      def f(a b) discr(a, b, 1) + discr(b, a + 1, b * 2);";
+
+        assert_eq!(
+            function_wat(source, "f", 2),
+            "(func $f (param $a f32) (param $b f32) (result f32)\n\
+             (local $discr.b_1 f32)\n(local $discr.c_2 f32)\n\
+             local.get $b\nlocal.get $b\nf32.mul\nf32.const 4\nlocal.get $a\nf32.mul\n\
+             f32.sub\n\
+             local.get $a\nf32.const 1\nf32.add\nlocal.set $discr.b_1\n\
+             local.get $b\nf32.const 2\nf32.mul\nlocal.set $discr.c_2\n\
+             local.get $discr.b_1\nlocal.get $discr.b_1\nf32.mul\n\
+             f32.const 4\nlocal.get $b\nf32.mul\nlocal.get $discr.c_2\nf32.mul\n\
+             f32.sub\nf32.add\n)\n"
+        );
+        assert!(function_wat(source, "f", 1).contains("call $discr"));
+    }
+
+    #[test]
+    fn it_follows_inline_attributes() {
+        let source = "@noinline def sq(x) x * x;
+                      @inline def poly(x) 1 + x * (2 + x * (3 + x * (4 + x * (5 + x * 6))));
+                      def f(x) sq(x) + poly(x);";
+
+        assert!(function_wat(source, "f", 2).contains("call $sq"));
+        assert!(!function_wat(source, "f", 1).contains("call $poly"));
+        assert!(function_wat(source, "f", 0).contains("call $poly"));
+    }
+
+    #[test]
+    fn it_never_inlines_recursion() {
+        let source = "@inline def fib(x) if x < 2 then x else fib(x - 1) + fib(x - 2);
+                      def even(n) if n == 0 then 1 else odd(n - 1);
+                      def odd(n) if n == 0 then 0 else even(n - 1);
+                      def f(x) fib(x) + even(x);";
+        let options = CompileOptions::default().emit_wasm(false).opt_level(2);
+        let artifact = compile(source, &options).unwrap();
+
+        assert!(function_wat(source, "f", 2).contains("call $fib\nlocal.get $x\ncall $even"));
+        assert!(function_wat(source, "even", 2).contains("call $odd"));
+        assert_eq!(artifact.warnings.len(), 1);
+        assert_eq!(
+            artifact.warnings[0].message,
+            "Function 'fib' is recursive and cannot be inlined"
+        );
+    }
+
+    #[test]
+    fn it_keeps_constants_from_being_captured() {
+        // Inlined into g, f reads the value of the constant, not the parameter k.
+        let source = "@export const k = 2; def f(x) x * k; @export def g(k) f(k + 1); @export def h(x) f(x);";
+
+        assert!(function_wat(source, "g", 2).contains("local.get $f.x_1\nf32.const 2\nf32.mul"));
+        assert!(function_wat(source, "h", 2).contains("local.get $x\nf32.const 2\nf32.mul"));
+    }
+
+    #[test]
+    fn it_preserves_results() {
+        let source = "def pos(x) x > 0;
+                      def sq(x) let y = x * x in y;
+                      def f(x y) let y = sq(y) in pos(x) + sq(x + y) + pos(sq(-x)) * 10;";
+        let asts = check(source).unwrap().get_asts().clone();
+        let mut optimized = asts.clone();
+        optimize(&mut optimized, 2);
+
+        for (x, y) in [(1.0, 2.0), (-3.0, 0.5), (0.0, -0.0)] {
+            assert_eq!(
+                Interpreter::new(&optimized).call("f", &[x, y]),
+                Interpreter::new(&asts).call("f", &[x, y])
+            );
+        }
+        let Some(f) = optimized.last().unwrap().as_definition() else {
+            panic!("f is a definition");
+        };
+        assert!(!f.get_body().calls_definitions());
+    }
+}
@@ -1,7 +1,12 @@
-use crate::ast::{Ast, ExprNode, Function, Prototype};
+use crate::ast::{
+    Ast, Attribute, Const, ExprKind, ExprNode, Extern, Function, Import, Parameter, Prototype,
+    DEFAULT_IMPORT_MODULE,
+};
+use crate::diagnostic::Diagnostic;
 use crate::error_logger::ErrorLogger;
-use crate::lexer::Lexer;
-use crate::operator::Operator;
+use crate::lexer::{parse_number, Lexer, NumberError};
+use crate::operator::{Associativity, OperatorInfo};
+use crate::span::{FileId, Span};
 use crate::token::Token;
 
 pub struct Parser<T>
@@ -10,7 +15,9 @@ where
 {
     lexer: Lexer<T>,
     token: Token,
+    prev_span: Span,
     asts: Vec<Ast>,
+    exprs: Vec<ExprNode>,
     err_logger: ErrorLogger,
 }
 
@@ -22,12 +29,21 @@ where
         Self {
             lexer: Lexer::new(src),
             token: Token::None,
+            prev_span: Span::default(),
             err_logger: ErrorLogger::new(),
             asts: vec![],
+            exprs: vec![],
         }
     }
 
+    /// Marks the spans of the parsed source as belonging to `file`.
+    pub fn in_file(mut self, file: FileId) -> Self {
+        self.lexer.file = file;
+        self
+    }
+
     fn get_token(&mut self) {
+        self.prev_span = self.lexer.span;
         self.token = self.lexer.get_token();
     }
 
@@ -35,6 +51,20 @@ where
         &self.asts
     }
 
+    pub fn get_asts_mut(&mut self) -> &mut Vec<Ast> {
+        &mut self.asts
+    }
+
+    /// Top-level expressions, only accepted by `repl_loop`.
+    pub fn get_expressions(&self) -> &Vec<ExprNode> {
+        &self.exprs
+    }
+
+    /// Comments of the source, which are otherwise dropped by the lexer.
+    pub fn get_comments(&self) -> &Vec<(Span, String)> {
+        &self.lexer.comments
+    }
+
     pub fn get_error_logger(&self) -> &ErrorLogger {
         &self.err_logger
     }
@@ -43,62 +73,82 @@ where
         self.lexer.lexeme.clone()
     }
 
-    pub fn push_error(&mut self, msg: &str) {
-        self.err_logger.push(self.lexer.get_line_numer(), msg);
+    /// Span of the current token.
+    pub fn span(&self) -> Span {
+        self.lexer.span
     }
 
-    fn parse_expression(&mut self) -> Option<ExprNode> {
-        let mut node = self.parse_subexpression()?;
-
-        while self.token.is_comparison_operator() {
-            let op = Operator::from(&self.token);
-
-            self.get_token();
+    /// Reports an error at the current token.
+    pub fn push_error(&mut self, code: &'static str, msg: &str) {
+        let label = format!("unexpected {}", self.token.describe());
+        let diagnostic = Diagnostic::error(msg)
+            .with_code(code)
+            .with_span(self.span())
+            .with_label(&label);
 
-            let rhs = self.parse_subexpression()?;
-            node = ExprNode::create_binary_op(op, node, rhs);
-        }
-        Some(node)
+        self.err_logger.push_diagnostic(diagnostic);
     }
 
-    fn parse_subexpression(&mut self) -> Option<ExprNode> {
-        let mut node = self.parse_term()?;
+    fn parse_expression(&mut self) -> Option<ExprNode> {
+        self.parse_binary(0)
+    }
 
-        while self.token.is_addition_operator() {
-            let op = Operator::from(&self.token);
+    /// Precedence climbing over the operator table: parses an expression whose
+    /// binary operators have at least `min_precedence`.
+    fn parse_binary(&mut self, min_precedence: u8) -> Option<ExprNode> {
+        let mut node = self.parse_prefix()?;
 
+        while let Some(info) = OperatorInfo::binary(&self.token) {
+            if info.precedence < min_precedence {
+                break;
+            }
             self.get_token();
 
-            let rhs = self.parse_term()?;
-            node = ExprNode::create_binary_op(op, node, rhs);
+            let rhs_precedence = match info.associativity {
+                Associativity::Right => info.precedence,
+                Associativity::Left | Associativity::None => info.precedence + 1,
+            };
+            let rhs = self.parse_binary(rhs_precedence)?;
+            node = ExprNode::create_binary_op(info.operator, node, rhs);
+
+            if info.associativity == Associativity::None
+                && OperatorInfo::binary(&self.token)
+                    .is_some_and(|next| next.precedence == info.precedence)
+            {
+                self.push_chained_operator_error();
+                return None;
+            }
+        }
+
+        if self.token == Token::Assign {
+            self.push_stray_assign_error();
+            return None;
         }
 
         Some(node)
     }
 
-    fn parse_term(&mut self) -> Option<ExprNode> {
-        let mut node = self.parse_factor()?;
-
-        while self.token.is_multiplication_operator() {
-            let op = Operator::from(&self.token);
-
-            self.get_token();
-
-            let rhs = self.parse_factor()?;
-            node = ExprNode::create_binary_op(op, node, rhs);
-        }
+    fn parse_prefix(&mut self) -> Option<ExprNode> {
+        let Some(info) = OperatorInfo::prefix(&self.token) else {
+            return self.parse_factor();
+        };
+        let span = self.span();
+        self.get_token();
 
-        Some(node)
+        let rhs = self.parse_binary(info.precedence)?;
+        Some(ExprNode::create_unary_op(info.operator, rhs, span))
     }
 
     fn parse_factor(&mut self) -> Option<ExprNode> {
         match self.token {
-            Token::Minus => self.parse_neg_expr(),
             Token::Identifier => self.parse_identifier_expr(),
             Token::Lpar => self.parse_paren_expr(),
             Token::Number => self.parse_number_expr(),
+            // Like a negation, these extend as far to the right as possible.
+            Token::If => self.parse_if_expr(),
+            Token::Let => self.parse_let_expr(),
             _ => {
-                self.push_error("Expected identifier or number");
+                self.push_error("E0001", "Expected identifier or number");
                 None
             }
         }
@@ -106,10 +156,11 @@ where
 
     fn parse_identifier_expr(&mut self) -> Option<ExprNode> {
         let id_name = self.lexeme();
+        let id_span = self.span();
 
         self.get_token();
         if self.token != Token::Lpar {
-            return Some(ExprNode::Variable(id_name));
+            return Some(ExprNode::create_variable(id_name, id_span));
         }
 
         self.get_token();
@@ -128,7 +179,7 @@ where
                 }
 
                 if self.token != Token::Comma {
-                    self.push_error("Expected ')' or ',' in argument list");
+                    self.push_error("E0002", "Expected ')' or ',' in argument list");
 
                     return None;
                 }
@@ -136,45 +187,56 @@ where
             }
         }
 
+        let span = id_span.to(self.span());
         self.get_token();
 
-        Some(ExprNode::create_call(id_name, args))
+        Some(ExprNode::create_call(id_name, args, span))
     }
 
     fn parse_number_expr(&mut self) -> Option<ExprNode> {
-        let node = ExprNode::Number(self.lexeme().parse().unwrap());
+        let literal = self.lexeme();
+        let number = match parse_number(&literal) {
+            Ok(number) => number,
+            Err(err) => {
+                self.push_number_error(&literal, err);
+                // Keep parsing with a placeholder to report further errors.
+                0.0
+            }
+        };
+
+        let node = ExprNode::create_number(number, self.span());
         self.get_token();
         Some(node)
     }
 
     fn parse_paren_expr(&mut self) -> Option<ExprNode> {
+        let span = self.span();
         self.get_token();
 
-        let node = self.parse_expression()?;
+        let mut node = self.parse_expression()?;
 
         if self.token != Token::Rpar {
-            self.push_error("Missing ')'");
+            self.push_error("E0003", "Missing ')'");
 
             return None;
         }
+        // A number keeps the span of its literal, which the formatter prints
+        // as written.
+        if !matches!(node.kind, ExprKind::Number(_)) {
+            node.span = span.to(self.span());
+        }
         self.get_token();
 
         Some(node)
     }
 
-    fn parse_neg_expr(&mut self) -> Option<ExprNode> {
-        self.get_token();
-
-        let node = self.parse_expression()?;
-        Some(ExprNode::create_unary_op(Operator::Neg, node))
-    }
-
     fn parse_if_expr(&mut self) -> Option<ExprNode> {
+        let span = self.span();
         self.get_token();
         let cond = self.parse_expression()?;
 
         if self.token != Token::Then {
-            self.push_error("Expected 'then'");
+            self.push_error("E0004", "Expected 'then'");
             return None;
         }
 
@@ -182,7 +244,7 @@ where
         let then_branch = self.parse_expression()?;
 
         if self.token != Token::Else {
-            self.push_error("Expected 'else'");
+            self.push_error("E0005", "Expected 'else'");
             return None;
         }
 
@@ -193,32 +255,230 @@ where
             cond,
             then_branch,
             else_branch,
+            span,
         ))
     }
 
+    fn parse_let_expr(&mut self) -> Option<ExprNode> {
+        let span = self.span();
+        self.get_token();
+
+        if self.token != Token::Identifier {
+            self.push_error("E0011", "Expected variable name after 'let'");
+            return None;
+        }
+        if !self.check_plain_name() {
+            return None;
+        }
+        let name = self.lexeme();
+        let name_span = self.span();
+
+        self.get_token();
+        if self.token != Token::Assign {
+            self.push_error("E0012", "Expected '=' after variable name");
+            return None;
+        }
+
+        self.get_token();
+        let value = self.parse_expression()?;
+
+        if self.token != Token::In {
+            self.push_error("E0013", "Expected 'in'");
+            return None;
+        }
+
+        self.get_token();
+        let body = self.parse_expression()?;
+
+        Some(ExprNode::create_let(name, name_span, value, body, span))
+    }
+
+    /// A definition or constant with the attributes before it.
+    fn parse_item(&mut self) -> Option<Ast> {
+        let attributes = self.parse_attributes()?;
+
+        match self.token {
+            Token::Define => Some(Ast::Definition(
+                self.parse_definition()?.with_attributes(attributes),
+            )),
+            Token::Const => Some(Ast::Const(self.parse_const()?.with_attributes(attributes))),
+            _ => {
+                self.push_error("E0022", "Expected 'def' or 'const' after attributes");
+                None
+            }
+        }
+    }
+
     fn parse_definition(&mut self) -> Option<Function> {
+        let span = self.span();
         self.get_token();
         let proto = self.parse_prototype()?;
+        let expr_node = self.parse_expression()?;
 
-        let expr_node = match self.token {
-            Token::If => self.parse_if_expr()?,
-            _ => self.parse_expression()?,
-        };
+        let span = span.to(expr_node.span);
+        Some(Function::new(proto, expr_node, span))
+    }
+
+    fn parse_const(&mut self) -> Option<Const> {
+        let span = self.span();
+        self.get_token();
+
+        if self.token != Token::Identifier {
+            self.push_error("E0023", "Expected constant name after 'const'");
+            return None;
+        }
+        if !self.check_plain_name() {
+            return None;
+        }
+        let name = self.lexeme();
+        let name_span = self.span();
+
+        self.get_token();
+        if self.token != Token::Assign {
+            self.push_error("E0012", "Expected '=' after constant name");
+            return None;
+        }
+
+        self.get_token();
+        let expr = self.parse_expression()?;
 
-        Some(Function::new(proto, expr_node))
+        let span = span.to(expr.span);
+        Some(Const::new(name, name_span, expr, span))
+    }
+
+    /// Any number of `@name` or `@name("arg")`.
+    fn parse_attributes(&mut self) -> Option<Vec<Attribute>> {
+        let mut attributes = vec![];
+
+        while self.token == Token::At {
+            let span = self.span();
+            self.get_token();
+            if self.token != Token::Identifier {
+                self.push_error("E0020", "Expected attribute name after '@'");
+                return None;
+            }
+            let name = self.lexeme();
+            let mut end = self.span();
+            self.get_token();
+
+            let mut arg = None;
+            if self.token == Token::Lpar {
+                self.get_token();
+                arg = Some(self.parse_string("E0021", "Expected string argument in attribute")?);
+                if self.token != Token::Rpar {
+                    self.push_error("E0003", "Missing ')'");
+                    return None;
+                }
+                end = self.span();
+                self.get_token();
+            }
+
+            attributes.push(Attribute {
+                name,
+                arg,
+                span: span.to(end),
+            });
+        }
+
+        Some(attributes)
+    }
+
+    /// `extern [def] name(params) [from "module"] [as "field"]`, where `from`
+    /// and `as` are only keywords in this position.
+    fn parse_extern(&mut self) -> Option<Extern> {
+        let span = self.span();
+        self.get_token();
+        if self.token == Token::Define {
+            self.get_token();
+        }
+        let proto = self.parse_prototype()?;
+
+        let mut module = String::from(DEFAULT_IMPORT_MODULE);
+        if self.is_contextual_keyword("from") {
+            self.get_token();
+            module = self.parse_string("E0018", "Expected module name string after 'from'")?;
+        }
+
+        let mut field = proto.get_name().to_owned();
+        if self.is_contextual_keyword("as") {
+            self.get_token();
+            field = self.parse_string("E0019", "Expected import name string after 'as'")?;
+        }
+
+        let span = span.to(self.prev_span);
+        Some(Extern::new(proto, module, field, span))
+    }
+
+    /// `import "path" [as name]`.
+    fn parse_import(&mut self) -> Option<Import> {
+        let span = self.span();
+        self.get_token();
+        let path = self.parse_string("E0024", "Expected file name string after 'import'")?;
+
+        let mut alias = None;
+        if self.is_contextual_keyword("as") {
+            self.get_token();
+            if self.token != Token::Identifier {
+                self.push_error("E0026", "Expected namespace name after 'as'");
+                return None;
+            }
+            if !self.check_plain_name() {
+                return None;
+            }
+            alias = Some(self.lexeme());
+            self.get_token();
+        }
+
+        let span = span.to(self.prev_span);
+        Some(Import { path, alias, span })
+    }
+
+    /// Reports a declared name that is qualified like `geometry.area`. Only
+    /// names referring to the items of an import can be qualified.
+    fn check_plain_name(&mut self) -> bool {
+        let name = self.lexeme();
+        if !name.contains('.') {
+            return true;
+        }
+
+        let diagnostic = Diagnostic::error(&format!("Name '{}' cannot contain '.'", name))
+            .with_code("E0025")
+            .with_span(self.span())
+            .with_label("qualified names only refer to imported items");
+        self.err_logger.push_diagnostic(diagnostic);
+        false
+    }
+
+    fn is_contextual_keyword(&mut self, keyword: &str) -> bool {
+        self.token == Token::Identifier && self.lexeme() == keyword
+    }
+
+    fn parse_string(&mut self, code: &'static str, msg: &str) -> Option<String> {
+        if self.token != Token::String {
+            self.push_error(code, msg);
+            return None;
+        }
+
+        let string = self.lexeme();
+        self.get_token();
+        Some(string)
     }
 
     fn parse_prototype(&mut self) -> Option<Prototype> {
         if self.token != Token::Identifier {
-            self.push_error("Expected function name in prototype");
+            self.push_error("E0006", "Expected function name in prototype");
+            return None;
+        }
+        if !self.check_plain_name() {
             return None;
         }
 
         let id_name = self.lexeme();
+        let id_span = self.span();
 
         self.get_token();
         if self.token != Token::Lpar {
-            self.push_error("Expected '(' in prototype");
+            self.push_error("E0007", "Expected '(' in prototype");
             return None;
         }
 
@@ -226,17 +486,87 @@ where
 
         let mut args = vec![];
         while self.token == Token::Identifier {
-            args.push(self.lexeme());
+            if !self.check_plain_name() {
+                return None;
+            }
+            args.push(Parameter {
+                name: self.lexeme(),
+                span: self.span(),
+            });
             self.get_token();
         }
 
         if self.token != Token::Rpar {
-            self.push_error("Expected ')' in prototype");
+            self.push_error("E0008", "Expected ')' in prototype");
             return None;
         }
 
+        let span = id_span.to(self.span());
         self.get_token();
-        Some(Prototype::new(id_name, args))
+        Some(Prototype::new(id_name, args, id_span, span))
+    }
+
+    fn push_chained_operator_error(&mut self) {
+        let diagnostic = Diagnostic::error("Comparison operators cannot be chained")
+            .with_code("E0014")
+            .with_span(self.span())
+            .with_label("cannot follow another comparison")
+            .with_help(
+                "compare once and combine the results with `and`, e.g. `(a < b) and (b < c)`",
+            );
+
+        self.err_logger.push_diagnostic(diagnostic);
+    }
+
+    fn push_number_error(&mut self, literal: &str, err: NumberError) {
+        let diagnostic = match err {
+            NumberError::Malformed => {
+                Diagnostic::error(&format!("Invalid number literal `{}`", literal))
+                    .with_code("E0016")
+                    .with_label("not a number")
+                    .with_help(
+                        "numbers look like `12`, `1.5`, `.5`, `1e-6`, `1_000`, `0xFF` or `0b101`",
+                    )
+            }
+            NumberError::OutOfRange => {
+                Diagnostic::error(&format!("Number literal `{}` is out of range", literal))
+                    .with_code("E0017")
+                    .with_label("not representable as a 32-bit float")
+            }
+        };
+
+        self.err_logger
+            .push_diagnostic(diagnostic.with_span(self.span()));
+    }
+
+    fn push_stray_assign_error(&mut self) {
+        let diagnostic = Diagnostic::error("Unexpected '=' in expression")
+            .with_code("E0015")
+            .with_span(self.span())
+            .with_label("`=` only binds a name in `let`")
+            .with_help("use `==` to compare values");
+
+        self.err_logger.push_diagnostic(diagnostic);
+    }
+
+    fn push_missing_semicolon(&mut self) {
+        // Point right after the end of the definition rather than at the
+        // next token, which is usually on another line.
+        let end = self.prev_span.end;
+        let span = Span::new(
+            end,
+            end,
+            self.prev_span.line,
+            self.prev_span.column + self.prev_span.len(),
+        )
+        .in_file(self.prev_span.file);
+        let diagnostic = Diagnostic::error("Missing ';'")
+            .with_code("E0010")
+            .with_span(span)
+            .with_label("expected `;`")
+            .with_help("every definition ends with a semicolon");
+
+        self.err_logger.push_diagnostic(diagnostic);
     }
 
     fn synchronize(&mut self, tokens: Vec<Token>) {
@@ -245,32 +575,418 @@ where
         }
     }
 
-    fn handle_definition(&mut self) {
-        if let Some(node) = self.parse_definition() {
-            self.asts.push(Ast::Definition(node))
+    fn handle_item(&mut self) {
+        if let Some(node) = self.parse_item() {
+            self.asts.push(node)
+        } else {
+            self.synchronize(vec![Token::Eof, Token::Semicolon]);
+        }
+    }
+
+    fn handle_import(&mut self) {
+        if let Some(node) = self.parse_import() {
+            self.asts.push(Ast::Import(node))
+        } else {
+            self.synchronize(vec![Token::Eof, Token::Semicolon]);
+        }
+    }
+
+    fn handle_extern(&mut self) {
+        if let Some(node) = self.parse_extern() {
+            self.asts.push(Ast::Extern(node))
+        } else {
+            self.synchronize(vec![Token::Eof, Token::Semicolon]);
+        }
+    }
+
+    fn handle_expression(&mut self) {
+        if let Some(node) = self.parse_expression() {
+            self.exprs.push(node)
         } else {
             self.synchronize(vec![Token::Eof, Token::Semicolon]);
         }
     }
 
     pub fn main_loop(&mut self) {
+        self.parse_items(false);
+    }
+
+    /// Like `main_loop`, but also accepts bare expressions terminated by
+    /// `;`, which are collected in `get_expressions`.
+    pub fn repl_loop(&mut self) {
+        self.parse_items(true);
+    }
+
+    fn parse_items(&mut self, allow_expressions: bool) {
         self.get_token();
 
         loop {
             match self.token {
                 Token::Eof => break,
-                Token::Define => self.handle_definition(),
+                Token::Define | Token::Const | Token::At => self.handle_item(),
+                Token::Extern => self.handle_extern(),
+                Token::Import => self.handle_import(),
+                _ if allow_expressions => self.handle_expression(),
                 _ => {
-                    self.push_error("Expected 'def'");
+                    self.push_error("E0009", "Expected 'def'");
                     self.synchronize(vec![Token::Eof, Token::Semicolon]);
                 }
             }
 
             if self.token != Token::Semicolon {
-                self.push_error("Missing ';'");
+                self.push_missing_semicolon();
             } else {
                 self.get_token();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::ExprKind;
+    use crate::operator::Operator;
+    use std::io::Cursor;
+
+    fn parse(src: &str) -> Parser<Cursor<&str>> {
+        let mut parser = Parser::new(Cursor::new(src));
+        parser.main_loop();
+        parser
+    }
+
+    fn first_function<'a>(parser: &'a Parser<Cursor<&str>>) -> &'a Function {
+        let func = parser.get_asts()[0].as_definition().unwrap();
+        func
+    }
+
+    #[test]
+    fn it_attaches_spans_to_definitions() {
+        let parser = parse("def add(a b)\n  a + b;");
+        let func = first_function(&parser);
+
+        assert_eq!(func.get_span(), Span::new(0, 20, 1, 1));
+        assert_eq!(func.get_proto().get_span(), Span::new(4, 12, 1, 5));
+        assert_eq!(func.get_proto().get_name_span(), Span::new(4, 7, 1, 5));
+        assert_eq!(func.get_params()[1].span, Span::new(10, 11, 1, 11));
+        assert_eq!(func.get_body().span, Span::new(15, 20, 2, 3));
+    }
+
+    #[test]
+    fn it_attaches_spans_to_expressions() {
+        let parser = parse("def f(x) if x then -g(x, 2) else 1;");
+        let func = first_function(&parser);
+
+        let ExprKind::IfExpr {
+            cond, then_branch, ..
+        } = &func.get_body().kind
+        else {
+            panic!("expected if expression");
+        };
+        assert_eq!(func.get_body().span, Span::new(9, 34, 1, 10));
+        assert_eq!(cond.span, Span::new(12, 13, 1, 13));
+
+        let ExprKind::UnaryExpr { rhs, .. } = &then_branch.kind else {
+            panic!("expected negation");
+        };
+        assert_eq!(then_branch.span, Span::new(19, 27, 1, 20));
+        assert_eq!(rhs.span, Span::new(20, 27, 1, 21));
+    }
+
+    #[test]
+    fn it_parses_expressions_in_repl_mode() {
+        let mut parser = Parser::new(Cursor::new(
+            "def sq(x) x*x; sq(2) + 1; if 1 < 2 then 3 else 4;",
+        ));
+        parser.repl_loop();
+
+        assert!(!parser.get_error_logger().has_errors());
+        assert_eq!(parser.get_asts().len(), 1);
+        assert_eq!(parser.get_expressions().len(), 2);
+
+        let parser = parse("sq(2);");
+        assert_eq!(
+            parser.get_error_logger()[0].message,
+            String::from("Expected 'def'")
+        );
+    }
+
+    #[test]
+    fn it_parses_externs() {
+        let parser = parse("extern log(x);\nextern def pick(a b) from \"lib\" as \"choose\";");
+        assert!(!parser.get_error_logger().has_errors());
+
+        let Ast::Extern(log) = &parser.get_asts()[0] else {
+            panic!("expected extern");
+        };
+        assert_eq!(log.get_proto().to_string(), "log(x)");
+        assert_eq!((log.get_module(), log.get_field()), ("env", "log"));
+        assert_eq!(log.get_span(), Span::new(0, 13, 1, 1));
+
+        let Ast::Extern(pick) = &parser.get_asts()[1] else {
+            panic!("expected extern");
+        };
+        assert_eq!((pick.get_module(), pick.get_field()), ("lib", "choose"));
+        assert_eq!(pick.get_span(), Span::new(15, 58, 2, 1));
+    }
+
+    #[test]
+    fn it_reports_invalid_externs() {
+        let parser = parse("extern f(x) from env; extern g() as \"h\n;");
+        let errors = parser.get_error_logger();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, Some("E0018"));
+        assert_eq!(errors[1].code, Some("E0019"));
+    }
+
+    #[test]
+    fn it_parses_attributes() {
+        let parser = parse("@export @export(\"area\")\ndef f(x) x;");
+        assert!(!parser.get_error_logger().has_errors());
+        let func = parser.get_asts()[0].as_definition().unwrap();
+
+        let attributes = func.get_attributes();
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(
+            (attributes[0].name.as_str(), attributes[0].arg.as_deref()),
+            ("export", None)
+        );
+        assert_eq!(attributes[1].arg.as_deref(), Some("area"));
+        assert_eq!(attributes[1].span, Span::new(8, 23, 1, 9));
+        assert_eq!(func.get_span(), Span::new(24, 34, 2, 1));
+
+        let parser = parse("@export(area) def f(x) x; @ def g() 1; @inline extern h();");
+        let codes: Vec<_> = parser.get_error_logger().iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec![Some("E0021"), Some("E0020"), Some("E0022")]);
+    }
+
+    #[test]
+    fn it_parses_constants() {
+        let parser = parse("const g = 9.81;\n@export const two = g * 2;");
+        assert!(!parser.get_error_logger().has_errors());
+
+        let g = parser.get_asts()[0].as_const().unwrap();
+        assert_eq!(g.get_name(), "g");
+        assert_eq!(g.get_name_span(), Span::new(6, 7, 1, 7));
+        assert_eq!(g.get_span(), Span::new(0, 14, 1, 1));
+
+        let two = parser.get_asts()[1].as_const().unwrap();
+        assert_eq!(two.get_export_name(), Some("two"));
+
+        let parser = parse("const = 1; const x 1;");
+        let codes: Vec<_> = parser.get_error_logger().iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec![Some("E0023"), Some("E0012")]);
+    }
+
+    #[test]
+    fn it_parses_imports() {
+        let parser = parse("import \"lib/geometry.ml\";\nimport \"stats.ml\" as s;");
+        assert!(!parser.get_error_logger().has_errors());
+
+        let Ast::Import(geometry) = &parser.get_asts()[0] else {
+            panic!("expected import");
+        };
+        assert_eq!(geometry.path, "lib/geometry.ml");
+        assert_eq!(geometry.get_namespace(), "geometry");
+        assert_eq!(geometry.span, Span::new(0, 24, 1, 1));
+        assert_eq!(parser.get_asts()[1].get_name(), "s");
+
+        let parser = parse("import geometry; def geometry.area(r) r; def f(a.b) 1;");
+        let codes: Vec<_> = parser.get_error_logger().iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec![Some("E0024"), Some("E0025"), Some("E0025")]);
+
+        let parser = parse("import \"stats.ml\" as 1;");
+        let codes: Vec<_> = parser.get_error_logger().iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec![Some("E0026")]);
+    }
+
+    #[test]
+    fn it_parses_let_bindings() {
+        let parser = parse("def f(x) let y = x * 2 in let z = y in if z < 1 then y else z;");
+        let func = first_function(&parser);
+
+        let ExprKind::LetExpr {
+            name,
+            name_span,
+            body,
+            ..
+        } = &func.get_body().kind
+        else {
+            panic!("expected let expression");
+        };
+        assert_eq!(name, "y");
+        assert_eq!(*name_span, Span::new(13, 14, 1, 14));
+        assert_eq!(func.get_body().span, Span::new(9, 61, 1, 10));
+        assert!(matches!(body.kind, ExprKind::LetExpr { .. }));
+
+        let parser = parse("def f(x) let y = x y;");
+        assert_eq!(parser.get_error_logger()[0].code, Some("E0013"));
+        let parser = parse("def f(x) let y x in y;");
+        assert_eq!(parser.get_error_logger()[0].code, Some("E0012"));
+    }
+
+    #[test]
+    fn it_parses_if_anywhere() {
+        let parser = parse(
+            "def f(x) 1 + if x < 0 then 0 else x;
+             def g(x) f(if x then 1 else 2, 3);
+             def h(x) if x < 0 then if x < 1 then 1 else 2 else if x > 3 then 3 else 4;",
+        );
+        assert!(!parser.get_error_logger().has_errors());
+
+        let func = parser.get_asts()[0].as_definition().unwrap();
+        let ExprKind::BinaryExpr { rhs, .. } = &func.get_body().kind else {
+            panic!("expected binary expression");
+        };
+        assert!(matches!(rhs.kind, ExprKind::IfExpr { .. }));
+        assert_eq!(rhs.span, Span::new(13, 35, 1, 14));
+
+        let func = parser.get_asts()[1].as_definition().unwrap();
+        let ExprKind::CallExpr { args, .. } = &func.get_body().kind else {
+            panic!("expected call");
+        };
+        assert_eq!(args.len(), 2);
+        assert!(matches!(args[0].kind, ExprKind::IfExpr { .. }));
+
+        let func = parser.get_asts()[2].as_definition().unwrap();
+        let ExprKind::IfExpr {
+            then_branch,
+            else_branch,
+            ..
+        } = &func.get_body().kind
+        else {
+            panic!("expected if expression");
+        };
+        assert!(matches!(then_branch.kind, ExprKind::IfExpr { .. }));
+        assert!(matches!(else_branch.kind, ExprKind::IfExpr { .. }));
+    }
+
+    /// Fully parenthesized form of the body of `def f(a b c) <expr>;`.
+    fn grouping(expr: &str) -> String {
+        fn group(node: &ExprNode) -> String {
+            match &node.kind {
+                ExprKind::Variable(name) => name.clone(),
+                ExprKind::Number(number) => number.to_string(),
+                ExprKind::BinaryExpr { op, lhs, rhs } => {
+                    format!("({} {} {})", group(lhs), op.symbol(), group(rhs))
+                }
+                ExprKind::UnaryExpr {
+                    op: Operator::Not,
+                    rhs,
+                } => format!("(not {})", group(rhs)),
+                ExprKind::UnaryExpr { op, rhs } => format!("({}{})", op.symbol(), group(rhs)),
+                _ => panic!("unexpected expression"),
+            }
+        }
+
+        let src = format!("def f(a b c) {};", expr);
+        let parser = parse(&src);
+        assert!(!parser.get_error_logger().has_errors(), "{}", expr);
+        group(first_function(&parser).get_body())
+    }
+
+    #[test]
+    fn it_applies_precedence_and_associativity() {
+        let cases = [
+            ("a + b * c", "(a + (b * c))"),
+            ("a * b + c", "((a * b) + c)"),
+            ("a - b / c", "(a - (b / c))"),
+            ("a / b - c", "((a / b) - c)"),
+            ("a | b & c", "(a | (b & c))"),
+            ("a & b | c", "((a & b) | c)"),
+            ("a + b & c", "(a + (b & c))"),
+            ("a * b | c", "((a * b) | c)"),
+            ("a < b + c", "(a < (b + c))"),
+            ("a + b > c", "((a + b) > c)"),
+            ("a == b * c", "(a == (b * c))"),
+            ("a * b <> c", "((a * b) <> c)"),
+            ("a < b | c", "(a < (b | c))"),
+            ("a - b - c", "((a - b) - c)"),
+            ("a / b / c", "((a / b) / c)"),
+            ("a - b + c", "((a - b) + c)"),
+            ("a / b * c", "((a / b) * c)"),
+            ("-a + b", "((-a) + b)"),
+            ("-a * b", "((-a) * b)"),
+            ("a * -b", "(a * (-b))"),
+            ("a - -b", "(a - (-b))"),
+            ("--a", "(-(-a))"),
+            ("-a < b", "((-a) < b)"),
+            ("-(a + b)", "(-(a + b))"),
+            ("a <= b + c", "(a <= (b + c))"),
+            ("a * b >= c", "((a * b) >= c)"),
+            ("a != b", "(a <> b)"),
+            ("a < b and b < c", "((a < b) and (b < c))"),
+            ("a or b and c", "(a or (b and c))"),
+            ("a and b or c", "((a and b) or c)"),
+            ("a or b or c", "((a or b) or c)"),
+            ("a and b | c", "(a and (b | c))"),
+            ("not a < b", "(not (a < b))"),
+            ("not a and b", "((not a) and b)"),
+            ("!a or b", "((not a) or b)"),
+            ("not -a < b", "(not ((-a) < b))"),
+            ("not not a", "(not (not a))"),
+            ("a % b * c", "((a % b) * c)"),
+            ("a + b % c", "(a + (b % c))"),
+            ("a ^ b ^ c", "(a ^ (b ^ c))"),
+            ("a ** b", "(a ^ b)"),
+            ("a * b ^ c", "(a * (b ^ c))"),
+            ("a ^ b * c", "((a ^ b) * c)"),
+            ("-a ^ b", "(-(a ^ b))"),
+            ("a ^ -b", "(a ^ (-b))"),
+            ("(-a) ^ b", "((-a) ^ b)"),
+        ];
+
+        for (expr, expected) in cases {
+            assert_eq!(grouping(expr), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn it_rejects_chained_comparisons() {
+        for expr in ["a < b < c", "a == b < c", "a <> b == c", "a <= b >= c"] {
+            let src = format!("def f(a b c) {};", expr);
+            let parser = parse(&src);
+            assert_eq!(parser.get_error_logger()[0].code, Some("E0014"), "{}", expr);
+            assert_eq!(
+                parser.get_error_logger()[0].help.as_deref(),
+                Some("compare once and combine the results with `and`, e.g. `(a < b) and (b < c)`")
+            );
+        }
+        assert_eq!(grouping("(a < b) == (b < c)"), "((a < b) == (b < c))");
+    }
+
+    #[test]
+    fn it_rejects_stray_assignment() {
+        let parser = parse("def f(x) if x = 1 then 1 else 0;");
+        let error = &parser.get_error_logger()[0];
+
+        assert_eq!(error.code, Some("E0015"));
+        assert_eq!(error.span, Some(Span::new(14, 15, 1, 15)));
+        assert_eq!(error.help.as_deref(), Some("use `==` to compare values"));
+    }
+
+    #[test]
+    fn it_reports_invalid_number_literals() {
+        let parser = parse("def f(x) x + 1__0 * 1e40;");
+        let errors = parser.get_error_logger();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, Some("E0016"));
+        assert_eq!(errors[0].span, Some(Span::new(13, 17, 1, 14)));
+        assert_eq!(errors[1].code, Some("E0017"));
+        assert_eq!(errors[1].span, Some(Span::new(20, 24, 1, 21)));
+    }
+
+    #[test]
+    fn it_reports_error_location() {
+        let parser = parse("def f(x)\n  x + ;");
+        let error = &parser.get_error_logger()[0];
+
+        assert_eq!(error.span, Some(Span::new(15, 16, 2, 7)));
+        assert_eq!(
+            error.to_string(),
+            "error[E0001]: Expected identifier or number, line 2, column 7"
+        );
+    }
+}
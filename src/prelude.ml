@@ -0,0 +1,128 @@
+# The prelude: functions every program can call without defining them.
+# Only the definitions a program uses are compiled into it.
+
+const nan = 0 / 0;
+const inf = 1 / 0;
+const pi = 3.14159265358979;
+const halfPi = 1.57079632679490;
+
+# ln(2) and pi / 2 split so that multiplying the first part by a small
+# integer is exact.
+const ln2Hi = 0.693138122558593750;
+const ln2Lo = 9.05800061445e-06;
+const invLn2 = 1.44269504088896;
+const halfPiA = 1.5703125;
+const halfPiB = 4.83512878418e-04;
+const halfPiC = 3.13855707645e-07;
+const halfPiD = 6.07710050650e-11;
+const twoOverPi = 0.636619772367581;
+const sqrtHalf = 0.707106781186548;
+const sqrt2 = 1.41421356237310;
+const twoTo32 = 2 ^ 32;
+const twoToMinus32 = 2 ^ -32;
+
+# A NaN argument is ignored, like by f32::min and f32::max.
+def min(a b) if a < b or b != b then a else b;
+
+def max(a b) if a > b or b != b then a else b;
+
+def clamp(x lo hi) min(max(x, lo), hi);
+
+# -1, 0 or 1.
+def sign(x)
+  if x > 0 then 1
+  else if x < 0 then -1
+  else x;
+
+def exp(x)
+  if x > 88.72284 then inf
+  else if x < -103.97208 then 0
+  else if x != x then x
+  else
+    let k = nearest(x * invLn2) in
+    let r = x - k * ln2Hi - k * ln2Lo in
+    let half = floor(k / 2) in
+    expTaylor(r) * 2 ^ half * 2 ^ (k - half);
+
+# e^r for |r| <= ln(2) / 2.
+def expTaylor(r)
+  1 + r * (1 + r * (0.5 + r * (0.16666667 + r * (0.041666668 + r * (0.008333334
+    + r * (0.0013888889 + r * 0.0001984127))))));
+
+def ln(x)
+  if x < 0 or x != x then nan
+  else if x == 0 then -inf
+  else if x == inf then x
+  else lnReduce(x, 0);
+
+# ln(m * 2^e), halving or doubling m into [sqrt(1/2), sqrt(2)].
+def lnReduce(m e)
+  if m > twoTo32 then lnReduce(m * twoToMinus32, e + 32)
+  else if m < twoToMinus32 then lnReduce(m * twoTo32, e - 32)
+  else if m > sqrt2 then lnReduce(m / 2, e + 1)
+  else if m < sqrtHalf then lnReduce(m * 2, e - 1)
+  else
+    let s = (m - 1) / (m + 1) in
+    let s2 = s * s in
+    e * ln2Hi + (e * ln2Lo + 2 * s * (1 + s2 * (0.33333334 + s2 * (0.2 + s2 * (0.14285715
+      + s2 * 0.11111111)))));
+
+# Integral exponents are exact for any x; otherwise x must not be negative.
+def pow(x y)
+  if y == trunc(y) and abs(y) < inf then x ^ y
+  else if x < 0 then nan
+  else exp(y * ln(x));
+
+def sin(x) sinQuadrant(x, 0);
+
+def cos(x) sinQuadrant(x, 1);
+
+# sin(x + q * pi / 2).
+def sinQuadrant(x q)
+  let k = nearest(x * twoOverPi) in
+  let r = x - k * halfPiA - k * halfPiB - k * halfPiC - k * halfPiD in
+  let n = (k + q) % 4 in
+  if n == 0 then sinTaylor(r)
+  else if n == 1 then cosTaylor(r)
+  else if n == 2 then -sinTaylor(r)
+  else -cosTaylor(r);
+
+# sin(r) and cos(r) for |r| <= pi / 4.
+def sinTaylor(r)
+  let r2 = r * r in
+  r + r * r2 * (-0.16666667 + r2 * (0.008333334 + r2 * (-0.0001984127 + r2 * 0.0000027557319)));
+
+def cosTaylor(r)
+  let r2 = r * r in
+  1 + r2 * (-0.5 + r2 * (0.041666668 + r2 * (-0.0013888889 + r2 * 0.0000248015873)));
+
+def atan2(y x)
+  if x > 0 then atan(y / x)
+  else if x < 0 then (if y < 0 then atan(y / x) - pi else atan(y / x) + pi)
+  else if y > 0 then halfPi
+  else if y < 0 then -halfPi
+  else x + y;
+
+def atan(z)
+  if z > 1 then halfPi - atanReduced(1 / z)
+  else if z < -1 then -halfPi - atanReduced(1 / z)
+  else atanReduced(z);
+
+# atan(z) for |z| <= 1: halving the angle twice with
+# atan(z) = 2 * atan(z / (1 + sqrt(1 + z^2))) leaves |q| <= tan(pi / 16).
+def atanReduced(z)
+  let h = z / (1 + sqrt(1 + z * z)) in
+  let q = h / (1 + sqrt(1 + h * h)) in
+  let q2 = q * q in
+  4 * (q + q * q2 * (-0.33333334 + q2 * (0.2 + q2 * (-0.14285715 + q2 * (0.11111111
+    + q2 * (-0.09090909 + q2 * 0.07692308))))));
+
+# sqrt(x^2 + y^2) without overflow or underflow in the squares.
+def hypot(x y)
+  let a = abs(x) in
+  let b = abs(y) in
+  let big = max(a, b) in
+  let small = min(a, b) in
+  if big == inf then inf
+  else if big == 0 or big != big then big
+  else let r = small / big in big * sqrt(1 + r * r);
@@ -0,0 +1,295 @@
+use crate::ast::{Ast, ExprKind, ExprNode};
+use crate::import::Renamer;
+use crate::parser::Parser;
+use crate::span::FileId;
+use std::collections::{HashMap, HashSet};
+
+/// Source of the prelude, written in minilang.
+pub const SOURCE: &str = include_str!("prelude.ml");
+/// Name of the prelude in diagnostics.
+pub const NAME: &str = "<prelude>";
+/// Namespace of the prelude items, which can also be called as
+/// `prelude.name`.
+pub const NAMESPACE: &str = "prelude";
+/// Prelude functions that can be called without the namespace.
+pub const FUNCTIONS: [&str; 11] = [
+    "min", "max", "clamp", "sign", "pow", "exp", "ln", "sin", "cos", "atan2", "hypot",
+];
+
+/// Makes the prelude functions called by `asts` and `exprs` available.
+/// `known` holds the other items of the program, such as the earlier
+/// definitions of a REPL session.
+///
+/// Calls to one of `FUNCTIONS` are pointed at `prelude.name` unless the
+/// program has an item of that name, and the prelude items they need,
+/// directly or through other prelude items, are appended to `asts` with spans
+/// in `file`. Returns whether any item was appended.
+pub fn link(asts: &mut Vec<Ast>, exprs: &mut [ExprNode], known: &[Ast], file: FileId) -> bool {
+    let defined: HashSet<String> = asts
+        .iter()
+        .chain(known)
+        .map(|ast| ast.get_name().to_owned())
+        .collect();
+    let prefix = format!("{}.", NAMESPACE);
+
+    let mut needed = vec![];
+    let mut link_name = |name: &mut String, is_call: bool| {
+        if is_call && FUNCTIONS.contains(&name.as_str()) && !defined.contains(name) {
+            *name = format!("{}{}", prefix, name);
+        }
+        if name.starts_with(&prefix) && !defined.contains(name) {
+            needed.push(name.clone());
+        }
+    };
+    let nodes = asts.iter_mut().filter_map(Ast::get_expr_mut);
+    for node in nodes.chain(exprs.iter_mut()) {
+        visit_names(node, &mut link_name);
+    }
+
+    let mut prelude = parse(file);
+    let mut included = HashSet::new();
+    while let Some(name) = needed.pop() {
+        if !included.insert(name.clone()) {
+            continue;
+        }
+        let item = prelude.iter_mut().find(|ast| ast.get_name() == name);
+        if let Some(node) = item.and_then(Ast::get_expr_mut) {
+            visit_names(node, &mut |name, _| {
+                if name.starts_with(&prefix) {
+                    needed.push(name.clone());
+                }
+            });
+        }
+    }
+
+    let count = asts.len();
+    asts.extend(
+        prelude
+            .into_iter()
+            .filter(|ast| included.contains(ast.get_name())),
+    );
+    asts.len() > count
+}
+
+/// Every prelude item, named `prelude.name`.
+fn parse(file: FileId) -> Vec<Ast> {
+    let mut parser = Parser::new(SOURCE.as_bytes()).in_file(file);
+    parser.main_loop();
+    assert!(
+        !parser.get_error_logger().has_errors(),
+        "the prelude does not parse"
+    );
+
+    let mut asts = std::mem::take(parser.get_asts_mut());
+    let renamer = Renamer::new(NAMESPACE, &asts, HashMap::new());
+    for ast in asts.iter_mut() {
+        renamer.rename(ast);
+    }
+    asts
+}
+
+/// Calls `f` with the name of every call and variable in `node`, and whether
+/// it is a call.
+fn visit_names<F: FnMut(&mut String, bool)>(node: &mut ExprNode, f: &mut F) {
+    match &mut node.kind {
+        ExprKind::Number(_) => {}
+        ExprKind::Variable(name) => f(name, false),
+        ExprKind::BinaryExpr { lhs, rhs, .. } => {
+            visit_names(lhs, f);
+            visit_names(rhs, f);
+        }
+        ExprKind::UnaryExpr { rhs, .. } => visit_names(rhs, f),
+        ExprKind::CallExpr { callee, args } => {
+            f(callee, true);
+            for arg in args.iter_mut() {
+                visit_names(arg, f);
+            }
+        }
+        ExprKind::IfExpr {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            visit_names(cond, f);
+            visit_names(then_branch, f);
+            visit_names(else_branch, f);
+        }
+        ExprKind::LetExpr { value, body, .. } => {
+            visit_names(value, f);
+            visit_names(body, f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check;
+    use crate::interpreter::Interpreter;
+
+    /// The Rust function a prelude function is compared with.
+    type Reference = fn(&[f32]) -> f32;
+
+    /// Distance from `actual` to `expected` in units in the last place of
+    /// `expected`.
+    fn ulps(actual: f32, expected: f32) -> f32 {
+        if actual == expected || (actual.is_nan() && expected.is_nan()) {
+            return 0.0;
+        }
+        let next = f32::from_bits(expected.abs().to_bits() + 1);
+        (actual - expected).abs() / (next - expected.abs())
+    }
+
+    /// Largest error of the prelude function `name` over `args`, in ulps of
+    /// the result of `expected`, together with the worst arguments.
+    fn max_error(
+        name: &str,
+        args: &[Vec<f32>],
+        expected: impl Fn(&[f32]) -> f32,
+    ) -> (f32, Vec<f32>) {
+        let params = ["a", "b", "c"][..args[0].len()].join(" ");
+        let source = format!("def f({}) {}({});", params, name, params.replace(' ', ", "));
+        let program = check(&source).unwrap();
+        let mut interpreter = Interpreter::new(program.get_asts());
+
+        let mut worst = (0.0, vec![]);
+        for arg in args {
+            let error = ulps(interpreter.call("f", arg).unwrap(), expected(arg));
+            if error > worst.0 || error.is_nan() {
+                worst = (error, arg.clone());
+            }
+        }
+        worst
+    }
+
+    fn range(from: f32, to: f32, count: usize) -> Vec<f32> {
+        (0..=count)
+            .map(|i| from + (to - from) * i as f32 / count as f32)
+            .collect()
+    }
+
+    fn unary(values: Vec<f32>) -> Vec<Vec<f32>> {
+        values.into_iter().map(|x| vec![x]).collect()
+    }
+
+    fn binary(xs: &[f32], ys: &[f32]) -> Vec<Vec<f32>> {
+        xs.iter()
+            .flat_map(|&x| ys.iter().map(move |&y| vec![x, y]))
+            .collect()
+    }
+
+    #[test]
+    fn it_matches_rust_functions() {
+        let xs = range(-10.0, 10.0, 60);
+        let tiny: Vec<f32> = (-140..120).map(|e| 1.37 * 2f32.powi(e)).collect();
+        let ints: Vec<f32> = (-10..=10).map(|n| n as f32).collect();
+
+        let cases: Vec<(&str, Vec<Vec<f32>>, Reference, f32)> = vec![
+            ("exp", unary(range(-87.0, 88.0, 5000)), |a| a[0].exp(), 1.0),
+            ("ln", unary(range(1e-3, 1e4, 5000)), |a| a[0].ln(), 1.0),
+            ("ln", unary(tiny), |a| a[0].ln(), 1.0),
+            (
+                "sin",
+                unary(range(-1000.0, 1000.0, 5000)),
+                |a| a[0].sin(),
+                2.0,
+            ),
+            (
+                "cos",
+                unary(range(-1000.0, 1000.0, 5000)),
+                |a| a[0].cos(),
+                2.0,
+            ),
+            ("atan2", binary(&xs, &xs), |a| a[0].atan2(a[1]), 3.0),
+            ("hypot", binary(&xs, &xs), |a| a[0].hypot(a[1]), 2.0),
+            (
+                "pow",
+                binary(&range(-5.0, 5.0, 50), &ints),
+                |a| a[0].powf(a[1]),
+                4.0,
+            ),
+            // Computed as exp(y * ln(x)), which loses about one ulp per unit
+            // of |y * ln(x)|.
+            (
+                "pow",
+                binary(&range(0.0, 20.0, 60), &range(-8.0, 8.0, 60)),
+                |a| a[0].powf(a[1]),
+                32.0,
+            ),
+        ];
+
+        for (name, args, expected, tolerance) in cases {
+            let (error, worst) = max_error(name, &args, expected);
+            assert!(
+                error <= tolerance,
+                "{} is off by {} ulps for {:?}",
+                name,
+                error,
+                worst
+            );
+        }
+    }
+
+    #[test]
+    fn it_handles_special_values() {
+        let cases: [(&str, &[f32], f32); 20] = [
+            ("exp", &[f32::INFINITY], f32::INFINITY),
+            ("exp", &[f32::NEG_INFINITY], 0.0),
+            ("exp", &[100.0], f32::INFINITY),
+            ("ln", &[0.0], f32::NEG_INFINITY),
+            ("ln", &[-1.0], f32::NAN),
+            ("ln", &[f32::INFINITY], f32::INFINITY),
+            (
+                "ln",
+                &[f32::MIN_POSITIVE / 8.0],
+                (f32::MIN_POSITIVE / 8.0).ln(),
+            ),
+            ("pow", &[-2.0, 3.0], -8.0),
+            ("pow", &[-2.0, 0.5], f32::NAN),
+            ("pow", &[0.0, -1.0], f32::INFINITY),
+            ("pow", &[f32::NAN, 0.0], 1.0),
+            ("sin", &[f32::INFINITY], f32::NAN),
+            ("atan2", &[0.0, 0.0], 0.0),
+            ("atan2", &[0.0, -1.0], std::f32::consts::PI),
+            ("hypot", &[f32::INFINITY, 1.0], f32::INFINITY),
+            ("hypot", &[3e30, 4e30], 5e30),
+            ("min", &[f32::NAN, 1.0], 1.0),
+            ("max", &[1.0, f32::NAN], 1.0),
+            ("sign", &[-0.5], -1.0),
+            ("sign", &[0.0], 0.0),
+        ];
+
+        for (name, args, expected) in cases {
+            let (error, _) = max_error(name, &[args.to_vec()], |_| expected);
+            assert_eq!(error, 0.0, "{}{:?}", name, args);
+        }
+        let (error, _) = max_error("clamp", &[vec![5.0, -1.0, 1.0]], |_| 1.0);
+        assert_eq!(error, 0.0);
+    }
+
+    #[test]
+    fn it_includes_only_the_functions_used() {
+        let program = check("def f(x) hypot(x, 1) + prelude.exp(x);").unwrap();
+        let names: Vec<&str> = program.get_asts().iter().map(Ast::get_name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "f",
+                "prelude.inf",
+                "prelude.ln2Hi",
+                "prelude.ln2Lo",
+                "prelude.invLn2",
+                "prelude.min",
+                "prelude.max",
+                "prelude.exp",
+                "prelude.expTaylor",
+                "prelude.hypot"
+            ]
+        );
+        assert_eq!(program.get_files()[1].name, NAME);
+
+        let program = check("def f(x) x; def g(x) min(x, 1); def min(a b) a;").unwrap();
+        assert_eq!(program.get_asts().len(), 3);
+        assert_eq!(program.get_files().len(), 1);
+    }
+}
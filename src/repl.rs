@@ -0,0 +1,467 @@
+use crate::ast::{Ast, ExprNode, Function};
+use crate::code_generator::function_code::{LowerOptions, ModuleCode};
+use crate::diagnostic::{Diagnostic, Renderer};
+use crate::interpreter::{evaluate_constants, Interpreter};
+use crate::parser::Parser;
+use crate::prelude;
+use crate::resolver::Resolver;
+use crate::span::FileId;
+use crate::type_checker::TypeChecker;
+use std::fs;
+use std::io::{BufRead, Write};
+
+const REPL_SOURCE_NAME: &str = "<repl>";
+/// File of the spans of prelude items; diagnostics only point at the input.
+const PRELUDE_FILE: FileId = 1;
+
+const HELP: &str = "\
+Enter definitions (`def f(x) x * x;`) or expressions (`f(2) + 1;`).
+Input is evaluated once a line ends with `;`.
+
+:list          list the definitions
+:wat <name>    show the WebAssembly text of a definition
+:ast <name>    show the syntax tree of a definition
+:load <file>   load the definitions of a source file
+:help          show this message
+:quit          leave the REPL
+";
+
+/// Read-eval-print loop. Definitions accumulate across inputs, and defining
+/// a function again replaces the previous definition.
+pub struct Repl {
+    definitions: Vec<Ast>,
+    colored: bool,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            definitions: vec![],
+            colored: false,
+        }
+    }
+
+    pub fn colored(mut self, colored: bool) -> Self {
+        self.colored = colored;
+        self
+    }
+
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, output: &mut W) -> std::io::Result<()> {
+        let mut buffer = String::new();
+
+        write!(output, "> ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+
+            if buffer.is_empty() && line.trim_start().starts_with(':') {
+                if !self.command(line.trim(), output)? {
+                    return Ok(());
+                }
+            } else if buffer.is_empty() && line.trim().is_empty() {
+                // Nothing to evaluate.
+            } else {
+                buffer.push_str(&line);
+                buffer.push('\n');
+
+                if is_complete(&buffer) {
+                    self.eval(&buffer, output)?;
+                    buffer.clear();
+                }
+            }
+
+            write!(output, "{}", if buffer.is_empty() { "> " } else { "... " })?;
+            output.flush()?;
+        }
+
+        writeln!(output)?;
+        Ok(())
+    }
+
+    /// Evaluates one input: its definitions are added first, then each
+    /// expression is evaluated and its value printed.
+    pub fn eval<W: Write>(&mut self, source: &str, output: &mut W) -> std::io::Result<()> {
+        let mut parser = Parser::new(source.as_bytes());
+        parser.repl_loop();
+
+        if self.report(source, REPL_SOURCE_NAME, parser.get_error_logger(), output)?
+            || self.report(
+                source,
+                REPL_SOURCE_NAME,
+                &reject_imports(parser.get_asts()),
+                output,
+            )?
+        {
+            return Ok(());
+        }
+
+        let mut exprs = parser.get_expressions().clone();
+        let mut new_definitions = parser.get_asts().clone();
+        prelude::link(
+            &mut new_definitions,
+            &mut exprs,
+            &self.definitions,
+            PRELUDE_FILE,
+        );
+
+        let definitions = self.merge(&new_definitions);
+        let mut diagnostics = Resolver::new(&definitions)
+            .run_subset(&new_definitions)
+            .to_vec();
+        for expr in exprs.iter() {
+            diagnostics.extend(Resolver::new(&definitions).run_expression(expr).to_vec());
+        }
+        if self.report(source, REPL_SOURCE_NAME, &diagnostics, output)? {
+            return Ok(());
+        }
+
+        let mut diagnostics = TypeChecker::new()
+            .with_constants(&self.definitions)
+            .run(&mut new_definitions)
+            .to_vec();
+        let definitions = self.merge(&new_definitions);
+        for expr in exprs.iter_mut() {
+            diagnostics.extend(
+                TypeChecker::new()
+                    .with_constants(&definitions)
+                    .run_expression(expr)
+                    .to_vec(),
+            );
+        }
+        if self.report(source, REPL_SOURCE_NAME, &diagnostics, output)? {
+            return Ok(());
+        }
+
+        self.definitions = self.merge(&new_definitions);
+        self.print_values(&exprs, output)
+    }
+
+    /// Handles a `:command`. Returns false when the REPL should stop.
+    fn command<W: Write>(&mut self, line: &str, output: &mut W) -> std::io::Result<bool> {
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            ":quit" | ":q" => return Ok(false),
+            ":help" => write!(output, "{}", HELP)?,
+            ":list" => {
+                for ast in self.definitions.iter() {
+                    match ast {
+                        Ast::Definition(func) => writeln!(output, "{}", func.get_proto())?,
+                        Ast::Extern(ext) => writeln!(output, "extern {}", ext.get_proto())?,
+                        Ast::Const(constant) => writeln!(output, "const {}", constant.get_name())?,
+                        Ast::Import(import) => writeln!(output, "{}", import)?,
+                    }
+                }
+            }
+            ":wat" => {
+                if self.find(arg, output)?.is_some() {
+                    self.write_wat(arg, output)?;
+                }
+            }
+            ":ast" => {
+                if let Some(func) = self.find(arg, output)? {
+                    write!(output, "{}", func.dump())?;
+                }
+            }
+            ":load" => self.load(arg, output)?,
+            _ => writeln!(output, "Unknown command '{}', try :help", command)?,
+        }
+
+        Ok(true)
+    }
+
+    fn load<W: Write>(&mut self, file_name: &str, output: &mut W) -> std::io::Result<()> {
+        let source = match fs::read_to_string(file_name) {
+            Ok(source) => source,
+            Err(err) => {
+                writeln!(output, "Unable to read '{}': {}", file_name, err)?;
+                return Ok(());
+            }
+        };
+
+        let mut parser = Parser::new(source.as_bytes());
+        parser.main_loop();
+        if self.report(&source, file_name, parser.get_error_logger(), output)?
+            || self.report(
+                &source,
+                file_name,
+                &reject_imports(parser.get_asts()),
+                output,
+            )?
+        {
+            return Ok(());
+        }
+
+        let mut new_definitions = parser.get_asts().clone();
+        prelude::link(
+            &mut new_definitions,
+            &mut [],
+            &self.definitions,
+            PRELUDE_FILE,
+        );
+        let definitions = self.merge(&new_definitions);
+        let resolver_errors = Resolver::new(&definitions).run_subset(&new_definitions);
+        if self.report(&source, file_name, &resolver_errors, output)? {
+            return Ok(());
+        }
+
+        let type_errors = TypeChecker::new()
+            .with_constants(&self.definitions)
+            .run(&mut new_definitions);
+        if self.report(&source, file_name, &type_errors, output)? {
+            return Ok(());
+        }
+
+        self.definitions = self.merge(&new_definitions);
+        writeln!(
+            output,
+            "Loaded {} definition(s) from '{}'",
+            parser.get_asts().len(),
+            file_name
+        )
+    }
+
+    /// Current definitions with `new_definitions` replacing the ones with the
+    /// same name and the rest appended. Constants and functions have names
+    /// of their own.
+    fn merge(&self, new_definitions: &[Ast]) -> Vec<Ast> {
+        let mut definitions = self.definitions.clone();
+
+        for ast in new_definitions {
+            let existing = definitions.iter().position(|old| {
+                old.get_name() == ast.get_name()
+                    && old.as_const().is_some() == ast.as_const().is_some()
+            });
+
+            match existing {
+                Some(index) => definitions[index] = ast.clone(),
+                None => definitions.push(ast.clone()),
+            }
+        }
+
+        definitions
+    }
+
+    fn find<W: Write>(&self, name: &str, output: &mut W) -> std::io::Result<Option<&Function>> {
+        let func = self
+            .definitions
+            .iter()
+            .filter_map(Ast::as_definition)
+            .find(|func| func.get_function_name() == name);
+
+        if func.is_none() {
+            writeln!(output, "No definition named '{}'", name)?;
+        }
+        Ok(func)
+    }
+
+    /// Writes the code of the definition `name` as `compile` generates it,
+    /// reading constants by value or from their global.
+    fn write_wat<W: Write>(&self, name: &str, output: &mut W) -> std::io::Result<()> {
+        let mut asts = self.definitions.clone();
+        let errors = evaluate_constants(&mut asts);
+        if errors.has_errors() {
+            for diagnostic in errors.iter() {
+                writeln!(output, "{}", diagnostic.message)?;
+            }
+            return Ok(());
+        }
+
+        let module = ModuleCode::new(&asts, LowerOptions::default());
+        if let Some(func) = module.functions.iter().find(|func| func.name == name) {
+            write!(output, "{}", func.to_wat().join(""))?;
+        }
+        Ok(())
+    }
+
+    fn print_values<W: Write>(&self, exprs: &[ExprNode], output: &mut W) -> std::io::Result<()> {
+        let mut interpreter = Interpreter::new(&self.definitions);
+
+        for expr in exprs {
+            match interpreter.eval_expression(expr) {
+                Ok(value) => writeln!(output, "{}", value)?,
+                Err(err) => writeln!(output, "runtime error: {}", err)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints the diagnostics and returns true if any of them is an error.
+    fn report<W: Write>(
+        &self,
+        source: &str,
+        file_name: &str,
+        diagnostics: &[Diagnostic],
+        output: &mut W,
+    ) -> std::io::Result<bool> {
+        let renderer = Renderer::new(source, file_name).colored(self.colored);
+
+        for diagnostic in diagnostics {
+            writeln!(output, "{}", renderer.render(diagnostic))?;
+        }
+
+        Ok(diagnostics.iter().any(Diagnostic::is_error))
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An input is complete once its last line, ignoring comments, ends with `;`.
+fn is_complete(buffer: &str) -> bool {
+    buffer
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .rfind(|line| !line.is_empty())
+        .is_some_and(|line| line.ends_with(';'))
+}
+
+/// Errors for the `import`s of `asts`, which the REPL does not load.
+fn reject_imports(asts: &[Ast]) -> Vec<Diagnostic> {
+    asts.iter()
+        .filter(|ast| matches!(ast, Ast::Import(_)))
+        .map(|ast| {
+            Diagnostic::error("Imports are not supported in the REPL")
+                .with_code("E0119")
+                .with_span(ast.get_span())
+                .with_help("compile the file with `minilang build` instead")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn session(input: &str) -> String {
+        let mut output = vec![];
+        Repl::new().run(Cursor::new(input), &mut output).unwrap();
+
+        String::from_utf8(output)
+            .unwrap()
+            .replace("... ", "")
+            .replace("> ", "")
+    }
+
+    #[test]
+    fn it_evaluates_definitions_and_expressions() {
+        let output = session(
+            "def discr(a b c) b*b - 4*a*c;\n\
+             discr(1, 2, 3) + 1; 2 < 3;\n\
+             def sq(x)\n  x * x; # multi-line\n\
+             sq(\n4);\n",
+        );
+
+        assert_eq!(output, "-7\ntrue\n16\n\n");
+    }
+
+    #[test]
+    fn it_redefines_functions() {
+        let output = session("def f(x) x + 1;\nf(1);\ndef f(x) x * 10;\nf(1);\n:list\n");
+
+        assert_eq!(output, "2\n10\nf(x)\n\n");
+    }
+
+    #[test]
+    fn it_calls_prelude_functions() {
+        let output = session("max(1, 2) + exp(0);\ndef max(a b) a;\nmax(1, 2);\n");
+
+        assert_eq!(output, "3\n1\n\n");
+    }
+
+    #[test]
+    fn it_keeps_previous_definition_on_error() {
+        let output = session("def f(x) x;\ndef f(x) y;\nf(3);\n");
+
+        assert!(output.contains("error[E0104]: Undefined variable 'y'"));
+        assert!(output.contains("<repl>:1:10"));
+        assert!(output.ends_with("3\n\n"));
+    }
+
+    #[test]
+    fn it_shows_wat_and_ast() {
+        let output = session("def inc(x) x + 1;\n:wat inc\n:ast inc\n:wat nope\n");
+
+        assert!(output.starts_with(
+            "(func $inc (param $x f32) (result f32)\nlocal.get $x\nf32.const 1\nf32.add\n)\n"
+        ));
+        assert!(output.contains(
+            "Definition inc(x) @1:1\n  BinaryExpr + @1:12\n    Variable x @1:12\n    Number 1 @1:16\n"
+        ));
+        assert!(output.contains("No definition named 'nope'"));
+    }
+
+    #[test]
+    fn it_shows_wat_reading_constants() {
+        let output = session(
+            "const pi = 3; const big = sq(pi) > 5; def sq(x) x * x;\n\
+             def f(x) if big then x * pi else 0;\n:wat f\n",
+        );
+
+        assert_eq!(
+            output,
+            "(func $f (param $x f32) (result f32)\n\
+             i32.const 1\nif (result f32)\n\
+             local.get $x\nf32.const 3\nf32.mul\nelse\nf32.const 0\nend\n)\n\n"
+        );
+    }
+
+    #[test]
+    fn it_loads_files() {
+        let path = std::env::temp_dir().join("minilang_repl_load_test.ml");
+        fs::write(
+            &path,
+            "def fib(x) if x < 3 then 1 else fib(x-1) + fib(x-2);",
+        )
+        .unwrap();
+
+        let output = session(&format!(":load {}\nfib(10);\n:quit\n1;\n", path.display()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            output,
+            format!("Loaded 1 definition(s) from '{}'\n55\n", path.display())
+        );
+    }
+
+    #[test]
+    fn it_rejects_imports() {
+        let path = std::env::temp_dir().join("minilang_repl_import_test.ml");
+        fs::write(&path, "import \"lib/util.ml\";\ndef f(x) x;").unwrap();
+
+        let output = session(&format!(
+            "import \"lib/util.ml\";\n:load {}\n1 + 1;\n",
+            path.display()
+        ));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            output
+                .matches("error[E0119]: Imports are not supported in the REPL")
+                .count(),
+            2
+        );
+        assert!(!output.contains("Loaded"));
+        assert!(output.ends_with(
+            "2
+
+"
+        ));
+    }
+
+    #[test]
+    fn it_detects_complete_input() {
+        assert!(is_complete("def f(x)\n x; # done\n"));
+        assert!(!is_complete("def f(x) # not done;\n"));
+        assert!(!is_complete(""));
+    }
+}
@@ -0,0 +1,616 @@
+use crate::ast::{Ast, Attribute, Const, ExprKind, ExprNode, Function, Parameter, Prototype};
+use crate::code_generator::builtin_funcs;
+use crate::diagnostic::Diagnostic;
+use crate::error_logger::ErrorLogger;
+use crate::optimizer::call_graph::visit_calls;
+use std::collections::HashMap;
+
+/// Attributes a definition or constant can carry.
+const ATTRIBUTES: [&str; 3] = ["export", "inline", "noinline"];
+
+/// Checks that every name used in the program refers to something: variables
+/// to enclosing `let`s, parameters of the enclosing function or constants,
+/// callees to user definitions, externs or builtins called with the right
+/// number of arguments. Duplicate names, constants defined in terms of
+/// themselves, unknown attributes and clashing export names are reported as
+/// well.
+pub struct Resolver<'a> {
+    asts: &'a [Ast],
+    functions: HashMap<&'a str, &'a Prototype>,
+    constants: HashMap<&'a str, &'a Const>,
+    /// Names bound by the enclosing `let`s, innermost last.
+    locals: Vec<String>,
+    err_logger: ErrorLogger,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(asts: &'a [Ast]) -> Self {
+        Self {
+            asts,
+            functions: HashMap::new(),
+            constants: HashMap::new(),
+            locals: vec![],
+            err_logger: ErrorLogger::new(),
+        }
+    }
+
+    pub fn run(self) -> ErrorLogger {
+        let asts = self.asts;
+        self.run_subset(asts)
+    }
+
+    /// Checks only `asts`, which must be part of the program the resolver was
+    /// created with, e.g. the definitions just entered into the REPL.
+    pub fn run_subset(mut self, asts: &[Ast]) -> ErrorLogger {
+        self.collect_items();
+
+        for ast in asts.iter() {
+            if let Some(proto) = ast.get_proto() {
+                self.check_params(proto);
+            }
+            self.check_attributes(ast.get_attributes());
+            self.check_inline_attributes(ast);
+
+            match ast {
+                Ast::Definition(func) => self.resolve_expr(Some(func), func.get_body()),
+                Ast::Const(constant) => {
+                    self.resolve_expr(None, constant.get_expr());
+                    self.check_const_cycle(constant);
+                }
+                Ast::Extern(_) | Ast::Import(_) => {}
+            }
+        }
+
+        self.err_logger
+    }
+
+    /// Resolves a standalone expression, such as one typed into the REPL,
+    /// against the definitions. The expression has no variables in scope.
+    pub fn run_expression(mut self, node: &ExprNode) -> ErrorLogger {
+        self.collect_items();
+        self.resolve_expr(None, node);
+
+        self.err_logger
+    }
+
+    fn collect_items(&mut self) {
+        let builtin_funcs = builtin_funcs();
+        let mut exports: HashMap<&str, &str> = HashMap::new();
+
+        for ast in self.asts.iter() {
+            match ast {
+                Ast::Const(constant) => self.collect_const(constant),
+                // The loader replaces imports with the items they bring in.
+                Ast::Import(_) => continue,
+                Ast::Definition(_) | Ast::Extern(_) => {
+                    let proto = ast.get_proto().unwrap();
+                    self.collect_function(proto, &builtin_funcs);
+                }
+            }
+
+            let Some(attr) = ast
+                .get_attributes()
+                .iter()
+                .find(|attr| attr.name == "export")
+            else {
+                continue;
+            };
+            let export_name = attr.arg.as_deref().unwrap_or(ast.get_name());
+            if let Some(first) = exports.get(export_name) {
+                self.err_logger.push_diagnostic(
+                    Diagnostic::error(&format!("'{}' is exported more than once", export_name))
+                        .with_code("E0109")
+                        .with_span(attr.span)
+                        .with_label("export name already used")
+                        .with_help(&format!(
+                            "'{}' also exports '{}'; pass another name with `@export(\"name\")`",
+                            first, export_name
+                        )),
+                );
+            } else {
+                exports.insert(export_name, ast.get_name());
+            }
+        }
+    }
+
+    fn collect_function(
+        &mut self,
+        proto: &'a Prototype,
+        builtin_funcs: &HashMap<&str, crate::code_generator::instruction::Instruction>,
+    ) {
+        let name = proto.get_name();
+        let span = proto.get_name_span();
+
+        if builtin_funcs.contains_key(name) {
+            self.err_logger.push_diagnostic(
+                Diagnostic::error(&format!("Function '{}' shadows a builtin", name))
+                    .with_code("E0101")
+                    .with_span(span)
+                    .with_label("builtin function name"),
+            );
+        } else if let Some(first) = self.functions.get(name) {
+            let help = format!(
+                "'{}' is first defined on line {}",
+                name,
+                first.get_name_span().line
+            );
+            self.err_logger.push_diagnostic(
+                Diagnostic::error(&format!("Function '{}' is defined more than once", name))
+                    .with_code("E0102")
+                    .with_span(span)
+                    .with_label("redefined here")
+                    .with_help(&help),
+            );
+        } else {
+            self.functions.insert(name, proto);
+        }
+    }
+
+    fn collect_const(&mut self, constant: &'a Const) {
+        let name = constant.get_name();
+
+        if let Some(first) = self.constants.get(name) {
+            let help = format!(
+                "'{}' is first defined on line {}",
+                name,
+                first.get_name_span().line
+            );
+            self.err_logger.push_diagnostic(
+                Diagnostic::error(&format!("Constant '{}' is defined more than once", name))
+                    .with_code("E0110")
+                    .with_span(constant.get_name_span())
+                    .with_label("redefined here")
+                    .with_help(&help),
+            );
+        } else {
+            self.constants.insert(name, constant);
+        }
+    }
+
+    /// Reports a constant whose value refers back to the constant itself,
+    /// directly or through other constants and the definitions it calls.
+    fn check_const_cycle(&mut self, constant: &Const) {
+        let mut visited: Vec<Dependency> = vec![];
+        let mut pending = dependencies(constant.get_expr(), &[]);
+
+        while let Some(dependency) = pending.pop() {
+            if dependency == Dependency::Const(constant.get_name().to_owned()) {
+                self.err_logger.push_diagnostic(
+                    Diagnostic::error(&format!(
+                        "Constant '{}' depends on itself",
+                        constant.get_name()
+                    ))
+                    .with_code("E0111")
+                    .with_span(constant.get_name_span())
+                    .with_label("its value needs the constant itself")
+                    .with_help(
+                        "a constant can only use constants and call definitions that do not \
+                         depend on it",
+                    ),
+                );
+                return;
+            }
+            if visited.contains(&dependency) {
+                continue;
+            }
+
+            match &dependency {
+                Dependency::Const(name) => {
+                    if let Some(other) = self.constants.get(name.as_str()) {
+                        pending.extend(dependencies(other.get_expr(), &[]));
+                    }
+                }
+                Dependency::Call(name) => {
+                    let func = self
+                        .asts
+                        .iter()
+                        .filter_map(Ast::as_definition)
+                        .find(|func| func.get_function_name() == name);
+                    if let Some(func) = func {
+                        pending.extend(dependencies(func.get_body(), func.get_params()));
+                    }
+                }
+            }
+            visited.push(dependency);
+        }
+    }
+
+    fn check_attributes(&mut self, attributes: &[Attribute]) {
+        for (index, attr) in attributes.iter().enumerate() {
+            if !ATTRIBUTES.contains(&attr.name.as_str()) {
+                self.err_logger.push_diagnostic(
+                    Diagnostic::error(&format!("Unknown attribute '@{}'", attr.name))
+                        .with_code("E0107")
+                        .with_span(attr.span)
+                        .with_label("not a known attribute")
+                        .with_help(&format!("known attributes are @{}", ATTRIBUTES.join(", @"))),
+                );
+            } else if attributes[..index].iter().any(|a| a.name == attr.name) {
+                self.err_logger.push_diagnostic(
+                    Diagnostic::error(&format!("Attribute '@{}' is repeated", attr.name))
+                        .with_code("E0108")
+                        .with_span(attr.span)
+                        .with_label("already given for this item"),
+                );
+            }
+        }
+    }
+
+    /// Reports `@inline` and `@noinline` anywhere but on a definition, and
+    /// both on the same one.
+    fn check_inline_attributes(&mut self, ast: &Ast) {
+        let attributes = ast.get_attributes();
+        let inline = attributes.iter().find(|attr| attr.name == "inline");
+        let noinline = attributes.iter().find(|attr| attr.name == "noinline");
+
+        if !matches!(ast, Ast::Definition(_)) {
+            for attr in inline.into_iter().chain(noinline) {
+                self.err_logger.push_diagnostic(
+                    Diagnostic::error(&format!(
+                        "Attribute '@{}' only applies to definitions",
+                        attr.name
+                    ))
+                    .with_code("E0118")
+                    .with_span(attr.span)
+                    .with_label("not a definition"),
+                );
+            }
+        } else if let (Some(_), Some(noinline)) = (inline, noinline) {
+            self.err_logger.push_diagnostic(
+                Diagnostic::error("Attributes '@inline' and '@noinline' cannot be combined")
+                    .with_code("E0117")
+                    .with_span(noinline.span)
+                    .with_label("conflicts with @inline"),
+            );
+        }
+    }
+
+    fn check_params(&mut self, proto: &Prototype) {
+        let params = proto.get_params();
+
+        for (index, param) in params.iter().enumerate() {
+            if params[..index].iter().any(|p| p.name == param.name) {
+                self.err_logger.push_diagnostic(
+                    Diagnostic::error(&format!(
+                        "Parameter '{}' is declared more than once",
+                        param.name
+                    ))
+                    .with_code("E0103")
+                    .with_span(param.span)
+                    .with_label("duplicate parameter"),
+                );
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, func: Option<&Function>, node: &ExprNode) {
+        match &node.kind {
+            ExprKind::Number(_) => {}
+            ExprKind::Variable(name) => {
+                let is_param = func
+                    .is_some_and(|func| func.get_params().iter().any(|param| &param.name == name));
+                if self.locals.contains(name)
+                    || is_param
+                    || self.constants.contains_key(name.as_str())
+                {
+                    return;
+                }
+
+                let Some(func) = func else {
+                    self.err_logger.push_diagnostic(
+                        Diagnostic::error(&format!("Undefined variable '{}'", name))
+                            .with_code("E0104")
+                            .with_span(node.span)
+                            .with_label("no variables are in scope here"),
+                    );
+                    return;
+                };
+                self.err_logger.push_diagnostic(
+                    Diagnostic::error(&format!("Undefined variable '{}'", name))
+                        .with_code("E0104")
+                        .with_span(node.span)
+                        .with_label("not a parameter of this function")
+                        .with_help(&format!(
+                            "add '{}' to the parameters of '{}' or define it with `const`",
+                            name,
+                            func.get_function_name()
+                        )),
+                );
+            }
+            ExprKind::BinaryExpr { lhs, rhs, .. } => {
+                self.resolve_expr(func, lhs);
+                self.resolve_expr(func, rhs);
+            }
+            ExprKind::UnaryExpr { rhs, .. } => self.resolve_expr(func, rhs),
+            ExprKind::CallExpr { callee, args } => {
+                self.resolve_call(node, callee, args.len());
+                for arg in args {
+                    self.resolve_expr(func, arg);
+                }
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expr(func, cond);
+                self.resolve_expr(func, then_branch);
+                self.resolve_expr(func, else_branch);
+            }
+            ExprKind::LetExpr {
+                name, value, body, ..
+            } => {
+                self.resolve_expr(func, value);
+                self.locals.push(name.clone());
+                self.resolve_expr(func, body);
+                self.locals.pop();
+            }
+        }
+    }
+
+    fn resolve_call(&mut self, node: &ExprNode, callee: &str, arg_count: usize) {
+        let arity = if let Some(proto) = self.functions.get(callee) {
+            proto.get_params().len()
+        } else if builtin_funcs().contains_key(callee) {
+            1
+        } else {
+            self.err_logger.push_diagnostic(
+                Diagnostic::error(&format!("Unknown function '{}'", callee))
+                    .with_code("E0105")
+                    .with_span(node.span)
+                    .with_label("not defined or declared `extern` in this program or a builtin"),
+            );
+            return;
+        };
+
+        if arity != arg_count {
+            self.err_logger.push_diagnostic(
+                Diagnostic::error(&format!(
+                    "Function '{}' takes {} argument{} but {} {} supplied",
+                    callee,
+                    arity,
+                    if arity == 1 { "" } else { "s" },
+                    arg_count,
+                    if arg_count == 1 { "was" } else { "were" }
+                ))
+                .with_code("E0106")
+                .with_span(node.span)
+                .with_label(&format!("expected {} argument(s)", arity)),
+            );
+        }
+    }
+}
+
+/// What computing an expression needs, besides the values of `params`.
+#[derive(Debug, PartialEq)]
+enum Dependency {
+    Const(String),
+    Call(String),
+}
+
+fn dependencies(node: &ExprNode, params: &[Parameter]) -> Vec<Dependency> {
+    let mut dependencies: Vec<Dependency> = const_refs(node)
+        .into_iter()
+        .filter(|name| !params.iter().any(|param| param.name == *name))
+        .map(|name| Dependency::Const(name.to_owned()))
+        .collect();
+    visit_calls(node, &mut |callee| {
+        dependencies.push(Dependency::Call(callee.to_owned()))
+    });
+    dependencies
+}
+
+/// Names of the constants an expression may read: the variables that no
+/// enclosing `let` of the expression binds.
+pub fn const_refs(node: &ExprNode) -> Vec<&str> {
+    fn visit<'n>(node: &'n ExprNode, bound: &mut Vec<&'n str>, out: &mut Vec<&'n str>) {
+        match &node.kind {
+            ExprKind::Number(_) => {}
+            ExprKind::Variable(name) => {
+                if !bound.contains(&name.as_str()) {
+                    out.push(name);
+                }
+            }
+            ExprKind::BinaryExpr { lhs, rhs, .. } => {
+                visit(lhs, bound, out);
+                visit(rhs, bound, out);
+            }
+            ExprKind::UnaryExpr { rhs, .. } => visit(rhs, bound, out),
+            ExprKind::CallExpr { args, .. } => {
+                for arg in args {
+                    visit(arg, bound, out);
+                }
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                visit(cond, bound, out);
+                visit(then_branch, bound, out);
+                visit(else_branch, bound, out);
+            }
+            ExprKind::LetExpr {
+                name, value, body, ..
+            } => {
+                visit(value, bound, out);
+                bound.push(name);
+                visit(body, bound, out);
+                bound.pop();
+            }
+        }
+    }
+
+    let mut out = vec![];
+    visit(node, &mut vec![], &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use std::io::Cursor;
+
+    fn resolve(src: &str) -> Vec<(&'static str, String)> {
+        let mut parser = Parser::new(Cursor::new(src));
+        parser.main_loop();
+        assert!(!parser.get_error_logger().has_errors());
+
+        Resolver::new(parser.get_asts())
+            .run()
+            .iter()
+            .map(|diagnostic| (diagnostic.code.unwrap(), diagnostic.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn it_accepts_valid_program() {
+        let src = "def discr(a b c) b*b - 4*a*c;
+                   def root(a b c) (-b + sqrt(discr(a, b, c)))/(2*a);
+                   def fib(x) if x < 2 then 1 else fib(x-1) + fib(x-2);";
+
+        assert!(resolve(src).is_empty());
+    }
+
+    #[test]
+    fn it_reports_undefined_variable() {
+        assert_eq!(
+            resolve("def f(x) y;"),
+            vec![("E0104", String::from("Undefined variable 'y'"))]
+        );
+    }
+
+    #[test]
+    fn it_reports_unknown_function() {
+        assert_eq!(
+            resolve("def f(x) g(x);"),
+            vec![("E0105", String::from("Unknown function 'g'"))]
+        );
+    }
+
+    #[test]
+    fn it_reports_arity_mismatch() {
+        assert_eq!(
+            resolve("def sum(x) x; def f(x) sum(1, 2) + sqrt();"),
+            vec![
+                (
+                    "E0106",
+                    String::from("Function 'sum' takes 1 argument but 2 were supplied")
+                ),
+                (
+                    "E0106",
+                    String::from("Function 'sqrt' takes 1 argument but 0 were supplied")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_scopes_let_bindings() {
+        assert!(resolve("def f(x) let y = x in let x = y in x + y;").is_empty());
+        assert_eq!(
+            resolve("def f(x) let y = y in y;"),
+            vec![("E0104", String::from("Undefined variable 'y'"))]
+        );
+    }
+
+    #[test]
+    fn it_reports_duplicates() {
+        assert_eq!(
+            resolve("def f(x x) x; def f() 1; def abs(x) x;"),
+            vec![
+                (
+                    "E0102",
+                    String::from("Function 'f' is defined more than once")
+                ),
+                ("E0101", String::from("Function 'abs' shadows a builtin")),
+                (
+                    "E0103",
+                    String::from("Parameter 'x' is declared more than once")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_resolves_externs() {
+        assert!(
+            resolve("extern log(x); extern def random(); def f(x) log(x) + random();").is_empty()
+        );
+        assert_eq!(
+            resolve("extern log(x); def log(x) x; extern sqrt(x); def f() log();"),
+            vec![
+                (
+                    "E0102",
+                    String::from("Function 'log' is defined more than once")
+                ),
+                ("E0101", String::from("Function 'sqrt' shadows a builtin")),
+                (
+                    "E0106",
+                    String::from("Function 'log' takes 1 argument but 0 were supplied")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_checks_attributes() {
+        assert!(resolve("@export def f(x) x; @export(\"g\") def h(x) x;").is_empty());
+        assert_eq!(
+            resolve("@export(\"f\") @export def g(x) x; @export def f() 1; @pure def h() 1;"),
+            vec![
+                ("E0109", String::from("'f' is exported more than once")),
+                ("E0108", String::from("Attribute '@export' is repeated")),
+                ("E0107", String::from("Unknown attribute '@pure'")),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_checks_inline_attributes() {
+        assert!(resolve("@inline def f(x) x; @noinline @export def g(x) f(x);").is_empty());
+        assert_eq!(
+            resolve("@inline @noinline def f(x) x; @noinline const k = 1;"),
+            vec![
+                (
+                    "E0117",
+                    String::from("Attributes '@inline' and '@noinline' cannot be combined")
+                ),
+                (
+                    "E0118",
+                    String::from("Attribute '@noinline' only applies to definitions")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_resolves_constants() {
+        assert!(resolve("const g = 9.81; def f(x) x * g + half; const half = g / 2;").is_empty());
+        assert!(resolve("const x = 1; def f(x) let g = 1 in x + g;").is_empty());
+        assert_eq!(
+            resolve("const a = b + 1; const b = let a = 1 in a; const c = d; const d = c * 2; const a = 1;"),
+            vec![
+                (
+                    "E0110",
+                    String::from("Constant 'a' is defined more than once")
+                ),
+                ("E0111", String::from("Constant 'c' depends on itself")),
+                ("E0111", String::from("Constant 'd' depends on itself")),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_finds_constant_cycles_through_definitions() {
+        assert_eq!(
+            resolve("const a = f(); def f() a + 1; const b = g(1); def g(x) h(x); def h(x) x * b;"),
+            vec![
+                ("E0111", String::from("Constant 'a' depends on itself")),
+                ("E0111", String::from("Constant 'b' depends on itself")),
+            ]
+        );
+        assert!(resolve("def f(a) a * k; const a = f(1); const k = 2;").is_empty());
+    }
+}
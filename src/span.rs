@@ -0,0 +1,64 @@
+/// The file a span is in, an index into the files of the program. The main
+/// file is `MAIN_FILE`; imported files follow in the order they are loaded.
+pub type FileId = usize;
+
+pub const MAIN_FILE: FileId = 0;
+
+/// Location of a token or AST node in the source: the byte range
+/// `start..end` plus the 1-based line and column of `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub file: FileId,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+            file: MAIN_FILE,
+        }
+    }
+
+    pub fn in_file(mut self, file: FileId) -> Self {
+        self.file = file;
+        self
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(&self, other: Span) -> Span {
+        let first = if self.start <= other.start {
+            self
+        } else {
+            &other
+        };
+
+        Span {
+            start: first.start,
+            end: self.end.max(other.end),
+            line: first.line,
+            column: first.column,
+            file: self.file,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        Self::new(0, 0, 1, 1)
+    }
+}
@@ -23,31 +23,67 @@ pub enum Token {
     Semicolon,
     None,
     NotEq,
+    Let,
+    In,
+    Assign,
+    LessEq,
+    GreaterEq,
+    Not,
+    AndAlso,
+    OrElse,
+    Percent,
+    Caret,
+    Extern,
+    String,
+    UnterminatedString,
+    At,
+    Const,
+    Import,
 }
 
 impl Token {
-    pub fn is_addition_operator(&self) -> bool {
+    /// Human readable name used in diagnostics.
+    pub fn describe(&self) -> &'static str {
         match *self {
-            Token::Plus | Token::Minus | Token::Or => true,
-            _ => false,
-        }
-    }
-}
-
-impl Token {
-    pub fn is_multiplication_operator(&self) -> bool {
-        match *self {
-            Token::Star | Token::Slash | Token::And => true,
-            _ => false,
-        }
-    }
-}
-
-impl Token {
-    pub fn is_comparison_operator(&self) -> bool {
-        match *self {
-            Token::Less | Token::Greater | Token::Equal | Token::NotEq => true,
-            _ => false,
+            Token::Eof => "end of file",
+            Token::Comma => "`,`",
+            Token::Define => "`def`",
+            Token::Identifier => "identifier",
+            Token::Number => "number",
+            Token::InvalidChar => "invalid character",
+            Token::If => "`if`",
+            Token::Else => "`else`",
+            Token::Then => "`then`",
+            Token::Lpar => "`(`",
+            Token::Rpar => "`)`",
+            Token::Equal => "`==`",
+            Token::Less => "`<`",
+            Token::Greater => "`>`",
+            Token::Plus => "`+`",
+            Token::Minus => "`-`",
+            Token::Star => "`*`",
+            Token::Slash => "`/`",
+            Token::Or => "`|`",
+            Token::And => "`&`",
+            Token::Semicolon => "`;`",
+            Token::None => "unknown token",
+            Token::NotEq => "`<>`",
+            Token::Let => "`let`",
+            Token::In => "`in`",
+            Token::Assign => "`=`",
+            Token::LessEq => "`<=`",
+            Token::GreaterEq => "`>=`",
+            Token::Not => "`not`",
+            Token::AndAlso => "`and`",
+            Token::OrElse => "`or`",
+            Token::Percent => "`%`",
+            Token::Caret => "`^`",
+            Token::Extern => "`extern`",
+            Token::String => "string",
+            Token::UnterminatedString => "unterminated string",
+            Token::At => "`@`",
+            Token::Const => "`const`",
+            Token::Import => "`import`",
         }
     }
 }
@@ -0,0 +1,412 @@
+use crate::ast::{Ast, Const, ExprKind, ExprNode};
+use crate::diagnostic::Diagnostic;
+use crate::error_logger::ErrorLogger;
+use crate::operator::Operator;
+use crate::resolver::const_refs;
+use crate::span::Span;
+use crate::types::Type;
+use std::collections::HashMap;
+
+/// Infers the type of every expression and stores it in `ExprNode::ty`.
+///
+/// Arithmetic works on `f32`, comparisons produce `bool`, `&` and `|`
+/// combine `bool`s and `if` conditions must be `bool`. The only implicit
+/// conversion is a `bool` function body, which returns 1 or 0 since every
+/// function returns `f32`. Parameters are `f32` and a constant has the type
+/// of its value. Run after the resolver, as names are assumed to be valid
+/// and constants not to depend on themselves.
+pub struct TypeChecker {
+    /// Types of the variables bound by the enclosing `let`s, or parameters,
+    /// innermost last.
+    locals: Vec<(String, Type)>,
+    /// Types of the constants checked so far.
+    constants: HashMap<String, Type>,
+    err_logger: ErrorLogger,
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self {
+            locals: vec![],
+            constants: HashMap::new(),
+            err_logger: ErrorLogger::new(),
+        }
+    }
+
+    /// Takes the types of the constants of `asts`, which are checked
+    /// already, e.g. the ones entered into the REPL before.
+    pub fn with_constants(mut self, asts: &[Ast]) -> Self {
+        for constant in asts.iter().filter_map(Ast::as_const) {
+            self.constants
+                .insert(constant.get_name().to_owned(), constant.get_expr().ty);
+        }
+        self
+    }
+
+    pub fn run(mut self, asts: &mut [Ast]) -> ErrorLogger {
+        // Constants come first, each after the ones its value reads.
+        let names: Vec<String> = asts
+            .iter()
+            .filter_map(Ast::as_const)
+            .map(|constant| constant.get_name().to_owned())
+            .collect();
+        let mut pending: Vec<&mut Const> = asts
+            .iter_mut()
+            .filter_map(|ast| match ast {
+                Ast::Const(constant) => Some(constant),
+                _ => None,
+            })
+            .collect();
+        while !pending.is_empty() {
+            let count = pending.len();
+            let mut index = 0;
+            while index < pending.len() {
+                let is_ready = const_refs(pending[index].get_expr()).iter().all(|name| {
+                    !names.iter().any(|n| n == name) || self.constants.contains_key(*name)
+                });
+                if is_ready {
+                    let constant = pending.remove(index);
+                    let ty = self.check_expr(constant.get_expr_mut());
+                    self.constants.insert(constant.get_name().to_owned(), ty);
+                } else {
+                    index += 1;
+                }
+            }
+            if pending.len() == count {
+                // A cycle, which the resolver reports.
+                break;
+            }
+        }
+
+        for func in asts.iter_mut().filter_map(|ast| match ast {
+            Ast::Definition(func) => Some(func),
+            _ => None,
+        }) {
+            self.locals = func
+                .get_params()
+                .iter()
+                .map(|param| (param.name.clone(), Type::F32))
+                .collect();
+            self.check_expr(func.get_body_mut());
+        }
+        self.locals.clear();
+
+        self.err_logger
+    }
+
+    /// Checks a standalone expression, such as one typed into the REPL.
+    pub fn run_expression(mut self, node: &mut ExprNode) -> ErrorLogger {
+        self.check_expr(node);
+        self.err_logger
+    }
+
+    fn check_expr(&mut self, node: &mut ExprNode) -> Type {
+        let span = node.span;
+        let ty = match &mut node.kind {
+            ExprKind::Number(_) => Type::F32,
+            ExprKind::Variable(name) => self
+                .locals
+                .iter()
+                .rev()
+                .find(|(local, _)| local == name)
+                .map(|(_, ty)| *ty)
+                .or_else(|| self.constants.get(name.as_str()).copied())
+                .unwrap_or(Type::F32),
+            ExprKind::BinaryExpr { op, lhs, rhs } => {
+                let lhs_ty = self.check_expr(lhs);
+                let rhs_ty = self.check_expr(rhs);
+                if *op == Operator::Pow {
+                    self.check_exponent(rhs);
+                }
+                self.check_binary_op(*op, lhs_ty, rhs_ty, span)
+            }
+            ExprKind::UnaryExpr { op, rhs } => {
+                let operand_ty = if op.is_logical() {
+                    Type::Bool
+                } else {
+                    Type::F32
+                };
+                if self.check_expr(rhs) != operand_ty {
+                    self.push_operand_error(*op, operand_ty, span);
+                }
+                operand_ty
+            }
+            ExprKind::CallExpr { args, .. } => {
+                for arg in args.iter_mut() {
+                    let arg_ty = self.check_expr(arg);
+                    self.expect(Type::F32, arg_ty, arg.span, "function argument");
+                }
+                Type::F32
+            }
+            ExprKind::IfExpr {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let cond_ty = self.check_expr(cond);
+                self.expect(Type::Bool, cond_ty, cond.span, "if condition");
+
+                let then_ty = self.check_expr(then_branch);
+                let else_ty = self.check_expr(else_branch);
+                if then_ty != else_ty {
+                    self.err_logger.push_diagnostic(
+                        Diagnostic::error(&format!(
+                            "If branches have different types: {} and {}",
+                            then_ty, else_ty
+                        ))
+                        .with_code("E0202")
+                        .with_span(else_branch.span)
+                        .with_label(&format!("expected {}, found {}", then_ty, else_ty)),
+                    );
+                }
+                then_ty
+            }
+            ExprKind::LetExpr {
+                name, value, body, ..
+            } => {
+                let value_ty = self.check_expr(value);
+                self.locals.push((name.clone(), value_ty));
+                let body_ty = self.check_expr(body);
+                self.locals.pop();
+                body_ty
+            }
+        };
+
+        node.ty = ty;
+        ty
+    }
+
+    fn check_binary_op(&mut self, op: Operator, lhs: Type, rhs: Type, span: Span) -> Type {
+        if op.is_logical() {
+            if lhs != Type::Bool || rhs != Type::Bool {
+                self.push_operand_error(op, Type::Bool, span);
+            }
+            return Type::Bool;
+        }
+
+        if op.is_comparison() {
+            let equality = matches!(op, Operator::Equal | Operator::NotEq);
+            if lhs != rhs || (lhs == Type::Bool && !equality) {
+                self.push_operand_error(op, Type::F32, span);
+            }
+            return Type::Bool;
+        }
+
+        if lhs != Type::F32 || rhs != Type::F32 {
+            self.push_operand_error(op, Type::F32, span);
+        }
+        Type::F32
+    }
+
+    /// `^` truncates its exponent, so a literal one must be an integer.
+    fn check_exponent(&mut self, exponent: &ExprNode) {
+        let value = match &exponent.kind {
+            ExprKind::Number(number) => *number,
+            ExprKind::UnaryExpr {
+                op: Operator::Neg,
+                rhs,
+            } => match rhs.kind {
+                ExprKind::Number(number) => -number,
+                _ => return,
+            },
+            _ => return,
+        };
+        if value.fract() == 0.0 {
+            return;
+        }
+
+        self.err_logger.push_diagnostic(
+            Diagnostic::error(&format!("Exponent {} of `^` is not an integer", value))
+                .with_code("E0204")
+                .with_span(exponent.span)
+                .with_label("`^` takes integer exponents")
+                .with_help("use `pow(x, y)` for other exponents"),
+        );
+    }
+
+    fn push_operand_error(&mut self, op: Operator, expected: Type, span: Span) {
+        self.err_logger.push_diagnostic(
+            Diagnostic::error(&format!(
+                "Operator `{}` expects {} operands",
+                op.symbol(),
+                expected
+            ))
+            .with_code("E0201")
+            .with_span(span)
+            .with_label("mismatched operand types")
+            .with_help(conversion_help(expected)),
+        );
+    }
+
+    fn expect(&mut self, expected: Type, found: Type, span: Span, context: &str) {
+        if expected == found {
+            return;
+        }
+
+        self.err_logger.push_diagnostic(
+            Diagnostic::error(&format!(
+                "Mismatched types: {} expects {}, found {}",
+                context, expected, found
+            ))
+            .with_code("E0203")
+            .with_span(span)
+            .with_label(&format!("expected {}", expected))
+            .with_help(conversion_help(expected)),
+        );
+    }
+}
+
+fn conversion_help(expected: Type) -> &'static str {
+    match expected {
+        Type::Bool => "compare numbers to get a bool, e.g. `x <> 0`",
+        Type::F32 => "use `if c then 1 else 0` to turn a bool into a number",
+    }
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use std::io::Cursor;
+
+    fn check(src: &str) -> (Parser<Cursor<&str>>, Vec<String>) {
+        let mut parser = Parser::new(Cursor::new(src));
+        parser.main_loop();
+        assert!(!parser.get_error_logger().has_errors());
+
+        let errors = TypeChecker::new()
+            .run(parser.get_asts_mut())
+            .iter()
+            .map(|diagnostic| diagnostic.message.clone())
+            .collect();
+        (parser, errors)
+    }
+
+    fn body_type(parser: &Parser<Cursor<&str>>, index: usize) -> Type {
+        let func = parser.get_asts()[index].as_definition().unwrap();
+        func.get_body().ty
+    }
+
+    #[test]
+    fn it_infers_types() {
+        let (parser, errors) = check(
+            "def f(x) if (x == 1) | (x == 2) then 1 else x;
+             def lt(a b) a < b;
+             def same(a b) (a < 1) == (b < 1);
+             def pick(x) if x > 0 then x < 1 else x > 2;",
+        );
+
+        assert!(errors.is_empty());
+        assert_eq!(body_type(&parser, 0), Type::F32);
+        assert_eq!(body_type(&parser, 1), Type::Bool);
+        assert_eq!(body_type(&parser, 2), Type::Bool);
+        assert_eq!(body_type(&parser, 3), Type::Bool);
+    }
+
+    #[test]
+    fn it_rejects_bool_arithmetic() {
+        let (_, errors) = check("def f(x) (x < 1) + 2;");
+        assert_eq!(errors, vec!["Operator `+` expects f32 operands"]);
+    }
+
+    #[test]
+    fn it_rejects_numeric_logic() {
+        let (_, errors) = check("def g(a b) a & b;");
+        assert_eq!(errors, vec!["Operator `&` expects bool operands"]);
+    }
+
+    #[test]
+    fn it_rejects_numeric_condition() {
+        let (_, errors) = check("def f(x) if x then 1 else 0;");
+        assert_eq!(
+            errors,
+            vec!["Mismatched types: if condition expects bool, found f32"]
+        );
+    }
+
+    #[test]
+    fn it_rejects_mismatched_branches_and_bool_arguments() {
+        let (_, errors) = check("def f(x) if x < 0 then x < 1 else 0; def g(x) f(x < 1);");
+        assert_eq!(
+            errors,
+            vec![
+                "If branches have different types: bool and f32",
+                "Mismatched types: function argument expects f32, found bool",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_rejects_fractional_literal_exponents() {
+        let (_, errors) = check("def f(x) x ^ 2 + x ^ -1 + 2 ^ 0.5 + x ^ (-1.5) + x ^ x;");
+        assert_eq!(
+            errors,
+            vec![
+                "Exponent 0.5 of `^` is not an integer",
+                "Exponent -1.5 of `^` is not an integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_types_constants_by_their_value() {
+        let (parser, errors) = check(
+            "def f(x) if ok and x > k then x else 0; const ok = not off; const off = k < 0;
+             const k = 2; def g(ok) ok + 1;",
+        );
+        assert!(errors.is_empty());
+        assert_eq!(
+            parser.get_asts()[1].as_const().unwrap().get_expr().ty,
+            Type::Bool
+        );
+
+        let (_, errors) = check("const t = 1 < 2; def g(x) x + t;");
+        assert_eq!(errors, vec!["Operator `+` expects f32 operands"]);
+    }
+
+    #[test]
+    fn it_infers_let_binding_types() {
+        let (parser, errors) = check("def f(x) let c = x < 1 in if c then 1 else 0;");
+        assert!(errors.is_empty());
+        assert_eq!(body_type(&parser, 0), Type::F32);
+
+        let (_, errors) = check("def f(x) let c = x < 1 in c + 1;");
+        assert_eq!(errors, vec!["Operator `+` expects f32 operands"]);
+    }
+
+    #[test]
+    fn it_checks_logic_operators() {
+        let (parser, errors) = check("def f(x) not (x <= 1) and x >= 0 or x != 2;");
+        assert!(errors.is_empty());
+        assert_eq!(body_type(&parser, 0), Type::Bool);
+
+        let (_, errors) = check("def f(x) not x or x;");
+        assert_eq!(
+            errors,
+            vec![
+                "Operator `not` expects bool operands",
+                "Operator `or` expects bool operands"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_converts_bool_results_in_generated_code() {
+        let (parser, _) = check("def f(a b) (a < b) == (b < a);");
+        let func = parser.get_asts()[0].as_definition().unwrap();
+
+        assert_eq!(
+            func.to_wat().join(""),
+            "(func $f (param $a f32) (param $b f32) (result f32)\n\
+             local.get $a\nlocal.get $b\nf32.lt\n\
+             local.get $b\nlocal.get $a\nf32.lt\n\
+             i32.eq\nf32.convert_i32_u\n)\n"
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+
+/// Type of an expression. Numbers are `f32`, comparisons and logic
+/// operators produce `bool`, which WebAssembly represents as `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    F32,
+    Bool,
+}
+
+impl Type {
+    pub fn to_wat(self) -> &'static str {
+        match self {
+            Self::F32 => "f32",
+            Self::Bool => "i32",
+        }
+    }
+
+    pub fn to_wasm(self) -> u8 {
+        match self {
+            Self::F32 => 0x7d,
+            Self::Bool => 0x7f,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::F32 => write!(f, "f32"),
+            Self::Bool => write!(f, "bool"),
+        }
+    }
+}